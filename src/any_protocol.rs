@@ -0,0 +1,481 @@
+//! Wrapper for mixing encryption protocols
+//!
+//! This module contains the implementation of the trait `EncryptionProtocol` that wraps
+//! the other protocols of the crate. Every key belongs to one of the protocols, and keys
+//! and ciphertexts carry the name of their protocol as a tag. This allows users with
+//! different protocols to coexist in a single `Env<AnyProtocol>`.
+use crate::encryption_protocol::{EncryptionProtocol, ProtocolError};
+use crate::{caesar, elgamal, one_time_pad, paillier, rsa, vigenere, xor_cipher};
+
+/// Calls the macro with the list `Variant => module::Protocol` of the wrapped protocols
+/// appended to its arguments.
+macro_rules! with_protocols {
+    ($macro:ident!($($args:tt)*)) => {
+        $macro! {
+            $($args)*;
+            Rsa => rsa::RSA,
+            ElGamal => elgamal::ElGamal,
+            Paillier => paillier::Paillier,
+            OneTimePad => one_time_pad::OneTimePad,
+            XorCipher => xor_cipher::XorCipher,
+            Caesar => caesar::Caesar,
+            Vigenere => vigenere::Vigenere
+        }
+    };
+}
+
+/// Matches a `PublicKey` or a `PrivateKey`. In every arm, `$key` is the wrapped key
+/// and `$P` is the type of its protocol.
+macro_rules! match_key {
+    ($value:expr, $enum:ident, |$key:ident, $P:ident| $body:expr;
+        $($variant:ident => $module:ident::$protocol:ident),*) => {
+        match $value {
+            $($enum::$variant($key) => {
+                type $P = $module::$protocol;
+                $body
+            })*
+        }
+    };
+}
+
+/// Matches a `ProtocolKind`. In every arm, `$P` is the type of the protocol.
+macro_rules! match_kind {
+    ($value:expr, |$P:ident| $body:expr; $($variant:ident => $module:ident::$protocol:ident),*) => {
+        match $value {
+            $(ProtocolKind::$variant => {
+                type $P = $module::$protocol;
+                $body
+            })*
+        }
+    };
+}
+
+/// Implements `get_kind` for `PublicKey` and `PrivateKey`, and the conversions
+/// from the keys of the wrapped protocols.
+macro_rules! impl_keys {
+    (; $($variant:ident => $module:ident::$protocol:ident),*) => {
+        impl PublicKey {
+            /// Returns the protocol of the key.
+            pub fn get_kind(&self) -> ProtocolKind {
+                match self {
+                    $(PublicKey::$variant(_) => ProtocolKind::$variant,)*
+                }
+            }
+        }
+
+        impl PrivateKey {
+            /// Returns the protocol of the key.
+            pub fn get_kind(&self) -> ProtocolKind {
+                match self {
+                    $(PrivateKey::$variant(_) => ProtocolKind::$variant,)*
+                }
+            }
+        }
+
+        $(
+            impl From<$module::PublicKey> for PublicKey {
+                fn from(key: $module::PublicKey) -> Self {
+                    PublicKey::$variant(key)
+                }
+            }
+
+            impl From<$module::PrivateKey> for PrivateKey {
+                fn from(key: $module::PrivateKey) -> Self {
+                    PrivateKey::$variant(key)
+                }
+            }
+        )*
+    };
+}
+
+/// Protocols that can be wrapped by `AnyProtocol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolKind {
+    Rsa,
+    ElGamal,
+    Paillier,
+    OneTimePad,
+    XorCipher,
+    Caesar,
+    Vigenere,
+}
+
+impl ProtocolKind {
+    /// All of the protocols that can be wrapped.
+    pub const ALL: [ProtocolKind; 7] = [
+        ProtocolKind::Rsa,
+        ProtocolKind::ElGamal,
+        ProtocolKind::Paillier,
+        ProtocolKind::OneTimePad,
+        ProtocolKind::XorCipher,
+        ProtocolKind::Caesar,
+        ProtocolKind::Vigenere,
+    ];
+
+    /// Returns the name of the protocol (see `EncryptionProtocol::protocol_name`),
+    /// which is used as the tag of keys and ciphertexts.
+    pub fn get_name(&self) -> &'static str {
+        with_protocols!(match_kind!(self, |P| P::protocol_name()))
+    }
+
+    /// Splits a tagged string `"name:body"` into the protocol and the body.
+    fn untag(message: &str) -> Option<(ProtocolKind, &str)> {
+        let (name, body) = message.split_once(':')?;
+        let kind = Self::ALL.into_iter().find(|kind| kind.get_name() == name)?;
+        Some((kind, body))
+    }
+}
+
+/// Public key of one of the wrapped protocols.
+#[derive(Clone)]
+pub enum PublicKey {
+    Rsa(rsa::PublicKey),
+    ElGamal(elgamal::PublicKey),
+    Paillier(paillier::PublicKey),
+    OneTimePad(one_time_pad::PublicKey),
+    XorCipher(xor_cipher::PublicKey),
+    Caesar(caesar::PublicKey),
+    Vigenere(vigenere::PublicKey),
+}
+
+/// Private key of one of the wrapped protocols.
+#[derive(Clone)]
+pub enum PrivateKey {
+    Rsa(rsa::PrivateKey),
+    ElGamal(elgamal::PrivateKey),
+    Paillier(paillier::PrivateKey),
+    OneTimePad(one_time_pad::PrivateKey),
+    XorCipher(xor_cipher::PrivateKey),
+    Caesar(caesar::PrivateKey),
+    Vigenere(vigenere::PrivateKey),
+}
+
+with_protocols!(impl_keys!());
+
+/// Implementation of the trait `EncryptionProtocol` that dispatches every method
+/// to the protocol of the key.
+///
+/// Public keys are converted to strings `"name:key"`, and every ciphertext starts
+/// with `"name:"`, so a message encrypted for one protocol is rejected by the keys
+/// of another protocol instead of being decrypted to garbage.
+pub struct AnyProtocol {}
+
+impl AnyProtocol {
+    /// Creates keys of the given protocol.
+    pub fn create_keys_for(kind: ProtocolKind) -> (PublicKey, PrivateKey) {
+        with_protocols!(match_kind!(kind, |P| {
+            let (public_key, private_key) = P::create_keys();
+            (public_key.into(), private_key.into())
+        }))
+    }
+
+    fn tag<P: EncryptionProtocol>(body: &str) -> String {
+        format!("{}:{}", P::protocol_name(), body)
+    }
+
+    fn untag<P: EncryptionProtocol>(message: &[u8]) -> Result<&[u8], ProtocolError> {
+        message
+            .strip_prefix(Self::tag::<P>("").as_bytes())
+            .ok_or_else(|| {
+                ProtocolError::InvalidCiphertext(format!(
+                    "expected a {} ciphertext",
+                    P::protocol_name()
+                ))
+            })
+    }
+
+    fn encrypt_tagged<P: EncryptionProtocol<Error = ProtocolError>>(
+        message: &[u8],
+        pub_key: &P::PublicKey,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let mut encrypted = Self::tag::<P>("").into_bytes();
+        encrypted.extend(P::encrypt_bytes(message, pub_key)?);
+        Ok(encrypted)
+    }
+
+    fn decrypt_tagged<P: EncryptionProtocol<Error = ProtocolError>>(
+        message: &[u8],
+        priv_key: &P::PrivateKey,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        P::decrypt_bytes(Self::untag::<P>(message)?, priv_key)
+    }
+
+    fn encrypt_byte_blocks_tagged<P: EncryptionProtocol<Error = ProtocolError>>(
+        message: &[u8],
+        pub_key: &P::PublicKey,
+    ) -> Result<Vec<Vec<u8>>, ProtocolError> {
+        let tag = Self::tag::<P>("").into_bytes();
+        Ok(P::encrypt_byte_blocks(message, pub_key)?
+            .into_iter()
+            .map(|block| [tag.as_slice(), &block].concat())
+            .collect())
+    }
+
+    fn decrypt_byte_blocks_tagged<P: EncryptionProtocol<Error = ProtocolError>>(
+        blocks: &[Vec<u8>],
+        priv_key: &P::PrivateKey,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let blocks: Vec<Vec<u8>> = blocks
+            .iter()
+            .map(|block| Self::untag::<P>(block).map(<[u8]>::to_vec))
+            .collect::<Result<_, _>>()?;
+        P::decrypt_byte_blocks(&blocks, priv_key)
+    }
+
+    fn encrypt_blocks_tagged<P: EncryptionProtocol<Error = ProtocolError>>(
+        message: &str,
+        pub_key: &P::PublicKey,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let blocks = P::encrypt_blocks(message, pub_key)?;
+        Ok(vec![Self::tag::<P>(&P::join_ciphertext(&blocks))])
+    }
+
+    fn decrypt_blocks_tagged<P: EncryptionProtocol<Error = ProtocolError>>(
+        message: &str,
+        priv_key: &P::PrivateKey,
+    ) -> Result<String, ProtocolError> {
+        let body = Self::untag::<P>(message.as_bytes())?;
+        // The tag is ASCII, so the rest of the message is valid UTF-8.
+        let body = std::str::from_utf8(body).unwrap_or_default();
+        P::decrypt_blocks(&P::split_ciphertext(body), priv_key)
+    }
+}
+
+impl EncryptionProtocol for AnyProtocol {
+    /// `PublicKey` of one of the wrapped protocols is used.
+    type PublicKey = PublicKey;
+    /// `PrivateKey` of one of the wrapped protocols is used.
+    type PrivateKey = PrivateKey;
+    /// `ProtocolError` is used.
+    type Error = ProtocolError;
+
+    /// The message is encrypted by the protocol of the key, and the result
+    /// is prefixed with the tag `"name:"`.
+    fn encrypt_bytes(message: &[u8], pub_key: &PublicKey) -> Result<Vec<u8>, ProtocolError> {
+        with_protocols!(match_key!(pub_key, PublicKey, |key, P| {
+            Self::encrypt_tagged::<P>(message, key)
+        }))
+    }
+
+    /// The message is decrypted by the protocol of the key.
+    /// Returns an error if the message is tagged with another protocol.
+    fn decrypt_bytes(message: &[u8], priv_key: &PrivateKey) -> Result<Vec<u8>, ProtocolError> {
+        with_protocols!(match_key!(priv_key, PrivateKey, |key, P| {
+            Self::decrypt_tagged::<P>(message, key)
+        }))
+    }
+
+    /// The message is encrypted at once if the protocol of the key supports it.
+    fn encrypt_long(message: &str, pub_key: &PublicKey) -> Option<String> {
+        with_protocols!(match_key!(pub_key, PublicKey, |key, P| {
+            P::encrypt_long(message, key).map(|encrypted| Self::tag::<P>(&encrypted))
+        }))
+    }
+
+    /// The message is signed by the protocol of the key.
+    fn sign(message: &str, priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        with_protocols!(match_key!(priv_key, PrivateKey, |key, P| {
+            <P as EncryptionProtocol>::sign(message, key)
+        }))
+    }
+
+    /// The signature is verified by the protocol of the key.
+    fn verify(message: &str, signature: &str, pub_key: &PublicKey) -> Result<bool, ProtocolError> {
+        with_protocols!(match_key!(pub_key, PublicKey, |key, P| {
+            <P as EncryptionProtocol>::verify(message, signature, key)
+        }))
+    }
+
+    /// The public key is validated by the protocol of the key.
+    fn validate_public_key(pub_key: &PublicKey) -> Result<(), ProtocolError> {
+        with_protocols!(match_key!(pub_key, PublicKey, |key, P| {
+            <P as EncryptionProtocol>::validate_public_key(key)
+        }))
+    }
+
+    /// The message is encrypted block by block by the protocol of the key, and the blocks
+    /// are joined by that protocol. The result is a single tagged block, since
+    /// `join_ciphertext` and `split_ciphertext` do not know the protocol.
+    fn encrypt_blocks(message: &str, pub_key: &PublicKey) -> Result<Vec<String>, ProtocolError> {
+        with_protocols!(match_key!(pub_key, PublicKey, |key, P| {
+            Self::encrypt_blocks_tagged::<P>(message, key)
+        }))
+    }
+
+    /// Every block is split and decrypted by the protocol of the key.
+    fn decrypt_blocks(blocks: &[String], priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        blocks
+            .iter()
+            .map(|block| {
+                with_protocols!(match_key!(priv_key, PrivateKey, |key, P| {
+                    Self::decrypt_blocks_tagged::<P>(block, key)
+                }))
+            })
+            .collect()
+    }
+
+    /// The bytes are encrypted block by block by the protocol of the key,
+    /// and every block is tagged.
+    fn encrypt_byte_blocks(
+        message: &[u8],
+        pub_key: &PublicKey,
+    ) -> Result<Vec<Vec<u8>>, ProtocolError> {
+        with_protocols!(match_key!(pub_key, PublicKey, |key, P| {
+            Self::encrypt_byte_blocks_tagged::<P>(message, key)
+        }))
+    }
+
+    /// The blocks are decrypted together by the protocol of the key.
+    /// Returns an error if a block is tagged with another protocol.
+    fn decrypt_byte_blocks(
+        blocks: &[Vec<u8>],
+        priv_key: &PrivateKey,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        with_protocols!(match_key!(priv_key, PrivateKey, |key, P| {
+            Self::decrypt_byte_blocks_tagged::<P>(blocks, key)
+        }))
+    }
+
+    /// The message is a single block (see `AnyProtocol::encrypt_blocks`).
+    fn split_ciphertext(message: &str) -> Vec<String> {
+        vec![String::from(message)]
+    }
+
+    /// The method generates keys for RSA.
+    /// Keys of other protocols are created with `AnyProtocol::create_keys_for`.
+    fn create_keys() -> (PublicKey, PrivateKey) {
+        Self::create_keys_for(ProtocolKind::Rsa)
+    }
+
+    /// The new keys belong to the protocol of the given key, so key rotation
+    /// does not change the protocol of a user.
+    fn create_keys_like(pub_key: &PublicKey) -> (PublicKey, PrivateKey) {
+        Self::create_keys_for(pub_key.get_kind())
+    }
+
+    /// Parses a string `"name:key"` to public key of the protocol `name`.
+    fn to_public_key(message: &str) -> Result<PublicKey, ProtocolError> {
+        let (kind, body) = ProtocolKind::untag(message).ok_or_else(|| {
+            ProtocolError::InvalidPublicKey(String::from("expected \"protocol:key\""))
+        })?;
+        with_protocols!(match_kind!(kind, |P| P::to_public_key(body).map(PublicKey::from)))
+    }
+
+    /// Creates a string `"name:key"` from public key.
+    fn to_string(pub_key: &Self::PublicKey) -> String {
+        with_protocols!(match_key!(pub_key, PublicKey, |key, P| {
+            Self::tag::<P>(&P::to_string(key))
+        }))
+    }
+
+    /// Creates a string `"name:key"` from private key.
+    fn private_key_to_string(priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        with_protocols!(match_key!(priv_key, PrivateKey, |key, P| {
+            P::private_key_to_string(key).map(|body| Self::tag::<P>(&body))
+        }))
+    }
+
+    /// Parses a string `"name:key"` to private key of the protocol `name`.
+    fn to_private_key(message: &str) -> Result<PrivateKey, ProtocolError> {
+        let (kind, body) = ProtocolKind::untag(message).ok_or_else(|| {
+            ProtocolError::InvalidPrivateKey(String::from("expected \"protocol:key\""))
+        })?;
+        with_protocols!(match_kind!(kind, |P| P::to_private_key(body).map(PrivateKey::from)))
+    }
+
+    fn protocol_name() -> &'static str {
+        "Any"
+    }
+
+    /// The key size of RSA, which is used by `create_keys`.
+    fn key_size_bits() -> usize {
+        <rsa::RSA as EncryptionProtocol>::key_size_bits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::any_protocol::{AnyProtocol, PrivateKey, ProtocolKind, PublicKey};
+    use crate::encryption_protocol::{EncryptionProtocol, ProtocolError};
+
+    #[test]
+    fn test_encrypt_decrypt_every_protocol() {
+        for kind in ProtocolKind::ALL {
+            let (public_key, private_key) = AnyProtocol::create_keys_for(kind);
+            assert_eq!(public_key.get_kind(), kind);
+            assert_eq!(private_key.get_kind(), kind);
+
+            // Paillier encrypts only numbers.
+            let (message, bytes): (&str, &[u8]) = match kind {
+                ProtocolKind::Paillier => ("12345678", b"42"),
+                _ => ("Hello, world! Привет!", b"bytes"),
+            };
+            let blocks = AnyProtocol::encrypt_blocks(message, &public_key).unwrap();
+            let joined = AnyProtocol::join_ciphertext(&blocks);
+            assert!(joined.starts_with(&(kind.get_name().to_string() + ":")));
+            let chunks = AnyProtocol::split_ciphertext(&joined);
+            assert_eq!(
+                AnyProtocol::decrypt_blocks(&chunks, &private_key).unwrap(),
+                message
+            );
+
+            let encrypted = AnyProtocol::encrypt_bytes(bytes, &public_key).unwrap();
+            assert_eq!(
+                AnyProtocol::decrypt_bytes(&encrypted, &private_key).unwrap(),
+                bytes
+            );
+            let blocks = AnyProtocol::encrypt_byte_blocks(bytes, &public_key).unwrap();
+            assert_eq!(
+                AnyProtocol::decrypt_byte_blocks(&blocks, &private_key).unwrap(),
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn test_key_strings() {
+        for kind in ProtocolKind::ALL {
+            let (public_key, private_key) = AnyProtocol::create_keys_for(kind);
+            let key_string = AnyProtocol::to_string(&public_key);
+            let parsed = AnyProtocol::to_public_key(&key_string).unwrap();
+            assert_eq!(parsed.get_kind(), kind);
+            assert_eq!(AnyProtocol::to_string(&parsed), key_string);
+
+            let private_string = AnyProtocol::private_key_to_string(&private_key).unwrap();
+            let parsed = AnyProtocol::to_private_key(&private_string).unwrap();
+            assert_eq!(parsed.get_kind(), kind);
+        }
+        assert!(AnyProtocol::to_public_key("17").is_err());
+        assert!(AnyProtocol::to_public_key("Unknown:17").is_err());
+        assert!(AnyProtocol::to_public_key("Caesar:x").is_err());
+        assert!(AnyProtocol::to_private_key("Caesar17").is_err());
+    }
+
+    #[test]
+    fn test_wrong_protocol() {
+        let (caesar_key, _) = AnyProtocol::create_keys_for(ProtocolKind::Caesar);
+        let (_, rsa_key) = AnyProtocol::create_keys_for(ProtocolKind::Rsa);
+        let encrypted = AnyProtocol::encrypt_bytes(b"secret", &caesar_key).unwrap();
+        assert_eq!(
+            AnyProtocol::decrypt_bytes(&encrypted, &rsa_key),
+            Err(ProtocolError::InvalidCiphertext(String::from(
+                "expected a RSA ciphertext"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let (public_key, private_key) = AnyProtocol::create_keys_for(ProtocolKind::Rsa);
+        let signature = AnyProtocol::sign("message", &private_key).unwrap();
+        assert_eq!(
+            AnyProtocol::verify("message", &signature, &public_key),
+            Ok(true)
+        );
+        assert!(matches!(public_key, PublicKey::Rsa(_)));
+        assert!(matches!(private_key, PrivateKey::Rsa(_)));
+
+        let (_, private_key) = AnyProtocol::create_keys_for(ProtocolKind::Caesar);
+        assert!(AnyProtocol::sign("message", &private_key).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Implementaion of Caesar cipher
+//!
+//! This module contains the implementation of the trait `EncryptionProtocol`.
+//! Caesar cipher is not secure and is intended only for educational purposes.
+use crate::encryption_protocol::{EncryptionProtocol, ProtocolError};
+use rand::Rng;
+
+/// Struct for public key in Caesar cipher.
+///
+/// The key consists of a shift `1 <= s <= 25` of the alphabet.
+#[derive(Clone)]
+pub struct PublicKey {
+    pub(crate) shift: u8,
+}
+
+/// Struct for private key in Caesar cipher.
+///
+/// The key consists of a shift `1 <= s <= 25` of the alphabet.
+/// Note that the private key is equal to the public key.
+#[derive(Clone)]
+pub struct PrivateKey {
+    pub(crate) shift: u8,
+}
+
+/// Implementation of the trait `EncryptionProtocol`.
+///
+/// Contains a helper method for shifting the letters and the implementation of trait methods.
+pub struct Caesar {}
+
+impl Caesar {
+    fn shift_letters(message: &[u8], shift: u8) -> Vec<u8> {
+        message
+            .iter()
+            .map(|&c| {
+                if c.is_ascii_lowercase() {
+                    (c - b'a' + shift) % 26 + b'a'
+                } else if c.is_ascii_uppercase() {
+                    (c - b'A' + shift) % 26 + b'A'
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+}
+
+impl EncryptionProtocol for Caesar {
+    /// Implementation of `PublicKey` for Caesar cipher is used.
+    type PublicKey = PublicKey;
+    /// Implementation of `PrivateKey` for Caesar cipher is used.
+    type PrivateKey = PrivateKey;
+    /// `ProtocolError` is used.
+    type Error = ProtocolError;
+
+    /// Every ASCII letter of the message is shifted forward by `s` positions
+    /// in the alphabet. Other characters remain unchanged.
+    fn encrypt_bytes(message: &[u8], pub_key: &PublicKey) -> Result<Vec<u8>, ProtocolError> {
+        Ok(Self::shift_letters(message, pub_key.shift))
+    }
+
+    /// Every ASCII letter of the message is shifted backward by `s` positions
+    /// in the alphabet. Other characters remain unchanged.
+    fn decrypt_bytes(message: &[u8], priv_key: &PrivateKey) -> Result<Vec<u8>, ProtocolError> {
+        Ok(Self::shift_letters(message, 26 - priv_key.shift))
+    }
+
+    /// The blocks are concatenated, as the ciphertext may contain spaces.
+    fn join_ciphertext(blocks: &[String]) -> String {
+        blocks.concat()
+    }
+
+    /// The whole message is decrypted as a single block.
+    fn split_ciphertext(message: &str) -> Vec<String> {
+        vec![String::from(message)]
+    }
+
+    /// The method chooses a random shift `1 <= s <= 25`.
+    fn create_keys() -> (PublicKey, PrivateKey) {
+        let shift: u8 = rand::thread_rng().gen_range(1..=25);
+
+        (PublicKey { shift }, PrivateKey { shift })
+    }
+
+    /// Parses a decimal string to public key. The shift must satisfy `1 <= s <= 25`.
+    fn to_public_key(message: &str) -> Result<PublicKey, ProtocolError> {
+        let invalid =
+            || ProtocolError::InvalidPublicKey(String::from("expected a number from 1 to 25"));
+        let shift: u8 = message.parse().map_err(|_| invalid())?;
+        if !(1..=25).contains(&shift) {
+            return Err(invalid());
+        }
+
+        Ok(PublicKey { shift })
+    }
+
+    /// Creates a decimal string from public key.
+    fn to_string(pub_key: &Self::PublicKey) -> String {
+        pub_key.shift.to_string()
+    }
+
+    /// Creates a decimal string from private key (the same as for the public key).
+    fn private_key_to_string(priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Ok(priv_key.shift.to_string())
+    }
+
+    /// Parses a decimal string to private key. The shift must satisfy `1 <= s <= 25`.
+    fn to_private_key(message: &str) -> Result<PrivateKey, ProtocolError> {
+        Self::to_public_key(message)
+            .map(|pub_key| PrivateKey {
+                shift: pub_key.shift,
+            })
+            .map_err(|_| {
+                ProtocolError::InvalidPrivateKey(String::from("expected a number from 1 to 25"))
+            })
+    }
+
+    fn protocol_name() -> &'static str {
+        "Caesar"
+    }
+
+    /// The key is a single byte (the shift).
+    fn key_size_bits() -> usize {
+        8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::caesar::{Caesar, PrivateKey, PublicKey};
+    use crate::encryption_protocol::EncryptionProtocol;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let (public_key, private_key) = Caesar::create_keys();
+        let encrypted_message = Caesar::encrypt("Hello, Bob!", &public_key).unwrap();
+        let decrypted_message = Caesar::decrypt(&encrypted_message, &private_key).unwrap();
+        assert_eq!(decrypted_message, "Hello, Bob!");
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let public_key = PublicKey { shift: 3 };
+        let private_key = PrivateKey { shift: 3 };
+        assert_eq!(Caesar::encrypt("xyz XYZ", &public_key).unwrap(), "abc ABC");
+        assert_eq!(Caesar::decrypt("abc ABC", &private_key).unwrap(), "xyz XYZ");
+    }
+
+    #[test]
+    fn test_to_public_key() {
+        let key = Caesar::to_public_key("17").unwrap();
+
+        assert_eq!(key.shift, 17);
+        for invalid in ["0", "26", "250", "-1", "x"] {
+            assert!(Caesar::to_public_key(invalid).is_err());
+            assert!(Caesar::to_private_key(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_to_string() {
+        let key = PublicKey { shift: 17 };
+        let mes = Caesar::to_string(&key);
+
+        assert_eq!(mes, "17");
+    }
+}
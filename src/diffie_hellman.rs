@@ -0,0 +1,53 @@
+//! Diffie-Hellman key agreement
+//!
+//! This module generates the group parameters of Diffie-Hellman key exchange
+//! (see `User::initiate_exchange`) and derives the keys of messages from the shared secret.
+
+use crate::elgamal::ElGamal;
+use crate::hashing::hmac_sha256;
+use crate::rsa::RSA;
+use rand::Rng;
+
+/// Generates the group parameters: a 64-bit safe prime `p` and a generator `g`
+/// of the multiplicative group modulo `p`.
+pub fn generate_group() -> (u128, u128) {
+    let p = ElGamal::generate_safe_prime(RSA::first_primes());
+    (p, ElGamal::generate_generator(p))
+}
+
+/// Generates a secret exponent `1 < a < p - 1`.
+pub fn generate_exponent(p: u128) -> u128 {
+    rand::thread_rng().gen_range(2..p - 1)
+}
+
+/// Computes `base^exponent % p`: the public value `g^a` or the shared secret `B^a`.
+pub fn power(base: u128, exponent: u128, p: u128) -> u128 {
+    RSA::expmod(base, exponent, p)
+}
+
+/// Derives the 32-byte key of a message from the shared secret and the nonce
+/// of the message, so every message is encrypted with a different key.
+pub fn message_key(secret: u128, nonce: u64) -> [u8; 32] {
+    hmac_sha256(&secret.to_be_bytes(), &nonce.to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diffie_hellman::{generate_exponent, generate_group, message_key, power};
+
+    #[test]
+    fn test_shared_secret() {
+        let (p, g) = generate_group();
+        assert_ne!(power(g, (p - 1) / 2, p), 1);
+        let a = generate_exponent(p);
+        let b = generate_exponent(p);
+        assert_eq!(power(power(g, b, p), a, p), power(power(g, a, p), b, p));
+    }
+
+    #[test]
+    fn test_message_key() {
+        assert_eq!(message_key(42, 1), message_key(42, 1));
+        assert_ne!(message_key(42, 1), message_key(42, 2));
+        assert_ne!(message_key(42, 1), message_key(43, 1));
+    }
+}
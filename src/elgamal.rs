@@ -0,0 +1,292 @@
+//! Implementaion of ElGamal encryption protocol
+//!
+//! This module contains the implementation of the trait `EncryptionProtocol`.
+use crate::encryption_protocol::{EncryptionProtocol, ProtocolError, parse_numbers};
+use crate::rsa::RSA;
+use num_bigint::BigUint;
+use num_bigint::ToBigUint;
+use num_traits::cast::ToPrimitive;
+use rand::Rng;
+
+/// Struct for public key in ElGamal.
+///
+/// ElGamal public key consists of a safe prime `p = 2 * q + 1` (`q` - prime),
+/// a generator `g` of the multiplicative group modulo `p`
+/// and a number `h = g^x % p` (`x` - private exponent).
+#[derive(Clone)]
+pub struct PublicKey {
+    pub(crate) p: u128,
+    pub(crate) g: u128,
+    pub(crate) h: u128,
+}
+
+/// Struct for private key in ElGamal.
+///
+/// ElGamal private key consists of a safe prime `p`
+/// and a private exponent `x < p - 1`.
+#[derive(Clone)]
+pub struct PrivateKey {
+    pub(crate) p: u128,
+    pub(crate) x: u128,
+}
+
+/// Implementation of the trait `EncryptionProtocol`.
+///
+/// Contains helper methods for creating keys and the implementation of trait methods.
+pub struct ElGamal {}
+
+impl ElGamal {
+    pub(crate) fn generate_safe_prime(first_primes: &Vec<u128>) -> u128 {
+        let lower_bound: u128 = 2_u128.pow(63) + 1;
+        let upper_bound: u128 = 2_u128.pow(64) - 1;
+        loop {
+            let q = RSA::generate_prime(lower_bound, upper_bound, first_primes);
+            let p = 2 * q + 1;
+
+            let mut is_divided = false;
+            for prime in first_primes {
+                if p.is_multiple_of(*prime) {
+                    is_divided = true;
+                    break;
+                }
+            }
+
+            if !is_divided && RSA::rabin_miller_test(p) {
+                return p;
+            }
+        }
+    }
+
+    pub(crate) fn generate_generator(p: u128) -> u128 {
+        let q = (p - 1) / 2;
+        loop {
+            let g: u128 = rand::thread_rng().gen_range(2..p - 1);
+            if RSA::expmod(g, q, p) != 1 {
+                return g;
+            }
+        }
+    }
+
+    fn mulmod(a: u128, b: u128, modulo: u128) -> u128 {
+        let big_a: BigUint = a.to_biguint().unwrap();
+        let big_b: BigUint = b.to_biguint().unwrap();
+        let big_modulo: BigUint = modulo.to_biguint().unwrap();
+        let res: BigUint = big_a * big_b % big_modulo;
+        res.to_u128().unwrap()
+    }
+}
+
+impl EncryptionProtocol for ElGamal {
+    /// Implementation of `PublicKey` for ElGamal is used.
+    type PublicKey = PublicKey;
+    /// Implementation of `PrivateKey` for ElGamal is used.
+    type PrivateKey = PrivateKey;
+    /// `ProtocolError` is used.
+    type Error = ProtocolError;
+
+    /// The message is encrypted using ElGamal protocol: `m -> (g^k % p, m * h^k % p)`
+    /// (`m` - message, `k` - random number). The result is the string `"c1 c2:l"`
+    /// (`l` - number of bytes).
+    fn encrypt_bytes(message: &[u8], pub_key: &PublicKey) -> Result<Vec<u8>, ProtocolError> {
+        if message.len() > 8 {
+            return Err(ProtocolError::InvalidPlaintext(String::from(
+                "expected at most 8 bytes",
+            )));
+        }
+        let mut res: u128 = 0;
+        let mut base: u128 = 1;
+        for &b in message {
+            res += base * (b as u128);
+            base *= 256;
+        }
+
+        let k: u128 = rand::thread_rng().gen_range(2..pub_key.p - 1);
+        let c1 = RSA::expmod(pub_key.g, k, pub_key.p);
+        let c2 = Self::mulmod(res, RSA::expmod(pub_key.h, k, pub_key.p), pub_key.p);
+
+        Ok(format!("{} {}:{}", c1, c2, message.len()).into_bytes())
+    }
+
+    /// The message is decrypted using ElGamal protocol: `(c1, c2) -> c2 * c1^(p - 1 - x) % p`
+    /// (`x` - private exponent). Exactly `l` bytes are recovered, so the zero bytes
+    /// at the end of the message are preserved. Returns an error if the message
+    /// is not of the form `"c1 c2:l"` or `"c1 c2"`.
+    fn decrypt_bytes(message: &[u8], priv_key: &PrivateKey) -> Result<Vec<u8>, ProtocolError> {
+        let invalid = || ProtocolError::InvalidCiphertext(String::from("expected \"c1 c2:l\""));
+        let message = std::str::from_utf8(message).map_err(|_| invalid())?;
+        let (first, second) = message.split_once(' ').ok_or_else(invalid)?;
+        let (second, length) = match second.split_once(':') {
+            Some((second, length)) => (second, Some(length.parse().map_err(|_| invalid())?)),
+            None => (second, None),
+        };
+        let c1: u128 = first.parse().map_err(|_| invalid())?;
+        let c2: u128 = second.parse().map_err(|_| invalid())?;
+        if c1 == 0 || c1 >= priv_key.p || c2 >= priv_key.p {
+            return Err(invalid());
+        }
+        let inverse = RSA::expmod(c1, priv_key.p - 1 - priv_key.x, priv_key.p);
+        let mut decrypted_num = Self::mulmod(c2, inverse, priv_key.p);
+        let mut decrypted_bytes: Vec<u8> = Vec::new();
+        while decrypted_num > 0 {
+            decrypted_bytes.push((decrypted_num % 256) as u8);
+            decrypted_num /= 256;
+        }
+        if let Some(length) = length {
+            if length > 8 || length < decrypted_bytes.len() {
+                return Err(ProtocolError::InvalidCiphertext(String::from(
+                    "invalid length",
+                )));
+            }
+            decrypted_bytes.resize(length, 0);
+        }
+
+        Ok(decrypted_bytes)
+    }
+
+    /// Groups the whitespace-separated numbers into pairs `"c1 c2"`.
+    fn split_ciphertext(message: &str) -> Vec<String> {
+        let numbers: Vec<&str> = message.split_whitespace().collect();
+        numbers.chunks(2).map(|pair| pair.join(" ")).collect()
+    }
+
+    /// The method generates keys for ElGamal.
+    ///
+    /// The method generates a 65-bit safe prime `p`, chooses a generator `g`
+    /// and a private exponent `x`, and calculates `h = g^x % p`.
+    fn create_keys() -> (PublicKey, PrivateKey) {
+        let first_primes = RSA::first_primes();
+        let p = Self::generate_safe_prime(first_primes);
+        let g = Self::generate_generator(p);
+        let x: u128 = rand::thread_rng().gen_range(2..p - 1);
+        let h = RSA::expmod(g, x, p);
+
+        let public_key: PublicKey = PublicKey { p, g, h };
+        let private_key: PrivateKey = PrivateKey { p, x };
+
+        (public_key, private_key)
+    }
+
+    /// Parses a string `"a b c"` to public key (`p = a, g = b, h = c`).
+    fn to_public_key(message: &str) -> Result<PublicKey, ProtocolError> {
+        let components: Vec<u128> = message
+            .split(' ')
+            .map(|component| component.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| ProtocolError::InvalidPublicKey(String::from("expected numbers")))?;
+        match components[..] {
+            [p, g, h] => Ok(PublicKey { p, g, h }),
+            _ => Err(ProtocolError::InvalidPublicKey(String::from(
+                "expected \"p g h\"",
+            ))),
+        }
+    }
+
+    /// Creates a string from public key: `p, g, h -> "p g h"`.
+    fn to_string(pub_key: &Self::PublicKey) -> String {
+        pub_key.p.to_string() + " " + &pub_key.g.to_string() + " " + &pub_key.h.to_string()
+    }
+
+    /// Creates a string from private key: `p, x -> "p x"`.
+    fn private_key_to_string(priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Ok(priv_key.p.to_string() + " " + &priv_key.x.to_string())
+    }
+
+    /// Parses a string `"p x"` to private key.
+    fn to_private_key(message: &str) -> Result<PrivateKey, ProtocolError> {
+        match parse_numbers(message).as_deref() {
+            Some(&[p, x]) => Ok(PrivateKey { p, x }),
+            _ => Err(ProtocolError::InvalidPrivateKey(String::from(
+                "expected \"p x\"",
+            ))),
+        }
+    }
+
+    fn protocol_name() -> &'static str {
+        "ElGamal"
+    }
+
+    /// The key size is the size of the safe prime `p = 2q + 1` (`q` has 64 bits).
+    fn key_size_bits() -> usize {
+        65
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elgamal::{ElGamal, PublicKey};
+    use crate::encryption_protocol::{BLOCK_SIZE, EncryptionProtocol};
+    use rand::Rng;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let (public_key, private_key) = ElGamal::create_keys();
+        let encrypted_message = ElGamal::encrypt("hello", &public_key).unwrap();
+        assert_eq!(encrypted_message.split(' ').count(), 2);
+        let decrypted_message = ElGamal::decrypt(&encrypted_message, &private_key).unwrap();
+        assert_eq!(decrypted_message, "hello");
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_random_bytes() {
+        let mut rng = rand::thread_rng();
+        let (public_key, private_key) = ElGamal::create_keys();
+        for length in 0..=BLOCK_SIZE {
+            let mut bytes: Vec<u8> = vec![0; length];
+            rng.fill(&mut bytes[..]);
+            let encrypted = ElGamal::encrypt_bytes(&bytes, &public_key).unwrap();
+            assert_eq!(
+                ElGamal::decrypt_bytes(&encrypted, &private_key).unwrap(),
+                bytes
+            );
+        }
+        for bytes in [vec![1, 0], vec![0; BLOCK_SIZE], vec![]] {
+            let encrypted = ElGamal::encrypt_bytes(&bytes, &public_key).unwrap();
+            assert_eq!(
+                ElGamal::decrypt_bytes(&encrypted, &private_key).unwrap(),
+                bytes
+            );
+        }
+
+        let encrypted = ElGamal::encrypt("hello", &public_key).unwrap();
+        assert!(encrypted.ends_with(":5"));
+        let (numbers, _length) = encrypted.split_once(':').unwrap();
+        assert!(ElGamal::decrypt(&format!("{}:2", numbers), &private_key).is_err());
+        assert!(ElGamal::decrypt(&format!("{}:9", numbers), &private_key).is_err());
+        assert!(ElGamal::decrypt(&format!("{}:x", numbers), &private_key).is_err());
+    }
+
+    #[test]
+    fn test_split_ciphertext() {
+        let blocks = ElGamal::split_ciphertext("1 2 3 4 ");
+        assert_eq!(blocks, vec!["1 2", "3 4"]);
+    }
+
+    #[test]
+    fn test_to_public_key() {
+        let key = ElGamal::to_public_key("123 456 789").unwrap();
+
+        assert_eq!(key.p, 123);
+        assert_eq!(key.g, 456);
+        assert_eq!(key.h, 789);
+    }
+
+    #[test]
+    fn test_to_string() {
+        let key = PublicKey {
+            p: 123_u128,
+            g: 456_u128,
+            h: 789_u128,
+        };
+        let mes = ElGamal::to_string(&key);
+
+        assert_eq!(mes, "123 456 789");
+    }
+
+    #[test]
+    fn test_to_public_key_malformed() {
+        assert!(ElGamal::to_public_key("").is_err());
+        assert!(ElGamal::to_public_key("123 456").is_err());
+        assert!(ElGamal::to_public_key("123 456 789 10").is_err());
+        assert!(ElGamal::to_public_key("123 abc 789").is_err());
+    }
+}
@@ -1,6 +1,80 @@
 //! Trait for encryption protocols
 //!
 //! This module contains a simple trait that allows for the integration of encryption protocols.
+use crate::hashing::sha256_hex;
+use std::fmt;
+use std::str::FromStr;
+
+/// Size of the plaintext blocks (in bytes) that are encrypted separately.
+pub(crate) const BLOCK_SIZE: usize = 8;
+
+/// Splits the plaintext into blocks of at most `BLOCK_SIZE` bytes. Blocks never end
+/// inside a character, so every block is a valid UTF-8 string.
+pub(crate) fn split_into_blocks(message: &str) -> Vec<&str> {
+    let mut blocks: Vec<&str> = Vec::new();
+    let mut cur_mes = message;
+    while !cur_mes.is_empty() {
+        let mut boundary = BLOCK_SIZE.min(cur_mes.len());
+        while !cur_mes.is_char_boundary(boundary) {
+            boundary -= 1;
+        }
+        let (head, tail) = cur_mes.split_at(boundary);
+        blocks.push(head);
+        cur_mes = tail;
+    }
+
+    blocks
+}
+
+/// Error returned by the methods of the encryption protocols in this crate.
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    /// The string could not be converted to a public key. Contains the reason.
+    InvalidPublicKey(String),
+    /// The message could not be encrypted. Contains the reason.
+    InvalidPlaintext(String),
+    /// The encrypted message is invalid (e.g. it could not be decrypted). Contains the reason.
+    InvalidCiphertext(String),
+    /// The string could not be converted to a private key. Contains the reason.
+    InvalidPrivateKey(String),
+    /// The key has no unused material left (e.g. an exhausted one-time pad).
+    KeyExhausted,
+    /// The operation is not supported by the protocol. Contains the name of the operation.
+    Unsupported(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::InvalidPublicKey(reason) => {
+                write!(f, "invalid public key: {}", reason)
+            }
+            ProtocolError::InvalidPlaintext(reason) => {
+                write!(f, "invalid plaintext: {}", reason)
+            }
+            ProtocolError::InvalidCiphertext(reason) => {
+                write!(f, "invalid ciphertext: {}", reason)
+            }
+            ProtocolError::InvalidPrivateKey(reason) => {
+                write!(f, "invalid private key: {}", reason)
+            }
+            ProtocolError::KeyExhausted => write!(f, "key exhausted"),
+            ProtocolError::Unsupported(operation) => {
+                write!(f, "{} is not supported", operation)
+            }
+        }
+    }
+}
+
+/// Parses numbers separated by spaces, e.g. the components of a private key.
+/// Returns `None` if any of the components is not a number.
+pub(crate) fn parse_numbers<N: FromStr>(message: &str) -> Option<Vec<N>> {
+    message
+        .split(' ')
+        .map(|component| component.parse().ok())
+        .collect()
+}
+
 /// Trait for encryption protocols.
 ///
 /// Implementations of this trait need to create custom structs for public and private keys,
@@ -11,27 +85,324 @@ pub trait EncryptionProtocol {
     type PublicKey: Clone;
 
     /// Type for private keys. Private key is known only to its owner.
-    type PrivateKey;
+    /// Private keys are cloned when the environment is snapshotted.
+    type PrivateKey: Clone;
+
+    /// Type for errors returned by the methods of the protocol. The protocols in this crate
+    /// use `ProtocolError`; other types must be convertible from it, as the default
+    /// methods return `ProtocolError`.
+    type Error: fmt::Display + From<ProtocolError>;
 
-    /// Method for encrypting messages. Accepts a message as a parameter and
+    /// Method for encrypting arbitrary bytes. Accepts a message as a parameter and
     /// encrypts it using the public key. To encrypt the message, the sender uses
-    /// the public key of the receiver of this message.
-    fn encrypt(message: &str, pub_key: &Self::PublicKey) -> String;
+    /// the public key of the receiver of this message. Returns an error
+    /// if the message cannot be encrypted.
+    fn encrypt_bytes(message: &[u8], pub_key: &Self::PublicKey) -> Result<Vec<u8>, Self::Error>;
+
+    /// Method for decrypting bytes produced by `encrypt_bytes`. To decrypt the message,
+    /// the receiver uses their own private key. Returns an error if the encrypted
+    /// message is malformed.
+    fn decrypt_bytes(message: &[u8], priv_key: &Self::PrivateKey) -> Result<Vec<u8>, Self::Error>;
 
-    /// Method for decrypting messages. Accepts an encrypted message as a parameter and
-    /// decrypts it using the private key. To decrypt the message, the receiver uses
-    /// their own private key.
-    fn decrypt(message: &str, priv_key: &Self::PrivateKey) -> String;
+    /// Method for encrypting messages. Encrypts the UTF-8 bytes of the message
+    /// with `encrypt_bytes`. The ciphertexts of the protocols in this crate are
+    /// valid UTF-8; protocols with binary ciphertexts should override this method
+    /// (e.g. to write the bytes in base64). Returns an error if the ciphertext
+    /// is not valid UTF-8.
+    fn encrypt(message: &str, pub_key: &Self::PublicKey) -> Result<String, Self::Error> {
+        let encrypted = Self::encrypt_bytes(message.as_bytes(), pub_key)?;
+        String::from_utf8(encrypted).map_err(|_| {
+            ProtocolError::InvalidCiphertext(String::from("ciphertext is not valid UTF-8")).into()
+        })
+    }
+
+    /// Method for decrypting messages. Decrypts the bytes of the message with
+    /// `decrypt_bytes`; invalid UTF-8 in the result is replaced with `U+FFFD`.
+    fn decrypt(message: &str, priv_key: &Self::PrivateKey) -> Result<String, Self::Error> {
+        let decrypted = Self::decrypt_bytes(message.as_bytes(), priv_key)?;
+        Ok(String::from_utf8_lossy(&decrypted).into_owned())
+    }
+
+    /// Method for encrypting a long message at once (e.g. using hybrid encryption)
+    /// instead of encrypting it block by block. The result must be accepted by `decrypt`.
+    /// Returns `None` if the protocol does not support it, which is the default.
+    fn encrypt_long(_message: &str, _pub_key: &Self::PublicKey) -> Option<String> {
+        None
+    }
+
+    /// Method for signing messages. Accepts a message as a parameter and
+    /// signs it using the private key of the sender. Returns an error
+    /// if the protocol does not support signatures, which is the default.
+    fn sign(_message: &str, _priv_key: &Self::PrivateKey) -> Result<String, Self::Error> {
+        Err(ProtocolError::Unsupported(String::from("signing")).into())
+    }
+
+    /// Method for verifying signatures. Accepts a message and its signature as parameters
+    /// and checks the signature using the public key of the sender. Returns an error
+    /// if the protocol does not support signatures, which is the default.
+    fn verify(
+        _message: &str,
+        _signature: &str,
+        _pub_key: &Self::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        Err(ProtocolError::Unsupported(String::from("verification of signatures")).into())
+    }
+
+    /// Method for checking that a public key is safe to encrypt with. Returns an error
+    /// describing the problem if the key is invalid. By default, every key is accepted.
+    fn validate_public_key(_pub_key: &Self::PublicKey) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Method for encrypting a message block by block. By default, the message is split
+    /// into blocks of at most `BLOCK_SIZE` bytes, and every block is encrypted with `encrypt`,
+    /// so there is exactly one encrypted block for every plaintext block. Protocols may
+    /// define their own blocking (e.g. a stream cipher encrypts the whole message
+    /// as one block), as long as `decrypt_blocks` accepts the result.
+    /// Returns the encrypted blocks, or the first error.
+    fn encrypt_blocks(
+        message: &str,
+        pub_key: &Self::PublicKey,
+    ) -> Result<Vec<String>, Self::Error> {
+        split_into_blocks(message)
+            .into_iter()
+            .map(|block| Self::encrypt(block, pub_key))
+            .collect()
+    }
+
+    /// Method for decrypting a message encrypted with `encrypt_blocks`. Every block
+    /// is decrypted with `decrypt`, and the results are concatenated.
+    /// Returns the first error.
+    fn decrypt_blocks(
+        blocks: &[String],
+        priv_key: &Self::PrivateKey,
+    ) -> Result<String, Self::Error> {
+        blocks
+            .iter()
+            .map(|block| Self::decrypt(block, priv_key))
+            .collect()
+    }
+
+    /// Method for encrypting arbitrary bytes block by block (e.g. attachments). Like
+    /// `encrypt_blocks`, the bytes are split into blocks of at most `BLOCK_SIZE` bytes
+    /// by default, and every block is encrypted with `encrypt_bytes`.
+    /// Returns the encrypted blocks, or the first error.
+    fn encrypt_byte_blocks(
+        message: &[u8],
+        pub_key: &Self::PublicKey,
+    ) -> Result<Vec<Vec<u8>>, Self::Error> {
+        message
+            .chunks(BLOCK_SIZE)
+            .map(|block| Self::encrypt_bytes(block, pub_key))
+            .collect()
+    }
+
+    /// Method for decrypting bytes encrypted with `encrypt_byte_blocks`. Every block
+    /// is decrypted with `decrypt_bytes`, and the results are concatenated.
+    /// Returns the first error.
+    fn decrypt_byte_blocks(
+        blocks: &[Vec<u8>],
+        priv_key: &Self::PrivateKey,
+    ) -> Result<Vec<u8>, Self::Error> {
+        let mut decrypted: Vec<u8> = Vec::new();
+        for block in blocks {
+            decrypted.extend(Self::decrypt_bytes(block, priv_key)?);
+        }
+        Ok(decrypted)
+    }
+
+    /// Method for joining encrypted blocks into a single message. By default,
+    /// the blocks are separated by spaces.
+    fn join_ciphertext(blocks: &[String]) -> String {
+        blocks.join(" ")
+    }
+
+    /// Method for splitting an encrypted message into blocks. Each block is decrypted
+    /// separately. By default, the blocks are separated by whitespace.
+    fn split_ciphertext(message: &str) -> Vec<String> {
+        message.split_whitespace().map(String::from).collect()
+    }
 
     /// Method for creating public and private keys. Public/private key pair is used for
     /// encrypting and decrypting messages.
     fn create_keys() -> (Self::PublicKey, Self::PrivateKey);
 
+    /// Method for creating new keys of the same kind as the given public key. The method
+    /// is used for key rotation (see `User::create_keys`). By default, `create_keys`
+    /// is called.
+    fn create_keys_like(_pub_key: &Self::PublicKey) -> (Self::PublicKey, Self::PrivateKey) {
+        Self::create_keys()
+    }
+
     /// Method for converting a string to a public key. The method is needed
-    /// to obtain public keys of other users from messages.
-    fn to_public_key(message: &str) -> Self::PublicKey;
+    /// to obtain public keys of other users from messages. Returns an error
+    /// if the string is not a valid public key.
+    fn to_public_key(message: &str) -> Result<Self::PublicKey, Self::Error>;
 
     /// Method for converting a public key to a string. The method is needed
     /// to send public keys to other users as a message.
     fn to_string(pub_key: &Self::PublicKey) -> String;
+
+    /// Method for converting a private key to a string. The method is needed to export
+    /// the private keys of a user (see `User::export_private_keys`). Returns an error
+    /// if the protocol does not support it, which is the default.
+    fn private_key_to_string(_priv_key: &Self::PrivateKey) -> Result<String, Self::Error> {
+        Err(ProtocolError::Unsupported(String::from("export of private keys")).into())
+    }
+
+    /// Method for converting a string created by `private_key_to_string` to a private key.
+    /// Returns an error if the string is not a valid private key or the protocol
+    /// does not support it, which is the default.
+    fn to_private_key(_message: &str) -> Result<Self::PrivateKey, Self::Error> {
+        Err(ProtocolError::Unsupported(String::from("import of private keys")).into())
+    }
+
+    /// Method for getting the name of the protocol, e.g. for logging.
+    /// By default, the name of the implementing type is returned.
+    fn protocol_name() -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Method for getting the size (in bits) of the keys created by `create_keys`.
+    /// By default, 0 is returned, which means that the size is unknown.
+    fn key_size_bits() -> usize {
+        0
+    }
+
+    /// Method for computing a short fingerprint of a public key. Users can compare
+    /// fingerprints out of band to check that they received the right key.
+    /// By default, the fingerprint is the first 16 hex digits of the SHA-256 hash
+    /// of the `to_string` output, so it does not depend on the build.
+    fn fingerprint(pub_key: &Self::PublicKey) -> String {
+        let mut fingerprint = sha256_hex(Self::to_string(pub_key).as_bytes());
+        fingerprint.truncate(16);
+        fingerprint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption_protocol::{
+        BLOCK_SIZE, EncryptionProtocol, ProtocolError, split_into_blocks,
+    };
+    use crate::rsa::RSA;
+
+    /// Protocol that implements only the required methods.
+    struct Plain {}
+
+    impl EncryptionProtocol for Plain {
+        type PublicKey = ();
+        type PrivateKey = ();
+        type Error = ProtocolError;
+
+        fn encrypt_bytes(message: &[u8], _pub_key: &()) -> Result<Vec<u8>, ProtocolError> {
+            Ok(message.to_vec())
+        }
+
+        fn decrypt_bytes(message: &[u8], _priv_key: &()) -> Result<Vec<u8>, ProtocolError> {
+            Ok(message.to_vec())
+        }
+
+        fn create_keys() -> ((), ()) {
+            ((), ())
+        }
+
+        fn to_public_key(_message: &str) -> Result<(), ProtocolError> {
+            Ok(())
+        }
+
+        fn to_string(_pub_key: &()) -> String {
+            String::new()
+        }
+    }
+
+    /// Protocol whose ciphertexts are not valid UTF-8.
+    struct Inverted {}
+
+    impl EncryptionProtocol for Inverted {
+        type PublicKey = ();
+        type PrivateKey = ();
+        type Error = ProtocolError;
+
+        fn encrypt_bytes(message: &[u8], _pub_key: &()) -> Result<Vec<u8>, ProtocolError> {
+            Ok(message.iter().map(|byte| !byte).collect())
+        }
+
+        fn decrypt_bytes(message: &[u8], _priv_key: &()) -> Result<Vec<u8>, ProtocolError> {
+            Ok(message.iter().map(|byte| !byte).collect())
+        }
+
+        fn create_keys() -> ((), ()) {
+            ((), ())
+        }
+
+        fn to_public_key(_message: &str) -> Result<(), ProtocolError> {
+            Ok(())
+        }
+
+        fn to_string(_pub_key: &()) -> String {
+            String::new()
+        }
+    }
+
+    #[test]
+    fn test_default_metadata() {
+        assert!(Plain::protocol_name().ends_with("Plain"));
+        assert_eq!(Plain::key_size_bits(), 0);
+    }
+
+    #[test]
+    fn test_default_methods() {
+        assert_eq!(Plain::encrypt("abc", &()).unwrap(), "abc");
+        assert_eq!(
+            Inverted::encrypt("abc", &()),
+            Err(ProtocolError::InvalidCiphertext(String::from(
+                "ciphertext is not valid UTF-8"
+            )))
+        );
+        assert_eq!(
+            Plain::sign("abc", &()),
+            Err(ProtocolError::Unsupported(String::from("signing")))
+        );
+        assert_eq!(
+            Plain::to_private_key("").unwrap_err().to_string(),
+            "import of private keys is not supported"
+        );
+    }
+
+    #[test]
+    fn test_split_into_blocks() {
+        assert_eq!(split_into_blocks("naïve café"), vec!["naïve c", "afé"]);
+        assert_eq!(
+            split_into_blocks("abcdefghijklmnop"),
+            vec!["abcdefgh", "ijklmnop"]
+        );
+        assert_eq!(split_into_blocks("🦀🦀🦀"), vec!["🦀🦀", "🦀"]);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_blocks() {
+        let (public_key, private_key) = RSA::create_keys();
+        for (message, block_count) in [
+            ("", 0),
+            ("abcdefgh", 1),
+            ("abcdefghi", 2),
+            ("a b c d e f g h ", 2),
+            ("🦀🦀🦀", 2),
+        ] {
+            let blocks = RSA::encrypt_blocks(message, &public_key).unwrap();
+            assert_eq!(blocks.len(), block_count);
+            assert!(blocks.iter().all(|block| !block.contains(' ')));
+            assert_eq!(RSA::decrypt_blocks(&blocks, &private_key).unwrap(), message);
+
+            let joined = RSA::join_ciphertext(&blocks);
+            assert_eq!(RSA::split_ciphertext(&joined), blocks);
+        }
+        assert_eq!(
+            RSA::encrypt_blocks(&"x".repeat(3 * BLOCK_SIZE), &public_key)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
 }
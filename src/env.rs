@@ -2,17 +2,234 @@
 //!
 //! Environment is responsible for handling users and sending messages.
 use crate::encryption_protocol::EncryptionProtocol;
-use crate::message::{Message, MessageType};
+use crate::message::{Message, MessageId, MessageType, json_string};
 use crate::user::User;
-use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use crate::user::UserState;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// Routing plan of a message.
+///
+/// Contains the names of the users that would receive the message (sorted by name)
+/// and the error that would occur during sending, if any.
+#[derive(Debug)]
+pub struct SendPlan {
+    /// Names of the users that would receive the message.
+    pub receivers: Vec<String>,
+    /// Error that would occur during sending.
+    pub error: Option<String>,
+}
+
+/// Statistics of the environment (see `Env::stats`).
+///
+/// The counters are updated on every successful sending and are never reset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnvStats {
+    /// Number of sent messages, including broadcasts and group messages.
+    pub messages_sent: usize,
+    /// Number of broadcasted messages (e.g. public keys).
+    pub broadcasts: usize,
+    /// Number of bytes written to the log, including the header and error lines.
+    pub bytes_logged: usize,
+    /// Number of messages delivered to every user (by name).
+    pub received: BTreeMap<String, usize>,
+}
+
+/// Decision of the send hook about a message (see `Env::set_send_hook`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookDecision {
+    /// The message is delivered as usual.
+    Deliver,
+    /// The message is silently dropped: it is neither logged nor delivered.
+    Drop,
+    /// The message is delivered by `Env::deliver_delayed` after the given delay.
+    /// A delay that cannot be added to the current time is rejected by `Env::send_message`.
+    Delay(Duration),
+}
+
+/// Model of an unreliable network (see `Env::with_network`).
+///
+/// Every message is dropped with the given probability. Otherwise it is delayed
+/// by a latency chosen uniformly between the minimal and the maximal latency.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkModel {
+    drop_probability: f64,
+    min_latency: Duration,
+    max_latency: Duration,
+    seed: Option<u64>,
+}
+
+impl NetworkModel {
+    /// Creates a network model with the given drop probability and range of latencies.
+    ///
+    /// Returns an error if the probability is not between 0 and 1, or if the minimal
+    /// latency is greater than the maximal latency.
+    pub fn new(
+        drop_probability: f64,
+        min_latency: Duration,
+        max_latency: Duration,
+    ) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&drop_probability) {
+            return Err(String::from("drop probability should be between 0 and 1"));
+        }
+        if min_latency > max_latency {
+            return Err(String::from(
+                "minimal latency should not exceed maximal latency",
+            ));
+        }
+        Ok(Self {
+            drop_probability,
+            min_latency,
+            max_latency,
+            seed: None,
+        })
+    }
+
+    /// Sets the seed of the random number generator, so the simulation is deterministic.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// Hook that is called for every sent message (see `Env::set_send_hook`).
+pub type SendHook = Box<dyn FnMut(&Message) -> HookDecision + Send>;
+
+/// Error that occurs during sending a message.
+#[derive(Debug, PartialEq)]
+pub enum SendError {
+    /// The sender of the message is not found in the environment.
+    SenderNotFound(String),
+    /// The receiver of the message is not found in the environment.
+    ReceiverNotFound(String),
+    /// The message could not be written to the log. Contains the reason.
+    LogWriteFailed(String),
+    /// The message was already sent or is too old. Contains the name of the sender.
+    ReplayDetected(String),
+    /// The sender of the message could not be verified (see `Env::with_sender_verification`).
+    /// Contains the name of the sender.
+    SenderNotVerified(String),
+    /// The delay chosen by the send hook cannot be added to the current time.
+    InvalidDelay(Duration),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::SenderNotFound(name) => write!(f, "sender '{}' not found", name),
+            SendError::ReceiverNotFound(name) => write!(f, "receiver '{}' not found", name),
+            SendError::LogWriteFailed(reason) => write!(f, "failed to write log: {}", reason),
+            SendError::ReplayDetected(name) => {
+                write!(f, "replayed message from sender '{}'", name)
+            }
+            SendError::SenderNotVerified(name) => {
+                write!(f, "sender '{}' could not be verified", name)
+            }
+            SendError::InvalidDelay(delay) => write!(f, "invalid delay of {:?}", delay),
+        }
+    }
+}
+
+/// Policy for users with the same name when environments are merged (see `Env::merge_with_policy`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergePolicy {
+    /// The merge is rejected if any name is taken.
+    Reject,
+    /// The imported user is renamed by appending the suffix to its name
+    /// (repeatedly, until the name is free).
+    Rename(String),
+}
+
+/// Error that occurs during merging environments.
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+    /// The names of the imported users are already taken. Contains the sorted names.
+    NameCollision(Vec<String>),
+    /// The suffix of `MergePolicy::Rename` is empty, so users cannot be renamed.
+    EmptySuffix,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::NameCollision(names) => {
+                write!(f, "names are already taken: {}", names.join(", "))
+            }
+            MergeError::EmptySuffix => write!(f, "suffix for renaming should not be empty"),
+        }
+    }
+}
+
+/// Format of the log written by the environment.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines produced by `Display` of messages.
+    #[default]
+    Text,
+    /// One JSON object per line produced by `Message::to_json`.
+    Json,
+}
+
+/// Verbosity of the log written by the environment. Every level also includes
+/// the records of the previous levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Nothing is logged except for the header.
+    Off,
+    /// Only errors (e.g. public keys that cannot be parsed) are logged.
+    Errors,
+    /// Ordinary messages, self-destructing messages and attachments are logged.
+    Messages,
+    /// All messages are logged, including public key broadcasts, key exchanges,
+    /// delete receipts and acknowledgements.
+    #[default]
+    Verbose,
+}
+
+/// Parses a text log written by the environment into messages.
+///
+/// Each message line is the `Display` form of a message. Error lines (starting
+/// with `"error: "`) and header lines (starting with `"protocol: "`) are skipped.
+/// The texts of the messages stay encrypted, because private keys are not written
+/// to the log. The ids of the messages are restored.
+pub fn parse_log(file_name: &str) -> Result<Vec<Message>, String> {
+    let contents = fs::read_to_string(file_name).map_err(|error| error.to_string())?;
+    parse_log_lines(contents.lines())
+}
+
+fn parse_log_lines<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Vec<Message>, String> {
+    lines
+        .filter(|line| {
+            !line.is_empty() && !line.starts_with("error: ") && !line.starts_with("protocol: ")
+        })
+        .map(Message::from_log_line)
+        .collect()
+}
+
+/// Destination of the log lines of the environment.
+enum LogSink {
+    /// The lines are appended to the file with the given path.
+    File { file: fs::File, path: String },
+    /// The lines are kept in memory (see `Env::log_lines`).
+    Memory(Vec<String>),
+    /// The log is disabled, so nothing is written.
+    None,
+}
 
 /// Environment struct.
 ///
 /// Environment is responsible for handling users and sending messages.
+/// Users are stored in the order of their names, so iteration over users
+/// (e.g. during broadcasts) is deterministic.
 /// It supports creating new users, sending public keys and encrypted messages
-/// between them. All of the encrypted messages are written to the log.
+/// between them. The encrypted messages are written to the log (see `Env::set_log_level`).
 ///
 /// # Example
 /// ```rust
@@ -34,109 +251,1802 @@ use std::io::Write;
 /// //env.create_user("Alice");
 /// ```
 pub struct Env<T: EncryptionProtocol> {
-    users: HashMap<String, User<T>>,
-    log: fs::File,
+    users: BTreeMap<String, User<T>>,
+    log: LogSink,
+    log_format: LogFormat,
+    log_level: LogLevel,
+    redact_ciphertext: bool,
+    stats: EnvStats,
+    history: Vec<Message>,
+    buffer_capacity: Option<usize>,
+    self_delivery: bool,
+    replay_window: Option<Duration>,
+    seen_nonces: HashMap<(String, u64), SystemTime>,
+    sequence_numbers: HashMap<(String, String), u64>,
+    send_hook: Option<SendHook>,
+    delayed: Vec<(SystemTime, Message)>,
+    groups: BTreeMap<String, BTreeSet<String>>,
+    sender_verification: bool,
+    identities: HashMap<String, T::PublicKey>,
+    rotation: HashMap<String, (Duration, SystemTime)>,
+    duplicate_detection: bool,
+    delivered_ids: HashSet<MessageId>,
 }
 
 impl<T: EncryptionProtocol> Env<T> {
     /// Creates a new environment. Outputs the log to `log.txt` by default.
+    ///
+    /// Note that the file is opened in append mode and is shared by all environments
+    /// created with `new`, so the logs of different runs accumulate in it.
+    /// Use `from_file` for a separate log, `clear_log` to start the log from scratch,
+    /// or `in_memory`/`without_log` to avoid the file entirely.
     pub fn new() -> Self {
+        Self::from_file("log.txt")
+    }
+
+    /// Creates a new environment from file. Outputs the log to the specified file.
+    pub fn from_file(file_name: &str) -> Self {
+        Self::from_file_with_format(file_name, LogFormat::Text)
+    }
+
+    /// Creates a new environment from file. Outputs the log to the specified file
+    /// in the given format.
+    ///
+    /// The log starts with a header line containing the name of the protocol
+    /// and the size of its keys (see `EncryptionProtocol::protocol_name`).
+    pub fn from_file_with_format(file_name: &str, log_format: LogFormat) -> Self {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_name)
+            .expect("failed to open file");
+        Self::with_log(
+            LogSink::File {
+                file,
+                path: String::from(file_name),
+            },
+            log_format,
+        )
+    }
+
+    /// Creates a new environment that keeps the log in memory instead of a file.
+    /// The lines of the log are available through `log_lines`.
+    pub fn in_memory() -> Self {
+        Self::in_memory_with_format(LogFormat::Text)
+    }
+
+    /// Creates a new environment that keeps the log in memory in the given format.
+    pub fn in_memory_with_format(log_format: LogFormat) -> Self {
+        Self::with_log(LogSink::Memory(Vec::new()), log_format)
+    }
+
+    /// Creates a new environment without a log. Messages are delivered as usual,
+    /// but nothing is written, and no file is created.
+    pub fn without_log() -> Self {
+        Self::with_log(LogSink::None, LogFormat::Text)
+    }
+
+    fn with_log(log: LogSink, log_format: LogFormat) -> Self {
+        let mut env = Self {
+            users: BTreeMap::new(),
+            history: Vec::new(),
+            log,
+            log_format,
+            log_level: LogLevel::default(),
+            redact_ciphertext: false,
+            stats: EnvStats::default(),
+            buffer_capacity: None,
+            self_delivery: false,
+            replay_window: None,
+            seen_nonces: HashMap::new(),
+            sequence_numbers: HashMap::new(),
+            send_hook: None,
+            delayed: Vec::new(),
+            groups: BTreeMap::new(),
+            sender_verification: false,
+            identities: HashMap::new(),
+            rotation: HashMap::new(),
+            duplicate_detection: false,
+            delivered_ids: HashSet::new(),
+        };
+        // A failed header write is not fatal: the error is reported on the first send.
+        let _ = env.write_log_header();
+        env
+    }
+
+    /// Takes a snapshot of the state of the environment. Private keys are included,
+    /// so the users restored from the snapshot can decrypt old messages.
+    /// Overflow callbacks of the users are not included.
+    pub fn snapshot(&self) -> EnvSnapshot<T> {
+        EnvSnapshot {
+            users: self
+                .users
+                .iter()
+                .map(|(name, user)| (name.clone(), user.snapshot()))
+                .collect(),
+            history: self.history.clone(),
+            seen_nonces: self.seen_nonces.clone(),
+            sequence_numbers: self.sequence_numbers.clone(),
+            groups: self.groups.clone(),
+            identities: self.identities.clone(),
+            rotation: self.rotation.clone(),
+            delivered_ids: self.delivered_ids.clone(),
+        }
+    }
+
+    /// Restores the state of the environment from the snapshot. All current users,
+    /// groups, identities and rotation intervals are replaced with the ones from
+    /// the snapshot. The log file and the settings of the environment are kept.
+    pub fn restore(&mut self, snapshot: EnvSnapshot<T>) {
+        self.users = snapshot.users;
+        self.history = snapshot.history;
+        self.seen_nonces = snapshot.seen_nonces;
+        self.sequence_numbers = snapshot.sequence_numbers;
+        self.groups = snapshot.groups;
+        self.identities = snapshot.identities;
+        self.rotation = snapshot.rotation;
+        self.delivered_ids = snapshot.delivered_ids;
+    }
+
+    /// Sets the capacity of the message buffers of all users in the environment,
+    /// including the users created later. See `User::set_capacity`.
+    pub fn with_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.buffer_capacity = Some(capacity);
+        for user in self.users.values_mut() {
+            user.set_capacity(self.buffer_capacity);
+        }
+        self
+    }
+
+    fn write_log_line(&mut self, line: String) -> Result<(), SendError> {
+        let bytes = line.len() + 1;
+        match &mut self.log {
+            LogSink::File { file, .. } => writeln!(file, "{}", line)
+                .map_err(|error| SendError::LogWriteFailed(error.to_string()))?,
+            LogSink::Memory(lines) => lines.push(line),
+            LogSink::None => return Ok(()),
+        }
+        self.stats.bytes_logged += bytes;
+        Ok(())
+    }
+
+    /// Returns the statistics of the environment: the numbers of sent messages
+    /// and broadcasts, the size of the log and the numbers of received messages per user.
+    pub fn stats(&self) -> EnvStats {
+        self.stats.clone()
+    }
+
+    /// Sets the verbosity of the log (`LogLevel::Verbose` by default).
+    pub fn set_log_level(&mut self, log_level: LogLevel) {
+        self.log_level = log_level;
+    }
+
+    /// Returns the verbosity of the log.
+    pub fn get_log_level(&self) -> LogLevel {
+        self.log_level
+    }
+
+    /// Enables or disables redaction of the log (disabled by default). In redacted logs,
+    /// the text of every message except public and identity keys is replaced with
+    /// `<redacted len=N>` (`N` - length of the text in bytes). Redacted logs cannot be decrypted.
+    pub fn set_redact_ciphertext(&mut self, redact_ciphertext: bool) {
+        self.redact_ciphertext = redact_ciphertext;
+    }
+
+    fn write_log(&mut self, message: &Message) -> Result<(), SendError> {
+        let required_level = match message.get_message_type() {
+            MessageType::Message
+            | MessageType::SelfDestruct
+            | MessageType::Attachment
+            | MessageType::Envelope => LogLevel::Messages,
+            MessageType::PublicKey
+            | MessageType::DeleteReceipt
+            | MessageType::KeyExchange
+            | MessageType::Ack
+            | MessageType::Identity => LogLevel::Verbose,
+        };
+        if self.log_level < required_level {
+            return Ok(());
+        }
+        let redacted: Message;
+        let message = match message.get_message_type() {
+            MessageType::PublicKey => message,
+            MessageType::Identity => message,
+            _ if self.redact_ciphertext => {
+                redacted = message.redacted();
+                &redacted
+            }
+            _ => message,
+        };
+        let line = match self.log_format {
+            LogFormat::Text => message.to_string(),
+            LogFormat::Json => message.to_json(),
+        };
+        self.write_log_line(line)
+    }
+
+    fn write_log_header(&mut self) -> Result<(), SendError> {
+        let line = match self.log_format {
+            LogFormat::Text => format!(
+                "protocol: '{}'; key size: '{}'",
+                T::protocol_name(),
+                T::key_size_bits()
+            ),
+            LogFormat::Json => format!(
+                "{{\"protocol\":{},\"key_size_bits\":{}}}",
+                json_string(T::protocol_name()),
+                T::key_size_bits()
+            ),
+        };
+        self.write_log_line(line)
+    }
+
+    fn write_log_error(&mut self, error: &str) -> Result<(), SendError> {
+        if self.log_level < LogLevel::Errors {
+            return Ok(());
+        }
+        let line = match self.log_format {
+            LogFormat::Text => format!("error: {}", error),
+            LogFormat::Json => format!("{{\"error\":{}}}", json_string(error)),
+        };
+        self.write_log_line(line)
+    }
+
+    /// Returns the lines of the log kept in memory, or `None` if the log
+    /// is written to a file or disabled (see `Env::in_memory`).
+    pub fn log_lines(&self) -> Option<&[String]> {
+        match &self.log {
+            LogSink::File { .. } | LogSink::None => None,
+            LogSink::Memory(lines) => Some(lines),
+        }
+    }
+
+    /// Removes all lines from the log, including the header. The log file is truncated,
+    /// and the following messages are written from its beginning.
+    ///
+    /// Returns an error if the log file cannot be truncated.
+    pub fn clear_log(&mut self) -> Result<(), String> {
+        match &mut self.log {
+            LogSink::File { file, .. } => file.set_len(0).map_err(|error| error.to_string()),
+            LogSink::Memory(lines) => {
+                lines.clear();
+                Ok(())
+            }
+            LogSink::None => Ok(()),
+        }
+    }
+
+    /// Reads the messages back from the log of the environment using `parse_log`.
+    /// Only the text format can be parsed. Returns an error if the log is disabled.
+    pub fn read_log(&self) -> Result<Vec<Message>, String> {
+        if self.log_format != LogFormat::Text {
+            return Err(String::from("only text logs can be parsed"));
+        }
+        match &self.log {
+            LogSink::File { path, .. } => parse_log(path),
+            LogSink::Memory(lines) => parse_log_lines(lines.iter().map(String::as_str)),
+            LogSink::None => Err(String::from("log is disabled")),
+        }
+    }
+
+    /// Reads the messages from the log (see `Env::read_log`) whose timestamps are
+    /// between `start` and `end`, both inclusive, in the order of the log.
+    /// The result is empty if `start` is later than `end`.
+    pub fn log_entries_between(
+        &self,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Vec<Message>, String> {
+        Ok(self
+            .read_log()?
+            .into_iter()
+            .filter(|message| (start..=end).contains(&message.get_timestamp()))
+            .collect())
+    }
+
+    /// Creates new user. Note that all users in the environment must have unique names.
+    pub fn create_user(&mut self, user_name: &str) {
+        if user_name.is_empty() {
+            panic!("name should not be empty");
+        }
+        match self.users.get(&String::from(user_name)) {
+            Some(_) => panic!("this name is already taken!"),
+            None => {
+                let mut user = User::<T>::new(user_name);
+                user.set_capacity(self.buffer_capacity);
+                self.users.insert(String::from(user_name), user)
+            }
+        };
+    }
+
+    /// Removes the user from the environment. Returns `false` if the user is not found.
+    ///
+    /// Messages already delivered to other users and the keys cached by them are kept.
+    /// The user leaves all groups.
+    pub fn remove_user(&mut self, user_name: &str) -> bool {
+        self.identities.remove(user_name);
+        self.rotation.remove(user_name);
+        for members in self.groups.values_mut() {
+            members.remove(user_name);
+        }
+        self.users.remove(user_name).is_some()
+    }
+
+    /// Renames the user. Returns an error if the old name is not found
+    /// or the new name is empty or already taken.
+    ///
+    /// Other users still cache the keys of the user under the old name, so the user
+    /// should broadcast a new public key to be able to receive encrypted messages.
+    pub fn rename_user(&mut self, old_name: &str, new_name: &str) -> Result<(), String> {
+        if new_name.is_empty() {
+            return Err(String::from("name should not be empty"));
+        }
+        if self.users.contains_key(new_name) {
+            return Err(String::from("this name is already taken!"));
+        }
+        let mut user = self
+            .users
+            .remove(old_name)
+            .ok_or_else(|| String::from("user not found"))?;
+        user.set_name(new_name);
+        self.users.insert(String::from(new_name), user);
+        if let Some(identity) = self.identities.remove(old_name) {
+            self.identities.insert(String::from(new_name), identity);
+        }
+        if let Some(rotation) = self.rotation.remove(old_name) {
+            self.rotation.insert(String::from(new_name), rotation);
+        }
+        for members in self.groups.values_mut() {
+            if members.remove(old_name) {
+                members.insert(String::from(new_name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Imports the users of the other environment with their buffers, keys and caches.
+    /// Named groups, registered identities and rotation intervals of the users are imported
+    /// as well, and the history of the other environment is appended to the history.
+    /// The log, the statistics and the settings of this environment are kept, and the
+    /// delayed messages of the other environment are discarded.
+    ///
+    /// Returns an error if a name is already taken; in this case, nothing is imported.
+    /// Use `merge_with_policy` to rename such users instead.
+    pub fn merge(&mut self, other: Env<T>) -> Result<(), MergeError> {
+        self.merge_with_policy(other, MergePolicy::Reject)
+    }
+
+    /// Imports the users of the other environment (see `Env::merge`), resolving name
+    /// collisions with the given policy. Groups with the same name are joined.
+    ///
+    /// Note that the users keep the caches of the other environment, so they know
+    /// the keys of renamed users under their old names (see `Env::rename_user`).
+    pub fn merge_with_policy(
+        &mut self,
+        other: Env<T>,
+        policy: MergePolicy,
+    ) -> Result<(), MergeError> {
+        let mut collisions: Vec<String> = other
+            .users
+            .keys()
+            .filter(|name| self.users.contains_key(*name))
+            .cloned()
+            .collect();
+        let suffix = match &policy {
+            MergePolicy::Reject if !collisions.is_empty() => {
+                collisions.sort();
+                return Err(MergeError::NameCollision(collisions));
+            }
+            MergePolicy::Rename(suffix) if suffix.is_empty() && !collisions.is_empty() => {
+                return Err(MergeError::EmptySuffix);
+            }
+            MergePolicy::Reject => "",
+            MergePolicy::Rename(suffix) => suffix.as_str(),
+        };
+
+        let mut new_names: HashMap<String, String> = HashMap::new();
+        for (name, mut user) in other.users {
+            let mut new_name = name.clone();
+            while self.users.contains_key(&new_name) {
+                new_name += suffix;
+            }
+            user.set_name(&new_name);
+            if self.buffer_capacity.is_some() {
+                user.set_capacity(self.buffer_capacity);
+            }
+            self.users.insert(new_name.clone(), user);
+            new_names.insert(name, new_name);
+        }
+        for (name, identity) in other.identities {
+            self.identities
+                .entry(new_names[&name].clone())
+                .or_insert(identity);
+        }
+        for (name, rotation) in other.rotation {
+            self.rotation.insert(new_names[&name].clone(), rotation);
+        }
+        for (group_name, members) in other.groups {
+            self.groups
+                .entry(group_name)
+                .or_default()
+                .extend(members.iter().map(|name| new_names[name].clone()));
+        }
+        self.history.extend(other.history);
+
+        Ok(())
+    }
+
+    /// Returns a reference to user by name.
+    pub fn get_user(&self, user_name: &str) -> Option<&User<T>> {
+        self.users.get(&String::from(user_name))
+    }
+
+    /// Returns a mutable reference to user by name.
+    pub fn get_mut_user(&mut self, user_name: &str) -> Option<&mut User<T>> {
+        self.users.get_mut(&String::from(user_name))
+    }
+
+    /// Checks whether a given user is present in the environment.
+    pub fn find_user(&self, user_name: &str) -> bool {
+        self.users.contains_key(&String::from(user_name))
+    }
+
+    /// Returns the names of all users in the environment sorted by name.
+    pub fn user_names(&self) -> Vec<String> {
+        self.users.keys().cloned().collect()
+    }
+
+    /// Returns the number of users in the environment.
+    pub fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Returns an iterator over all users in the environment sorted by name.
+    pub fn users(&self) -> impl Iterator<Item = &User<T>> {
+        self.users.values()
+    }
+
+    /// Sets whether broadcasts are also delivered to the buffer of their sender.
+    /// By default, the sender does not receive their own broadcasts.
+    pub fn with_self_delivery(mut self, self_delivery: bool) -> Self {
+        self.self_delivery = self_delivery;
+        self
+    }
+
+    /// Simulates an unreliable network with the given model: messages are dropped
+    /// or delayed randomly (see `NetworkModel`). Delayed messages are delivered by `tick`.
+    ///
+    /// The model is implemented as a send hook, so it replaces the hook set
+    /// with `set_send_hook` and vice versa.
+    pub fn with_network(mut self, model: NetworkModel) -> Self {
+        let mut rng = match model.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        self.set_send_hook(Box::new(move |_: &Message| {
+            if rng.gen_bool(model.drop_probability) {
+                HookDecision::Drop
+            } else if model.max_latency.is_zero() {
+                HookDecision::Deliver
+            } else {
+                HookDecision::Delay(rng.gen_range(model.min_latency..=model.max_latency))
+            }
+        }));
+        self
+    }
+
+    /// Enables replay protection with the given time window.
+    ///
+    /// A message is rejected if a message with the same sender and nonce was sent
+    /// within the window, or if its timestamp is older than the window (such messages
+    /// cannot be checked, because old nonces are forgotten).
+    pub fn with_replay_protection(mut self, window: Duration) -> Self {
+        self.replay_window = Some(window);
+        self
+    }
+
+    /// Enables verification of senders.
+    ///
+    /// The first identity key broadcasted by a user (see `User::create_identity`) is
+    /// registered in the environment. With verification enabled, a message is rejected
+    /// unless its sender proof (see `Message::get_sender_proof`) matches the registered
+    /// identity of its sender, so users cannot send messages on behalf of others.
+    /// The first identity broadcast of a user is accepted if it is signed with the key it
+    /// contains; later identity broadcasts must be signed with the registered key.
+    pub fn with_sender_verification(mut self, sender_verification: bool) -> Self {
+        self.sender_verification = sender_verification;
+        self
+    }
+
+    /// Enables detection of duplicate messages.
+    ///
+    /// With detection enabled, a message whose id was already delivered (e.g. the same
+    /// message or a re-broadcast sent twice) is not delivered again. Instead, the duplicate
+    /// is reported in the log as an error, and sending succeeds.
+    /// Unlike replay protection, the ids are never forgotten.
+    pub fn with_duplicate_detection(mut self, duplicate_detection: bool) -> Self {
+        self.duplicate_detection = duplicate_detection;
+        self
+    }
+
+    /// Checks whether the message was already delivered, and remembers its id otherwise.
+    fn is_duplicate(&mut self, message: &Message) -> bool {
+        self.duplicate_detection && !self.delivered_ids.insert(message.get_id())
+    }
+
+    fn check_sender(&self, message: &Message) -> Result<(), SendError> {
+        if !self.sender_verification {
+            return Ok(());
+        }
+        let not_verified = || SendError::SenderNotVerified(message.get_sender().clone());
+        let proof = message.get_sender_proof().ok_or_else(not_verified)?;
+        let new_identity: T::PublicKey;
+        let identity = match self.identities.get(message.get_sender()) {
+            Some(identity) => identity,
+            None if matches!(message.get_message_type(), MessageType::Identity) => {
+                new_identity =
+                    T::to_public_key(message.get_message()).map_err(|_| not_verified())?;
+                &new_identity
+            }
+            None => return Err(not_verified()),
+        };
+        match T::verify(&message.proof_input(), proof, identity) {
+            Ok(true) => Ok(()),
+            _ => Err(not_verified()),
+        }
+    }
+
+    fn check_replay(&mut self, message: &Message) -> Result<(), SendError> {
+        let Some(window) = self.replay_window else {
+            return Ok(());
+        };
+        let now = SystemTime::now();
+        let oldest = now.checked_sub(window).unwrap_or(SystemTime::UNIX_EPOCH);
+        self.seen_nonces.retain(|_, timestamp| *timestamp >= oldest);
+        let key = (message.get_sender().clone(), message.get_nonce());
+        if message.get_timestamp() < oldest || self.seen_nonces.contains_key(&key) {
+            return Err(SendError::ReplayDetected(message.get_sender().clone()));
+        }
+        self.seen_nonces.insert(key, message.get_timestamp());
+        Ok(())
+    }
+
+    /// Sets the user online or offline. Returns an error if the user is not found.
+    ///
+    /// Messages sent to an offline user (including broadcasts) are kept in a queue
+    /// and delivered to the buffer in order once the user goes online. Key exchanges
+    /// are completed on delivery, and the replies are sent back. Public keys are
+    /// cached immediately, even for offline users.
+    pub fn set_online(&mut self, user_name: &str, online: bool) -> Result<(), SendError> {
+        let user = self
+            .users
+            .get_mut(user_name)
+            .ok_or_else(|| SendError::ReceiverNotFound(String::from(user_name)))?;
+        for reply in user.set_online(online) {
+            match reply {
+                Ok(reply) => self.send_message(reply)?,
+                Err(error) => self.write_log_error(&error)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends an encrypted message between users. Outputs the message to the log.
+    ///
+    /// A message with an empty receiver is broadcasted to all users except its sender
+    /// (see `Env::with_self_delivery`). A broadcasted public key is cached by all users,
+    /// including the sender, so users can send messages to themselves. A broadcasted
+    /// identity key is cached in the same way (see `User::create_identity`).
+    ///
+    /// Key exchange messages are processed by the receiver on delivery, and the reply
+    /// of the receiver is sent back automatically; a failed exchange is written to the log.
+    /// If a broadcast public key cannot be parsed, the failure is written to the log
+    /// and the key is not cached.
+    ///
+    /// A message to a single receiver gets the next sequence number of its sender
+    /// for this receiver (see `Message::get_sequence_number`) before the send hook is called,
+    /// so dropped messages leave gaps. If a send hook is set (see `Env::set_send_hook`),
+    /// it decides whether the message is delivered, dropped or delayed.
+    ///
+    /// If the rotation interval of the sender has elapsed (see `Env::set_rotation_interval`),
+    /// the keys of the sender are rotated before the message is sent.
+    ///
+    /// Returns an error if the sender or the receiver is not found, the message is
+    /// replayed (see `Env::with_replay_protection`), the delay chosen by the send hook
+    /// is too large or the log could not be written. Except for the last case,
+    /// the message is not delivered.
+    pub fn send_message(&mut self, message: Message) -> Result<(), SendError> {
+        self.check_users(&message)?;
+        self.check_sender(&message)?;
+        self.check_replay(&message)?;
+        if message.get_message_type() != MessageType::PublicKey {
+            self.rotate_if_due(message.get_sender())?;
+        }
+        let mut message = message;
+        if !message.get_receiver().is_empty() {
+            let sequence_number = self
+                .sequence_numbers
+                .entry((message.get_sender().clone(), message.get_receiver().clone()))
+                .or_default();
+            *sequence_number += 1;
+            message.set_sequence_number(*sequence_number);
+        }
+        let decision = match self.send_hook.as_mut() {
+            Some(hook) => hook(&message),
+            None => HookDecision::Deliver,
+        };
+        match decision {
+            HookDecision::Deliver => self.deliver(message),
+            HookDecision::Drop => Ok(()),
+            HookDecision::Delay(delay) => {
+                let time = SystemTime::now()
+                    .checked_add(delay)
+                    .ok_or(SendError::InvalidDelay(delay))?;
+                self.delayed.push((time, message));
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the hook that is called in `send_message` for every message before delivery.
+    /// The hook can inspect the message and decide to deliver, drop or delay it,
+    /// e.g. to simulate an unreliable network.
+    pub fn set_send_hook(&mut self, hook: SendHook) {
+        self.send_hook = Some(hook);
+    }
+
+    /// Delivers the delayed messages whose delay has elapsed (see `HookDecision::Delay`).
+    /// Equivalent to `tick(SystemTime::now())`.
+    pub fn deliver_delayed(&mut self) -> Result<(), SendError> {
+        self.tick(SystemTime::now())
+    }
+
+    /// Delivers the delayed messages that are scheduled not later than `now`.
+    /// The messages are delivered in the order of their scheduled times; the other
+    /// messages stay delayed.
+    ///
+    /// Returns the first error that occurred. A message whose sender or receiver
+    /// was removed in the meantime is not delivered.
+    pub fn tick(&mut self, now: SystemTime) -> Result<(), SendError> {
+        let (mut due, delayed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition(|(time, _)| *time <= now);
+        self.delayed = delayed;
+        due.sort_by_key(|(time, _)| *time);
+        let mut result = Ok(());
+        for (_, message) in due {
+            let delivered = self
+                .check_users(&message)
+                .and_then(|_| self.deliver(message));
+            if result.is_ok() {
+                result = delivered;
+            }
+        }
+        result
+    }
+
+    fn check_users(&self, message: &Message) -> Result<(), SendError> {
+        if !self.users.contains_key(message.get_sender()) {
+            return Err(SendError::SenderNotFound(message.get_sender().clone()));
+        } else if !message.get_receiver().is_empty()
+            && !self.users.contains_key(message.get_receiver())
+        {
+            return Err(SendError::ReceiverNotFound(message.get_receiver().clone()));
+        }
+        Ok(())
+    }
+
+    /// Writes the message to the log and delivers it.
+    fn deliver(&mut self, message: Message) -> Result<(), SendError> {
+        if self.is_duplicate(&message) {
+            return self.write_log_error(&format!(
+                "duplicate message {} from '{}' is not delivered",
+                message.get_id(),
+                message.get_sender()
+            ));
+        }
+        if message.get_receiver().is_empty() {
+            self.write_log(&message)?;
+            self.history.push(message.clone());
+            self.stats.messages_sent += 1;
+            self.stats.broadcasts += 1;
+            let key_kind = match message.get_message_type() {
+                MessageType::PublicKey => Some("public key"),
+                MessageType::Identity => Some("identity"),
+                _ => None,
+            };
+            let public_key = match key_kind {
+                Some(key_kind) => match T::to_public_key(message.get_message()) {
+                    Ok(public_key) => Some(public_key),
+                    Err(error) => {
+                        self.write_log_error(&format!(
+                            "{} of '{}' is not cached: {}",
+                            key_kind,
+                            message.get_sender(),
+                            error
+                        ))?;
+                        None
+                    }
+                },
+                None => None,
+            };
+            let is_identity = matches!(message.get_message_type(), MessageType::Identity);
+            if let Some(public_key) = &public_key
+                && is_identity
+            {
+                self.identities
+                    .entry(message.get_sender().clone())
+                    .or_insert_with(|| public_key.clone());
+            }
+            for receiver in self.users.values_mut() {
+                if receiver.is_blocked(message.get_sender()) {
+                    continue;
+                }
+                if (self.self_delivery || receiver.get_name() != message.get_sender())
+                    && receiver.deliver_message(message.clone())
+                {
+                    *self
+                        .stats
+                        .received
+                        .entry(receiver.get_name().clone())
+                        .or_default() += 1;
+                }
+                if let Some(public_key) = &public_key
+                    && is_identity
+                {
+                    receiver
+                        .identity_cache
+                        .insert(message.get_sender().clone(), public_key.clone());
+                } else if let Some(public_key) = &public_key {
+                    receiver
+                        .public_key_cache
+                        .insert(message.get_sender().clone(), public_key.clone());
+                    receiver
+                        .session_key_cache
+                        .insert(message.get_sender().clone(), message.get_session_key());
+                }
+            }
+        } else {
+            self.write_log(&message)?;
+            self.history.push(message.clone());
+            self.stats.messages_sent += 1;
+            if self.users[message.get_receiver()].is_blocked(message.get_sender()) {
+                return Ok(());
+            }
+            *self
+                .stats
+                .received
+                .entry(message.get_receiver().clone())
+                .or_default() += 1;
+            let receiver: &mut User<T> = self.users.get_mut(message.get_receiver()).unwrap();
+            let reply = match message.get_message_type() {
+                MessageType::KeyExchange if receiver.is_online() => {
+                    receiver.complete_exchange(&message)
+                }
+                _ => Ok(None),
+            };
+            receiver.deliver_message(message);
+            match reply {
+                Ok(Some(reply)) => self.send_message(reply)?,
+                Ok(None) => {}
+                Err(error) => self.write_log_error(&error)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new key pair for the user and broadcasts the public key.
+    /// Messages encrypted with the old keys can still be read by the user.
+    /// Returns an error if the user is not found or the key could not be sent.
+    pub fn rotate_keys(&mut self, user_name: &str) -> Result<(), SendError> {
+        let key = self
+            .users
+            .get_mut(user_name)
+            .ok_or_else(|| SendError::SenderNotFound(String::from(user_name)))?
+            .create_keys();
+        if let Some((_, last_rotation)) = self.rotation.get_mut(user_name) {
+            *last_rotation = SystemTime::now();
+        }
+        self.send_message(key)
+    }
+
+    /// Sets the rotation interval of the keys of the user. When the user sends a message
+    /// and the interval has elapsed since the last rotation, the keys are rotated
+    /// automatically (see `Env::rotate_keys`). The interval starts when it is set.
+    /// `None` disables automatic rotation, which is the default.
+    ///
+    /// Returns an error if the user is not found.
+    pub fn set_rotation_interval(
+        &mut self,
+        user_name: &str,
+        interval: Option<Duration>,
+    ) -> Result<(), String> {
+        if !self.users.contains_key(user_name) {
+            return Err(format!("user '{}' not found", user_name));
+        }
+        match interval {
+            Some(interval) => {
+                self.rotation
+                    .insert(String::from(user_name), (interval, SystemTime::now()));
+            }
+            None => {
+                self.rotation.remove(user_name);
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate_if_due(&mut self, user_name: &str) -> Result<(), SendError> {
+        let due = self
+            .rotation
+            .get(user_name)
+            .is_some_and(|(interval, last_rotation)| {
+                last_rotation
+                    .elapsed()
+                    .is_ok_and(|elapsed| elapsed >= *interval)
+            });
+        if due {
+            self.rotate_keys(user_name)?;
+        }
+        Ok(())
+    }
+
+    /// Creates an empty named group. Returns an error if the group already exists.
+    pub fn create_group(&mut self, group_name: &str) -> Result<(), String> {
+        if self.groups.contains_key(group_name) {
+            return Err(format!("group '{}' already exists", group_name));
+        }
+        self.groups
+            .insert(String::from(group_name), BTreeSet::new());
+        Ok(())
+    }
+
+    /// Adds the user to the group. Joining a group twice has no effect.
+    /// Returns an error if the group or the user is not found.
+    pub fn join_group(&mut self, group_name: &str, user_name: &str) -> Result<(), String> {
+        if !self.users.contains_key(user_name) {
+            return Err(format!("user '{}' not found", user_name));
+        }
+        self.groups
+            .get_mut(group_name)
+            .ok_or_else(|| format!("group '{}' not found", group_name))?
+            .insert(String::from(user_name));
+        Ok(())
+    }
+
+    /// Removes the user from the group, so the user does not receive further messages
+    /// of the group. Returns an error if the group is not found or the user is not a member.
+    pub fn leave_group(&mut self, group_name: &str, user_name: &str) -> Result<(), String> {
+        let members = self
+            .groups
+            .get_mut(group_name)
+            .ok_or_else(|| format!("group '{}' not found", group_name))?;
+        if !members.remove(user_name) {
+            return Err(format!(
+                "user '{}' is not a member of group '{}'",
+                user_name, group_name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the members of the group (sorted by name),
+    /// or `None` if the group is not found.
+    pub fn group_members(&self, group_name: &str) -> Option<Vec<String>> {
+        self.groups
+            .get(group_name)
+            .map(|members| members.iter().cloned().collect())
+    }
+
+    /// Sends a message to the current members of the named group except its sender
+    /// using `send_to_recipients`.
+    ///
+    /// Returns an error if the group is not found, or if `send_to_recipients` fails
+    /// (e.g. the group has no other members).
+    pub fn send_to_group(&mut self, group_name: &str, message: Message) -> Result<(), String> {
+        let members: Vec<String> = self
+            .groups
+            .get(group_name)
+            .ok_or_else(|| format!("group '{}' not found", group_name))?
+            .iter()
+            .filter(|name| *name != message.get_sender())
+            .cloned()
+            .collect();
+        let recipients: Vec<&str> = members.iter().map(String::as_str).collect();
+        self.send_to_recipients(message, &recipients)
+    }
+
+    /// Sends a message to the given list of recipients. Outputs the message to the log.
+    /// Use `send_to_group` to send a message to the members of a named group.
+    ///
+    /// The same message is delivered to every recipient that has not blocked the sender.
+    /// The receiver of the delivered and logged message is replaced with the sorted list
+    /// of recipients separated by `", "`, so names of recipients must not contain `", "`.
+    ///
+    /// Sender verification, replay protection and automatic key rotation apply as in
+    /// `send_message`. The send hook (and the network model) and sequence numbers
+    /// do not: they are defined for messages to a single receiver, so the message
+    /// is always delivered at once and has no sequence number.
+    ///
+    /// Returns an error if the sender or one of the recipients is not found, the list
+    /// of recipients is empty or the name of a recipient contains `", "`.
+    /// In case of an error, the message is not delivered to anyone.
+    pub fn send_to_recipients(
+        &mut self,
+        message: Message,
+        recipients: &[&str],
+    ) -> Result<(), String> {
+        if !self.users.contains_key(message.get_sender()) {
+            return Err(String::from("sender not found"));
+        }
+        if recipients.is_empty() {
+            return Err(String::from("list of recipients should not be empty"));
+        }
+        if let Some(name) = recipients
+            .iter()
+            .find(|name| !self.users.contains_key(**name))
+        {
+            return Err(format!("receiver '{}' not found", name));
+        }
+        if let Some(name) = recipients.iter().find(|name| name.contains(", ")) {
+            return Err(format!("name '{}' should not contain \", \"", name));
+        }
+        self.check_sender(&message)
+            .map_err(|error| error.to_string())?;
+        self.check_replay(&message)
+            .map_err(|error| error.to_string())?;
+        self.rotate_if_due(message.get_sender())
+            .map_err(|error| error.to_string())?;
+        let recipients: BTreeSet<&str> = recipients.iter().copied().collect();
+        let mut message = message;
+        message.set_receiver(&recipients.iter().copied().collect::<Vec<&str>>().join(", "));
+        if message.get_digest().is_some() {
+            message.set_digest();
+        }
+        if self.is_duplicate(&message) {
+            return self
+                .write_log_error(&format!(
+                    "duplicate message {} from '{}' is not delivered",
+                    message.get_id(),
+                    message.get_sender()
+                ))
+                .map_err(|error| error.to_string());
+        }
+        self.write_log(&message)
+            .map_err(|error| error.to_string())?;
+        self.history.push(message.clone());
+        self.stats.messages_sent += 1;
+        for name in recipients {
+            if self
+                .users
+                .get_mut(name)
+                .unwrap()
+                .deliver_message(message.clone())
+            {
+                *self.stats.received.entry(String::from(name)).or_default() += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports the routing of a message without sending it.
+    ///
+    /// Neither the buffers of the users nor the log are changed.
+    pub fn dry_run_send(&self, message: &Message) -> SendPlan {
+        let mut receivers: Vec<String> = Vec::new();
+        let mut error: Option<String> = None;
+        if !self.users.contains_key(message.get_sender()) {
+            error = Some(String::from("sender not found"));
+        } else if message.get_receiver().is_empty() {
+            receivers = self.user_names();
+        } else if !self.users.contains_key(message.get_receiver()) {
+            error = Some(String::from("receiver not found"));
+        } else {
+            receivers.push(message.get_receiver().clone());
+        }
+
+        SendPlan { receivers, error }
+    }
+
+    /// Returns all sent messages between two users in either direction.
+    pub fn messages_between(&self, a: &str, b: &str) -> Vec<&Message> {
+        self.history
+            .iter()
+            .filter(|message| {
+                (message.get_sender() == a && message.get_receiver() == b)
+                    || (message.get_sender() == b && message.get_receiver() == a)
+            })
+            .collect()
+    }
+
+    /// Returns the timeline of key rotations observed in the environment.
+    ///
+    /// Each entry contains the time of the public key broadcast, the name of the user
+    /// and the session key. The entries are sorted by time.
+    pub fn key_rotation_timeline(&self) -> Vec<(SystemTime, String, usize)> {
+        let mut timeline: Vec<(SystemTime, String, usize)> = Vec::new();
+        for message in &self.history {
+            if let MessageType::PublicKey = message.get_message_type() {
+                timeline.push((
+                    message.get_timestamp(),
+                    message.get_sender().clone(),
+                    message.get_session_key(),
+                ));
+            }
+        }
+        timeline.sort_by_key(|entry| entry.0);
+        timeline
+    }
+}
+
+/// Snapshot of the state of an environment.
+///
+/// Contains copies of all users (with their keys, caches and message buffers),
+/// the history of sent messages, the nonces seen by replay protection,
+/// the counters of sequence numbers, the named groups, the registered identities,
+/// the rotation intervals and the ids of delivered messages.
+/// The log file and the settings of the environment are not included.
+///
+/// With the `serde` feature, snapshots can be serialized to save a simulation.
+/// Keys are serialized as strings, including the private keys, so the protocol
+/// has to support `EncryptionProtocol::private_key_to_string`.
+pub struct EnvSnapshot<T: EncryptionProtocol> {
+    users: BTreeMap<String, User<T>>,
+    history: Vec<Message>,
+    seen_nonces: HashMap<(String, u64), SystemTime>,
+    sequence_numbers: HashMap<(String, String), u64>,
+    groups: BTreeMap<String, BTreeSet<String>>,
+    identities: HashMap<String, T::PublicKey>,
+    rotation: HashMap<String, (Duration, SystemTime)>,
+    delivered_ids: HashSet<MessageId>,
+}
+
+impl<T: EncryptionProtocol> EnvSnapshot<T> {
+    /// Returns the names of the users in the snapshot in sorted order.
+    pub fn user_names(&self) -> Vec<String> {
+        self.users.keys().cloned().collect()
+    }
+}
+
+/// Serializable state of a snapshot. Seen nonces and sequence numbers are stored
+/// as lists, because their keys are not strings.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SnapshotState {
+    users: Vec<UserState>,
+    history: Vec<Message>,
+    seen_nonces: Vec<(String, u64, SystemTime)>,
+    sequence_numbers: Vec<(String, String, u64)>,
+    groups: BTreeMap<String, BTreeSet<String>>,
+    identities: HashMap<String, String>,
+    rotation: HashMap<String, (Duration, SystemTime)>,
+    delivered_ids: Vec<MessageId>,
+}
+
+#[cfg(feature = "serde")]
+impl<T: EncryptionProtocol> serde::Serialize for EnvSnapshot<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let users = self
+            .users
+            .values()
+            .map(User::to_state)
+            .collect::<Result<Vec<UserState>, String>>()
+            .map_err(serde::ser::Error::custom)?;
+        SnapshotState {
+            users,
+            history: self.history.clone(),
+            seen_nonces: self
+                .seen_nonces
+                .iter()
+                .map(|((sender, nonce), time)| (sender.clone(), *nonce, *time))
+                .collect(),
+            sequence_numbers: self
+                .sequence_numbers
+                .iter()
+                .map(|((sender, receiver), number)| (sender.clone(), receiver.clone(), *number))
+                .collect(),
+            groups: self.groups.clone(),
+            identities: self
+                .identities
+                .iter()
+                .map(|(name, key)| (name.clone(), T::to_string(key)))
+                .collect(),
+            rotation: self.rotation.clone(),
+            delivered_ids: self.delivered_ids.iter().copied().collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: EncryptionProtocol> serde::Deserialize<'de> for EnvSnapshot<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = SnapshotState::deserialize(deserializer)?;
+        let users = state
+            .users
+            .into_iter()
+            .map(|user| {
+                let user = User::from_state(user)?;
+                Ok((user.get_name().clone(), user))
+            })
+            .collect::<Result<BTreeMap<String, User<T>>, String>>()
+            .map_err(serde::de::Error::custom)?;
+        let identities = state
+            .identities
+            .into_iter()
+            .map(|(name, key)| Ok((name, T::to_public_key(&key)?)))
+            .collect::<Result<HashMap<String, T::PublicKey>, T::Error>>()
+            .map_err(serde::de::Error::custom)?;
+        Ok(Self {
+            users,
+            history: state.history,
+            seen_nonces: state
+                .seen_nonces
+                .into_iter()
+                .map(|(sender, nonce, time)| ((sender, nonce), time))
+                .collect(),
+            sequence_numbers: state
+                .sequence_numbers
+                .into_iter()
+                .map(|(sender, receiver, number)| ((sender, receiver), number))
+                .collect(),
+            groups: state.groups,
+            identities,
+            rotation: state.rotation,
+            delivered_ids: state.delivered_ids.into_iter().collect(),
+        })
+    }
+}
+
+impl<T: EncryptionProtocol> Default for Env<T> {
+    /// Creates a new environment that keeps the log in memory (see `Env::in_memory`),
+    /// so no file is created. Use `Env::new` for the log file `log.txt`.
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+/// Environment that can be shared between threads to simulate concurrent users.
+///
+/// The environment is kept behind a mutex, and every method locks it for the duration
+/// of the call, so messages are sent one at a time and log lines are never interleaved.
+/// Cloning returns a new handle to the same environment.
+pub struct SharedEnv<T: EncryptionProtocol> {
+    env: Arc<Mutex<Env<T>>>,
+}
+
+impl<T: EncryptionProtocol> SharedEnv<T> {
+    /// Wraps the environment so it can be shared between threads.
+    pub fn new(env: Env<T>) -> Self {
+        Self {
+            env: Arc::new(Mutex::new(env)),
+        }
+    }
+
+    /// Locks the environment. The environment stays locked until the guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, Env<T>> {
+        self.env.lock().expect("environment lock poisoned")
+    }
+
+    /// Creates an encrypted message from `sender` to `receiver`
+    /// (see `User::create_message`).
+    pub fn create_message(
+        &self,
+        sender: &str,
+        receiver: &str,
+        message: &str,
+    ) -> Result<Message, String> {
+        self.lock()
+            .get_user(sender)
+            .ok_or_else(|| format!("sender '{}' not found", sender))?
+            .create_message(receiver, message)
+    }
+
+    /// Sends the message (see `Env::send_message`).
+    pub fn send_message(&self, message: Message) -> Result<(), SendError> {
+        self.lock().send_message(message)
+    }
+}
+
+impl<T: EncryptionProtocol> Clone for SharedEnv<T> {
+    fn clone(&self) -> Self {
         Self {
-            users: HashMap::new(),
-            log: fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open("log.txt")
-                .expect("failed to open file"),
+            env: Arc::clone(&self.env),
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::{
+        Env, HookDecision, LogFormat, LogLevel, MergeError, MergePolicy, NetworkModel, SendError,
+        SharedEnv, parse_log,
+    };
+    use crate::message::{Message, MessageBuilder, MessageId, MessageType};
+    use crate::rsa::RSA;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::fs;
+    use std::io::Read;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_new() {
+        let _env: Env<RSA> = Env::new();
+        assert!(fs::exists("log.txt").unwrap());
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message.clone()).unwrap();
+
+        let lines = env.log_lines().unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "protocol: 'RSA'; key size: '128'");
+        assert_eq!(lines[2], message.to_string());
+        assert_eq!(env.read_log().unwrap()[1].get_id(), message.get_id());
+    }
+
+    #[test]
+    fn test_without_log() {
+        let mut env: Env<RSA> = Env::without_log();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message).unwrap();
+
+        let received = env.get_user("Bob").unwrap().read_last_message().unwrap();
+        assert_eq!(received.get_message(), "Hello, Bob!");
+        assert!(env.log_lines().is_none());
+        assert_eq!(env.read_log().err(), Some(String::from("log is disabled")));
+    }
+
+    #[test]
+    fn test_default() {
+        let mut env: Env<RSA> = Env::default();
+        env.create_user("Alice");
+        let key = env.get_mut_user("Alice").unwrap().create_keys();
+        env.send_message(key).unwrap();
+
+        assert!(env.find_user("Alice"));
+        let log = env.log_lines().unwrap();
+        assert!(log.last().unwrap().contains("message type: 'Public key'"));
+    }
+
+    #[test]
+    fn test_rotate_keys() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.rotate_keys("Bob").unwrap();
+        let old_fingerprint = env.get_user("Alice").unwrap().contact_fingerprint("Bob");
+        assert!(old_fingerprint.is_some());
+
+        env.rotate_keys("Bob").unwrap();
+        let bob = env.get_user("Bob").unwrap();
+        let new_fingerprint = RSA::fingerprint(bob.get_public_key().unwrap());
+        assert_ne!(old_fingerprint, Some(new_fingerprint.clone()));
+        assert_eq!(
+            env.get_user("Alice").unwrap().contact_fingerprint("Bob"),
+            Some(new_fingerprint)
+        );
+        assert_eq!(
+            env.rotate_keys("Carol"),
+            Err(SendError::SenderNotFound(String::from("Carol")))
+        );
+    }
+
+    #[test]
+    fn test_rotation_interval() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.rotate_keys("Alice").unwrap();
+        env.rotate_keys("Bob").unwrap();
+        env.set_rotation_interval("Bob", Some(Duration::ZERO))
+            .unwrap();
+        let old_fingerprint = env.get_user("Alice").unwrap().contact_fingerprint("Bob");
+
+        let message = env
+            .get_user("Bob")
+            .unwrap()
+            .create_message("Alice", "Hello, Alice!")
+            .unwrap();
+        env.send_message(message).unwrap();
+        let alice = env.get_user("Alice").unwrap();
+        assert_eq!(
+            alice.read_last_message().unwrap().get_message(),
+            "Hello, Alice!"
+        );
+        let rotated_fingerprint = alice.contact_fingerprint("Bob");
+        assert_ne!(rotated_fingerprint, old_fingerprint);
+
+        env.set_rotation_interval("Bob", Some(Duration::from_secs(3600)))
+            .unwrap();
+        let message = env
+            .get_user("Bob")
+            .unwrap()
+            .create_message("Alice", "Hello again!")
+            .unwrap();
+        env.send_message(message).unwrap();
+        assert_eq!(
+            env.get_user("Alice").unwrap().contact_fingerprint("Bob"),
+            rotated_fingerprint
+        );
+        assert!(env.set_rotation_interval("Carol", None).is_err());
+    }
+
+    #[test]
+    fn test_log_entries_between() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for i in 0..5 {
+            let mut message = env
+                .get_user("Alice")
+                .unwrap()
+                .create_message("Bob", &format!("Message {}", i))
+                .unwrap();
+            message.set_timestamp(start + Duration::from_secs(10 * i));
+            env.send_message(message).unwrap();
+        }
+
+        let entries = env
+            .log_entries_between(
+                start + Duration::from_secs(10),
+                start + Duration::from_secs(30),
+            )
+            .unwrap();
+        let timestamps: Vec<SystemTime> = entries
+            .iter()
+            .map(|message| message.get_timestamp())
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![
+                start + Duration::from_secs(10),
+                start + Duration::from_secs(20),
+                start + Duration::from_secs(30)
+            ]
+        );
+        assert!(
+            entries
+                .iter()
+                .all(|message| message.get_sender() == "Alice")
+        );
+
+        let single = env.log_entries_between(start, start).unwrap();
+        assert_eq!(single.len(), 1);
+        assert!(
+            env.log_entries_between(
+                start + Duration::from_secs(1),
+                start + Duration::from_secs(9)
+            )
+            .unwrap()
+            .is_empty()
+        );
+        assert!(
+            env.log_entries_between(start + Duration::from_secs(30), start)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_duplicate_detection() {
+        let mut env: Env<RSA> = Env::in_memory().with_duplicate_detection(true);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key.clone()).unwrap();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message.clone()).unwrap();
+        env.send_message(message.clone()).unwrap();
+
+        assert_eq!(env.get_user("Alice").unwrap().read_all_messages().len(), 1);
+        let bob = env.get_user("Bob").unwrap();
+        assert_eq!(bob.read_all_messages().len(), 1);
+        assert_eq!(
+            bob.read_last_message().unwrap().get_message(),
+            "Hello, Bob!"
+        );
+        assert_eq!(env.stats().messages_sent, 2);
+        let log = env.log_lines().unwrap();
+        assert_eq!(
+            log.last().unwrap(),
+            &format!(
+                "error: duplicate message {} from 'Alice' is not delivered",
+                message.get_id()
+            )
+        );
+
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message.clone()).unwrap();
+        env.send_message(message).unwrap();
+        assert_eq!(env.get_user("Bob").unwrap().read_all_messages().len(), 2);
+    }
+
+    #[test]
+    fn test_duplicate_detection_restored_messages() {
+        let mut env: Env<RSA> = Env::in_memory().with_duplicate_detection(true);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let restored = Message::from_wire(
+            &Message::new("Alice", 0, "Bob", "Hello!", MessageType::Message).to_wire(),
+        )
+        .unwrap();
+        env.send_message(restored).unwrap();
+        let message = Message::new("Alice", 0, "Bob", "Hello again!", MessageType::Message);
+        env.send_message(message).unwrap();
+        assert_eq!(env.get_user("Bob").unwrap().message_buffer.len(), 2);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.rotate_keys("Bob").unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message).unwrap();
+
+        let mut other: Env<RSA> = Env::in_memory();
+        other.create_user("Carol");
+        other.create_user("Dave");
+        other.rotate_keys("Dave").unwrap();
+        let message = other
+            .get_user("Carol")
+            .unwrap()
+            .create_message("Dave", "Hello, Dave!")
+            .unwrap();
+        other.send_message(message).unwrap();
+        other.create_group("friends").unwrap();
+        other.join_group("friends", "Carol").unwrap();
 
-    /// Creates a new environment from file. Outputs the log to the specified file.
-    pub fn from_file(file_name: &str) -> Self {
-        Self {
-            users: HashMap::new(),
-            log: fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(file_name)
-                .expect("failed to open file"),
-        }
+        env.merge(other).unwrap();
+        assert_eq!(env.user_names(), vec!["Alice", "Bob", "Carol", "Dave"]);
+        let dave = env.get_user("Dave").unwrap();
+        assert_eq!(dave.get_name(), "Dave");
+        assert_eq!(
+            dave.read_last_message().unwrap().get_message(),
+            "Hello, Dave!"
+        );
+        assert!(env.get_user("Carol").unwrap().has_key_for("Dave"));
+        assert_eq!(env.group_members("friends").unwrap(), vec!["Carol"]);
+        assert_eq!(
+            env.get_user("Bob")
+                .unwrap()
+                .read_last_message()
+                .unwrap()
+                .get_message(),
+            "Hello, Bob!"
+        );
+
+        env.rotate_keys("Alice").unwrap();
+        assert!(env.get_user("Dave").unwrap().has_key_for("Alice"));
+        let message = env
+            .get_user("Dave")
+            .unwrap()
+            .create_message("Alice", "Hello, Alice!")
+            .unwrap();
+        env.send_message(message).unwrap();
+        assert_eq!(
+            env.get_user("Alice")
+                .unwrap()
+                .read_last_message()
+                .unwrap()
+                .get_message(),
+            "Hello, Alice!"
+        );
     }
 
-    /// Creates new user. Note that all users in the environment must have unique names.
-    pub fn create_user(&mut self, user_name: &str) {
-        if user_name.is_empty() {
-            panic!("name should not be empty");
-        }
-        match self.users.get(&String::from(user_name)) {
-            Some(_) => panic!("this name is already taken!"),
-            None => self
-                .users
-                .insert(String::from(user_name), User::<T>::new(user_name)),
-        };
+    #[test]
+    fn test_merge_name_collision() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let mut other: Env<RSA> = Env::in_memory();
+        other.create_user("Bob");
+        other.create_user("Alice");
+        other.create_user("Carol");
+
+        assert_eq!(
+            env.merge(other),
+            Err(MergeError::NameCollision(vec![
+                String::from("Alice"),
+                String::from("Bob")
+            ]))
+        );
+        assert_eq!(env.user_names(), vec!["Alice", "Bob"]);
+
+        let mut other: Env<RSA> = Env::in_memory();
+        other.create_user("Bob");
+        other.create_user("Carol");
+        env.merge_with_policy(other, MergePolicy::Rename(String::from("'")))
+            .unwrap();
+        assert_eq!(env.user_names(), vec!["Alice", "Bob", "Bob'", "Carol"]);
+        assert_eq!(env.get_user("Bob'").unwrap().get_name(), "Bob'");
+
+        let mut other: Env<RSA> = Env::in_memory();
+        other.create_user("Bob");
+        assert_eq!(
+            env.merge_with_policy(other, MergePolicy::Rename(String::new())),
+            Err(MergeError::EmptySuffix)
+        );
     }
 
-    /// Returns a reference to user by name.
-    pub fn get_user(&self, user_name: &str) -> Option<&User<T>> {
-        self.users.get(&String::from(user_name))
+    #[test]
+    fn test_clear_log() {
+        let file_name = "clear_log_test.txt";
+        let _ = fs::remove_file(file_name);
+        let mut env: Env<RSA> = Env::from_file(file_name);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        assert_eq!(fs::read_to_string(file_name).unwrap().lines().count(), 2);
+
+        env.clear_log().unwrap();
+        assert_eq!(fs::read_to_string(file_name).unwrap(), "");
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message.clone()).unwrap();
+        let contents = fs::read_to_string(file_name).unwrap();
+        fs::remove_file(file_name).unwrap();
+        assert_eq!(contents, message.to_string() + "\n");
+
+        let mut env: Env<RSA> = Env::in_memory();
+        env.clear_log().unwrap();
+        assert!(env.log_lines().unwrap().is_empty());
     }
 
-    /// Returns a mutable reference to user by name.
-    pub fn get_mut_user(&mut self, user_name: &str) -> Option<&mut User<T>> {
-        self.users.get_mut(&String::from(user_name))
+    #[test]
+    fn test_log_level() {
+        let mut env: Env<RSA> = Env::in_memory();
+        assert_eq!(env.get_log_level(), LogLevel::Verbose);
+        env.set_log_level(LogLevel::Messages);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message.clone()).unwrap();
+        assert_eq!(env.log_lines().unwrap()[1..], [message.to_string()]);
+
+        env.set_log_level(LogLevel::Errors);
+        env.send_message(Message::new(
+            "Alice",
+            1,
+            "",
+            "not a key",
+            MessageType::PublicKey,
+        ))
+        .unwrap();
+        env.send_message(message.clone()).unwrap();
+        let lines = env.log_lines().unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("error: public key of 'Alice' is not cached"));
+
+        env.set_log_level(LogLevel::Off);
+        env.send_message(Message::new(
+            "Alice",
+            1,
+            "",
+            "not a key",
+            MessageType::PublicKey,
+        ))
+        .unwrap();
+        assert_eq!(env.log_lines().unwrap().len(), 3);
     }
 
-    /// Checks whether a given user is present in the environment.
-    pub fn find_user(&self, user_name: &str) -> bool {
-        self.users.contains_key(&String::from(user_name))
+    #[test]
+    fn test_redact_ciphertext() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.set_redact_ciphertext(true);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key.clone()).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message.clone()).unwrap();
+
+        let lines = env.log_lines().unwrap();
+        assert!(lines[1].contains(key.get_message()));
+        assert!(!lines[2].contains(message.get_message()));
+        assert!(lines[2].contains(&format!(
+            "message text: '<redacted len={}>'; session key: '1'",
+            message.get_message().len()
+        )));
+        assert_eq!(env.read_log().unwrap()[1].get_sender(), "Alice");
+        let received = env.get_user("Bob").unwrap().read_last_message().unwrap();
+        assert_eq!(received.get_message(), "Hello, Bob!");
     }
 
-    /// Sends an encrypted message between users. Outputs the message to the log.
-    pub fn send_message(&mut self, message: Message) {
-        if !self.users.contains_key(message.get_sender()) {
-            panic!("sender not found");
-        } else if message.get_receiver().is_empty() {
-            let _ = writeln!(self.log, "{}", message.clone());
-            for receiver in self.users.values_mut() {
-                receiver.message_buffer.push(message.clone());
-                if let MessageType::PublicKey = message.get_message_type() {
-                    receiver.public_key_cache.insert(
-                        message.get_sender().clone(),
-                        T::to_public_key(message.get_message()),
-                    );
-                    receiver
-                        .session_key_cache
-                        .insert(message.get_sender().clone(), message.get_session_key());
-                }
-            }
-        } else if !self.users.contains_key(message.get_receiver()) {
-            panic!("receiver not found");
-        } else {
-            let _ = writeln!(self.log, "{}", message.clone());
-            let receiver: &mut User<T> = self.users.get_mut(message.get_receiver()).unwrap();
-            receiver.message_buffer.push(message);
+    #[test]
+    fn test_stats() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.create_user("Carol");
+        let header_bytes = env.log_lines().unwrap()[0].len() + 1;
+        assert_eq!(env.stats().bytes_logged, header_bytes);
+
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        for text in ["1", "2", "3"] {
+            let message = env
+                .get_user("Alice")
+                .unwrap()
+                .create_message("Bob", text)
+                .unwrap();
+            env.send_message(message).unwrap();
         }
-    }
-}
+        let message = env
+            .get_user("Carol")
+            .unwrap()
+            .create_message("Bob", "4")
+            .unwrap();
+        env.send_message(message).unwrap();
+        let missing = Message::new("Dave", 1, "Bob", "5", MessageType::Message);
+        env.send_message(missing).unwrap_err();
 
-impl<T: EncryptionProtocol> Default for Env<T> {
-    fn default() -> Self {
-        Self::new()
+        let stats = env.stats();
+        assert_eq!(stats.messages_sent, 5);
+        assert_eq!(stats.broadcasts, 1);
+        assert_eq!(
+            stats.bytes_logged,
+            env.log_lines()
+                .unwrap()
+                .iter()
+                .map(|line| line.len() + 1)
+                .sum::<usize>()
+        );
+        assert_eq!(stats.received["Alice"], 1);
+        assert_eq!(stats.received["Bob"], 4);
+        assert_eq!(stats.received["Carol"], 1);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::env::Env;
-    use crate::message::{Message, MessageType};
-    use crate::rsa::RSA;
-    use std::fs;
-    use std::io::Read;
+    #[test]
+    fn test_send_hook() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.create_user("Carol");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        env.set_send_hook(Box::new(|message: &Message| {
+            if message.get_receiver() == "Bob" {
+                HookDecision::Drop
+            } else {
+                HookDecision::Deliver
+            }
+        }));
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message).unwrap();
+        assert!(env.get_user("Bob").unwrap().message_buffer.is_empty());
+        assert_eq!(env.log_lines().unwrap().len(), 2);
+
+        env.set_send_hook(Box::new(|_: &Message| HookDecision::Delay(Duration::ZERO)));
+        let key = env.get_mut_user("Carol").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        assert_eq!(env.get_user("Alice").unwrap().message_buffer.len(), 1);
+        assert!(env.get_user("Bob").unwrap().message_buffer.is_empty());
+        env.deliver_delayed().unwrap();
+        assert_eq!(env.get_user("Alice").unwrap().message_buffer.len(), 2);
+        assert_eq!(env.get_user("Bob").unwrap().message_buffer.len(), 1);
+
+        env.set_send_hook(Box::new(|_: &Message| HookDecision::Delay(Duration::MAX)));
+        let key = env.get_mut_user("Carol").unwrap().create_keys();
+        assert_eq!(
+            env.send_message(key),
+            Err(SendError::InvalidDelay(Duration::MAX))
+        );
+        env.deliver_delayed().unwrap();
+        assert_eq!(env.get_user("Alice").unwrap().message_buffer.len(), 2);
+    }
 
     #[test]
-    fn test_new() {
-        let _env: Env<RSA> = Env::new();
-        assert!(fs::exists("log.txt").unwrap());
+    fn test_network_model() {
+        assert!(NetworkModel::new(1.5, Duration::ZERO, Duration::ZERO).is_err());
+        assert!(NetworkModel::new(0.5, Duration::from_secs(2), Duration::from_secs(1)).is_err());
+
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let (min_latency, max_latency) = (Duration::from_millis(10), Duration::from_secs(1));
+        let model = NetworkModel::new(0.3, min_latency, max_latency)
+            .unwrap()
+            .with_seed(17);
+        let mut env = env.with_network(model);
+
+        let mut rng = StdRng::seed_from_u64(17);
+        let mut expected: Vec<String> = Vec::new();
+        let start = SystemTime::now();
+        for i in 0..20 {
+            if !rng.gen_bool(0.3) {
+                rng.gen_range(min_latency..=max_latency);
+                expected.push(i.to_string());
+            }
+            let message = env
+                .get_user("Alice")
+                .unwrap()
+                .create_message("Bob", &i.to_string())
+                .unwrap();
+            env.send_message(message).unwrap();
+        }
+        assert!(expected.len() < 20);
+        env.tick(start).unwrap();
+        assert!(env.get_user("Bob").unwrap().message_buffer.is_empty());
+
+        env.tick(start + Duration::from_secs(60)).unwrap();
+        let mut received: Vec<String> = env
+            .get_user("Bob")
+            .unwrap()
+            .read_all_messages()
+            .iter()
+            .map(|message| message.get_message().clone())
+            .collect();
+        received.sort();
+        expected.sort();
+        assert_eq!(received, expected);
     }
 
     #[test]
@@ -148,13 +2058,13 @@ mod tests {
     #[test]
     #[should_panic(expected = "name should not be empty")]
     fn test_create_empty_user() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("");
     }
 
     #[test]
     fn test_create_user() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("Alice");
         assert!(env.find_user("Alice"));
     }
@@ -162,14 +2072,14 @@ mod tests {
     #[test]
     #[should_panic(expected = "this name is already taken!")]
     fn test_create_duplicate_user() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("Alice");
         env.create_user("Alice");
     }
 
     #[test]
     fn test_find_user() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("Alice");
         env.create_user("Bob");
         assert!(env.find_user("Alice"));
@@ -177,43 +2087,91 @@ mod tests {
         assert!(!env.find_user("Bobb"));
     }
 
+    #[test]
+    fn test_user_names_order() {
+        let names = ["Carol", "Alice", "Dave", "Bob"];
+        let mut orders: Vec<Vec<String>> = Vec::new();
+        for _i in 0..5 {
+            let mut env: Env<RSA> = Env::in_memory();
+            for name in names {
+                env.create_user(name);
+            }
+            orders.push(env.user_names());
+        }
+        assert_eq!(orders[0], vec!["Alice", "Bob", "Carol", "Dave"]);
+        for order in &orders {
+            assert_eq!(order, &orders[0]);
+        }
+    }
+
+    #[test]
+    fn test_list_users() {
+        let mut env: Env<RSA> = Env::in_memory();
+        assert_eq!(env.user_count(), 0);
+        env.create_user("Carol");
+        env.create_user("Alice");
+        env.create_user("Bob");
+        assert_eq!(env.user_count(), 3);
+        assert_eq!(env.user_names(), vec!["Alice", "Bob", "Carol"]);
+        let names: Vec<&String> = env.users().map(|user| user.get_name()).collect();
+        assert_eq!(names, vec!["Alice", "Bob", "Carol"]);
+    }
+
     #[test]
     fn test_get_existing_user() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("Alice");
         assert!(env.get_user("Alice").is_some());
     }
 
     #[test]
     fn test_get_nonexisting_user() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("Alice");
         assert!(env.get_user("Bob").is_none());
     }
 
     #[test]
     fn test_get_mut_user() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("Alice");
         assert!(env.get_mut_user("Alice").is_some());
     }
 
     #[test]
-    #[should_panic(expected = "sender not found")]
     fn test_nonexisting_sender() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
         env.create_user("Bob");
         let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
-        env.send_message(message);
+        assert_eq!(
+            env.send_message(message),
+            Err(SendError::SenderNotFound(String::from("Alice")))
+        );
     }
 
     #[test]
-    #[should_panic(expected = "receiver not found")]
     fn test_nonexisting_receiver() {
-        let mut env: Env<RSA> = Env::new();
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        assert_eq!(
+            env.send_message(message),
+            Err(SendError::ReceiverNotFound(String::from("Bob")))
+        );
+        assert!(env.history.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_log_write_failed() {
+        let mut env: Env<RSA> = Env::from_file("/dev/full");
         env.create_user("Alice");
+        env.create_user("Bob");
         let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
-        env.send_message(message);
+        assert!(matches!(
+            env.send_message(message),
+            Err(SendError::LogWriteFailed(_))
+        ));
     }
 
     #[test]
@@ -222,10 +2180,715 @@ mod tests {
         env.create_user("Alice");
         env.create_user("Bob");
         let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
-        env.send_message(message);
+        env.send_message(message).unwrap();
         let mut file = fs::File::open("my_crazy_log777.txt").expect("failed to open file");
         let mut log_message = String::new();
         let _ = file.read_to_string(&mut log_message);
         assert!(log_message.contains("sender: 'Alice'; receiver: 'Bob'; message type: 'Message'; message text: 'Hello, Bob!'; session key: '1'; timestamp: '"));
     }
+
+    #[test]
+    fn test_dry_run_send() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.create_user("Carol");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+
+        let plan = env.dry_run_send(&key);
+        assert_eq!(plan.receivers, vec!["Alice", "Bob", "Carol"]);
+        assert!(plan.error.is_none());
+        assert!(env.get_user("Alice").unwrap().message_buffer.is_empty());
+
+        let message = Message::new("Alice", 1, "Dave", "Hello, Dave!", MessageType::Message);
+        let plan = env.dry_run_send(&message);
+        assert!(plan.receivers.is_empty());
+        assert_eq!(plan.error, Some(String::from("receiver not found")));
+        assert!(env.history.is_empty());
+    }
+
+    #[test]
+    fn test_messages_between() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.create_user("Carol");
+        let messages = [
+            Message::new("Alice", 1, "Bob", "1", MessageType::Message),
+            Message::new("Bob", 1, "Alice", "2", MessageType::Message),
+            Message::new("Carol", 1, "Bob", "3", MessageType::Message),
+            Message::new("Alice", 1, "Bob", "4", MessageType::Message),
+        ];
+        for message in messages {
+            env.send_message(message).unwrap();
+        }
+
+        let found: Vec<&String> = env
+            .messages_between("Bob", "Alice")
+            .iter()
+            .map(|message| message.get_message())
+            .collect();
+        assert_eq!(found, vec!["1", "2", "4"]);
+    }
+
+    #[test]
+    fn test_key_rotation_timeline() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Alice").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let key = env.get_mut_user("Alice").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Bob")
+            .unwrap()
+            .create_message("Alice", "Hello, Alice!")
+            .unwrap();
+        env.send_message(message).unwrap();
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+
+        let timeline = env.key_rotation_timeline();
+        assert_eq!(timeline.len(), 4);
+        assert_eq!((timeline[0].1.as_str(), timeline[0].2), ("Alice", 1));
+        assert_eq!((timeline[1].1.as_str(), timeline[1].2), ("Bob", 1));
+        assert_eq!((timeline[2].1.as_str(), timeline[2].2), ("Alice", 2));
+        assert_eq!((timeline[3].1.as_str(), timeline[3].2), ("Bob", 2));
+        for i in 1..timeline.len() {
+            assert!(timeline[i - 1].0 <= timeline[i].0);
+        }
+    }
+
+    #[test]
+    fn test_send_corrupted_public_key() {
+        let mut env: Env<RSA> = Env::from_file("my_log.txt");
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let message = Message::new("Alice", 1, "", "not a key", MessageType::PublicKey);
+        env.send_message(message).unwrap();
+
+        let bob = env.get_user("Bob").unwrap();
+        assert!(bob.public_key_cache.is_empty());
+        assert!(bob.session_key_cache.is_empty());
+        assert_eq!(bob.message_buffer.len(), 1);
+    }
+
+    #[test]
+    fn test_json_log() {
+        let file_name = "json_log_test.txt";
+        let _ = fs::remove_file(file_name);
+        let mut env: Env<RSA> = Env::from_file_with_format(file_name, LogFormat::Json);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let message = Message::new("Alice", 2, "Bob", "12 \"34\"", MessageType::Message);
+        env.send_message(message.clone()).unwrap();
+
+        let mut contents = String::new();
+        fs::File::open(file_name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        fs::remove_file(file_name).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let header: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(header["protocol"], "RSA");
+        assert_eq!(header["key_size_bits"], 128);
+        let json: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(json["id"], message.get_id().to_string());
+        assert_eq!(json["sender"], "Alice");
+        assert_eq!(json["receiver"], "Bob");
+        assert_eq!(json["message_type"], "Message");
+        assert_eq!(json["session_key"], 2);
+        assert_eq!(json["message"], "12 \"34\"");
+        assert_eq!(json["signature"], serde_json::Value::Null);
+        let nanos = message
+            .get_timestamp()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        assert_eq!(json["timestamp"].as_u64().map(u128::from), Some(nanos));
+    }
+
+    #[test]
+    fn test_read_log() {
+        let file_name = "read_log_test.txt";
+        let _ = fs::remove_file(file_name);
+        let mut env: Env<RSA> = Env::from_file(file_name);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Alice").unwrap().create_keys();
+        env.send_message(key.clone()).unwrap();
+        let message = env
+            .get_user("Bob")
+            .unwrap()
+            .create_message("Alice", "Hello, Alice!")
+            .unwrap();
+        env.send_message(message.clone()).unwrap();
+        let corrupted = Message::new("Bob", 1, "", "not a key", MessageType::PublicKey);
+        env.send_message(corrupted).unwrap();
+
+        let messages = env.read_log().unwrap();
+        assert_eq!(parse_log(file_name).unwrap().len(), 3);
+        let contents = fs::read_to_string(file_name).unwrap();
+        assert_eq!(
+            contents.lines().next(),
+            Some("protocol: 'RSA'; key size: '128'")
+        );
+        fs::remove_file(file_name).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].get_sender(), "Alice");
+        assert!(matches!(
+            messages[0].get_message_type(),
+            MessageType::PublicKey
+        ));
+        assert_eq!(messages[0].get_message(), key.get_message());
+        assert_eq!(messages[1].get_sender(), "Bob");
+        assert_eq!(messages[1].get_receiver(), "Alice");
+        assert!(matches!(
+            messages[1].get_message_type(),
+            MessageType::Message
+        ));
+        assert_eq!(messages[1].get_message(), message.get_message());
+        assert_eq!(messages[1].get_timestamp(), message.get_timestamp());
+        assert_eq!(messages[2].get_sender(), "Bob");
+        assert!(matches!(
+            messages[2].get_message_type(),
+            MessageType::PublicKey
+        ));
+    }
+
+    #[test]
+    fn test_shared_env() {
+        let file_name = "shared_env_test.txt";
+        let _ = fs::remove_file(file_name);
+        let mut env: Env<RSA> = Env::from_file(file_name);
+        env.create_user("Bob");
+        for i in 0..4 {
+            env.create_user(&format!("User{}", i));
+        }
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let shared = SharedEnv::new(env);
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    let sender = format!("User{}", i);
+                    for j in 0..10 {
+                        let message = shared
+                            .create_message(&sender, "Bob", &format!("Message {} {}", i, j))
+                            .unwrap();
+                        shared.send_message(message).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let messages = parse_log(file_name).unwrap();
+        fs::remove_file(file_name).unwrap();
+        assert_eq!(messages.len(), 41);
+        assert!(
+            messages[1..]
+                .iter()
+                .all(|message| message.get_receiver() == "Bob")
+        );
+        let env = shared.lock();
+        let mut texts: Vec<String> = env
+            .get_user("Bob")
+            .unwrap()
+            .read_all_messages()
+            .iter()
+            .map(|message| message.get_message().clone())
+            .collect();
+        texts.sort();
+        let mut expected: Vec<String> = (0..4)
+            .flat_map(|i| (0..10).map(move |j| format!("Message {} {}", i, j)))
+            .collect();
+        expected.sort();
+        assert_eq!(texts, expected);
+        drop(env);
+        assert!(shared.create_message("Carol", "Bob", "Hi").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_snapshot_serde_round_trip() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let identity = env.get_mut_user("Alice").unwrap().create_identity();
+        env.send_message(identity).unwrap();
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message).unwrap();
+
+        let json = serde_json::to_string(&env.snapshot()).unwrap();
+        let mut restored: Env<RSA> = Env::in_memory();
+        restored.restore(serde_json::from_str(&json).unwrap());
+        assert_eq!(restored.user_names(), vec!["Alice", "Bob"]);
+        assert_eq!(
+            restored
+                .get_user("Bob")
+                .unwrap()
+                .read_last_message()
+                .unwrap()
+                .get_message(),
+            "Hello, Bob!"
+        );
+
+        let reply = restored
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Still here")
+            .unwrap();
+        assert_eq!(restored.send_message(reply), Ok(()));
+        assert_eq!(
+            restored
+                .get_user("Bob")
+                .unwrap()
+                .read_last_message()
+                .unwrap()
+                .get_message(),
+            "Still here"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = env
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Hello, Bob!")
+            .unwrap();
+        env.send_message(message).unwrap();
+
+        let snapshot = env.snapshot();
+        assert_eq!(snapshot.user_names(), vec!["Alice", "Bob"]);
+        let new_key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(new_key).unwrap();
+        env.remove_user("Alice");
+
+        let mut restored: Env<RSA> = Env::in_memory();
+        restored.create_user("Carol");
+        restored.restore(snapshot);
+        assert_eq!(restored.user_names(), vec!["Alice", "Bob"]);
+        let bob = restored.get_user("Bob").unwrap();
+        assert_eq!(
+            bob.read_last_message().unwrap().get_message(),
+            "Hello, Bob!"
+        );
+        assert_eq!(restored.messages_between("Alice", "Bob").len(), 1);
+
+        let reply = restored
+            .get_user("Alice")
+            .unwrap()
+            .create_message("Bob", "Still here")
+            .unwrap();
+        restored.send_message(reply).unwrap();
+        assert_eq!(
+            restored
+                .get_user("Bob")
+                .unwrap()
+                .read_last_message()
+                .unwrap()
+                .get_message(),
+            "Still here"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_registries() {
+        let mut env: Env<RSA> = Env::in_memory()
+            .with_sender_verification(true)
+            .with_duplicate_detection(true);
+        for name in ["Alice", "Mallory"] {
+            env.create_user(name);
+            let identity = env.get_mut_user(name).unwrap().create_identity();
+            env.send_message(identity).unwrap();
+        }
+        env.create_group("friends").unwrap();
+        env.join_group("friends", "Alice").unwrap();
+        env.set_rotation_interval("Alice", Some(Duration::from_secs(3600)))
+            .unwrap();
+        let forged_identity = env.get_mut_user("Mallory").unwrap().create_identity();
+        let forged_identity = MessageBuilder::new()
+            .sender("Alice")
+            .text(forged_identity.get_message())
+            .message_type(MessageType::Identity)
+            .build()
+            .unwrap();
+
+        let check = |mut restored: Env<RSA>| {
+            assert_eq!(
+                restored.group_members("friends"),
+                Some(vec![String::from("Alice")])
+            );
+            assert_eq!(restored.rotation, env.rotation);
+            assert_eq!(restored.delivered_ids, env.delivered_ids);
+            assert_eq!(
+                restored.send_message(forged_identity.clone()),
+                Err(SendError::SenderNotVerified(String::from("Alice")))
+            );
+        };
+        let mut restored: Env<RSA> = Env::in_memory().with_sender_verification(true);
+        restored.restore(env.snapshot());
+        check(restored);
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&env.snapshot()).unwrap();
+            let mut restored: Env<RSA> = Env::in_memory().with_sender_verification(true);
+            restored.restore(serde_json::from_str(&json).unwrap());
+            check(restored);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_log() {
+        let file_name = "invalid_log_test.txt";
+        fs::write(file_name, "sender: 'Alice'; receiver: 'Bob'\n").unwrap();
+        let result = parse_log(file_name);
+        fs::remove_file(file_name).unwrap();
+        assert!(result.is_err());
+        assert!(parse_log("missing_log_test.txt").is_err());
+    }
+
+    #[test]
+    fn test_remove_user() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        assert!(env.remove_user("Alice"));
+        assert!(!env.find_user("Alice"));
+        assert!(!env.remove_user("Alice"));
+        assert!(!env.remove_user("Carol"));
+        assert_eq!(env.user_names(), vec!["Bob"]);
+    }
+
+    #[test]
+    fn test_rename_user() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        assert_eq!(env.rename_user("Alice", "Carol"), Ok(()));
+        assert!(!env.find_user("Alice"));
+        assert_eq!(env.get_user("Carol").unwrap().get_name(), "Carol");
+
+        assert_eq!(
+            env.rename_user("Carol", "Bob"),
+            Err(String::from("this name is already taken!"))
+        );
+        assert_eq!(
+            env.rename_user("Carol", ""),
+            Err(String::from("name should not be empty"))
+        );
+        assert_eq!(
+            env.rename_user("Alice", "Dave"),
+            Err(String::from("user not found"))
+        );
+        assert_eq!(env.user_names(), vec!["Bob", "Carol"]);
+    }
+
+    #[test]
+    fn test_send_to_recipients() {
+        let file_name = "group_log_test.txt";
+        let _ = fs::remove_file(file_name);
+        let mut env: Env<RSA> = Env::from_file(file_name);
+        for name in ["Alice", "Bob", "Carol", "Dave"] {
+            env.create_user(name);
+        }
+        let message = Message::new("Alice", 1, "Bob", "Hello!", MessageType::Message);
+        assert_eq!(
+            env.send_to_recipients(message, &["Dave", "Bob", "Dave"]),
+            Ok(())
+        );
+
+        let log = fs::read_to_string(file_name).unwrap();
+        fs::remove_file(file_name).unwrap();
+        assert!(log.contains("receiver: 'Bob, Dave'"));
+        assert_eq!(env.get_user("Bob").unwrap().message_buffer.len(), 1);
+        assert_eq!(env.get_user("Dave").unwrap().message_buffer.len(), 1);
+        assert!(env.get_user("Alice").unwrap().message_buffer.is_empty());
+        assert!(env.get_user("Carol").unwrap().message_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_named_group() {
+        let mut env: Env<RSA> = Env::in_memory();
+        for name in ["Alice", "Bob", "Carol"] {
+            env.create_user(name);
+        }
+        assert_eq!(env.create_group("room"), Ok(()));
+        assert_eq!(
+            env.create_group("room"),
+            Err(String::from("group 'room' already exists"))
+        );
+        env.join_group("room", "Alice").unwrap();
+        env.join_group("room", "Bob").unwrap();
+        env.join_group("room", "Carol").unwrap();
+        assert_eq!(
+            env.join_group("room", "Eve"),
+            Err(String::from("user 'Eve' not found"))
+        );
+        assert_eq!(
+            env.group_members("room").unwrap(),
+            vec!["Alice", "Bob", "Carol"]
+        );
+
+        let message = Message::new("Alice", 1, "", "1", MessageType::Message);
+        env.send_to_group("room", message).unwrap();
+        env.leave_group("room", "Carol").unwrap();
+        assert_eq!(
+            env.leave_group("room", "Carol"),
+            Err(String::from("user 'Carol' is not a member of group 'room'"))
+        );
+        let message = Message::new("Alice", 1, "", "2", MessageType::Message);
+        env.send_to_group("room", message).unwrap();
+
+        let texts = |env: &Env<RSA>, name: &str| -> Vec<String> {
+            env.get_user(name)
+                .unwrap()
+                .message_buffer
+                .iter()
+                .map(|message| message.get_message().clone())
+                .collect()
+        };
+        assert!(texts(&env, "Alice").is_empty());
+        assert_eq!(texts(&env, "Bob"), vec!["1", "2"]);
+        assert_eq!(texts(&env, "Carol"), vec!["1"]);
+        assert_eq!(
+            env.send_to_group(
+                "hall",
+                Message::new("Alice", 1, "", "3", MessageType::Message)
+            ),
+            Err(String::from("group 'hall' not found"))
+        );
+    }
+
+    #[test]
+    fn test_send_to_recipients_exemptions() {
+        let model = NetworkModel::new(1.0, Duration::ZERO, Duration::ZERO).unwrap();
+        let mut env: Env<RSA> = Env::in_memory().with_network(model);
+        for name in ["Alice", "Bob", "Carol"] {
+            env.create_user(name);
+        }
+        env.set_rotation_interval("Alice", Some(Duration::ZERO))
+            .unwrap();
+        let message = Message::new("Alice", 1, "", "Hello!", MessageType::Message);
+        env.send_to_recipients(message, &["Bob", "Carol"]).unwrap();
+
+        for name in ["Bob", "Carol"] {
+            let buffer = &env.get_user(name).unwrap().message_buffer;
+            assert_eq!(buffer.len(), 1);
+            assert_eq!(buffer[0].get_sequence_number(), None);
+        }
+        // The key broadcast of the rotation goes through the send hook and is dropped.
+        assert!(env.get_user("Alice").unwrap().get_public_key().is_some());
+        assert!(
+            !env.get_user("Bob")
+                .unwrap()
+                .public_key_cache
+                .contains_key("Alice")
+        );
+    }
+
+    #[test]
+    fn test_send_to_recipients_unknown_recipient() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.create_user("Bob, Carol");
+        let message = Message::new("Alice", 1, "Bob", "Hello!", MessageType::Message);
+        assert_eq!(
+            env.send_to_recipients(message.clone(), &["Bob", "Eve"]),
+            Err(String::from("receiver 'Eve' not found"))
+        );
+        assert_eq!(
+            env.send_to_recipients(message.clone(), &["Bob", "Bob, Carol"]),
+            Err(String::from("name 'Bob, Carol' should not contain \", \""))
+        );
+        assert_eq!(
+            env.send_to_recipients(message, &[]),
+            Err(String::from("list of recipients should not be empty"))
+        );
+        assert!(env.get_user("Bob").unwrap().message_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_offline_queue() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        env.set_online("Bob", false).unwrap();
+        assert!(!env.get_user("Bob").unwrap().is_online());
+
+        let key = env.get_mut_user("Alice").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        let id = message.get_id();
+        env.send_message(message).unwrap();
+        let bob = env.get_user("Bob").unwrap();
+        assert!(bob.message_buffer.is_empty());
+        assert_eq!(bob.pending_messages.len(), 2);
+        assert!(bob.public_key_cache.contains_key("Alice"));
+        assert_eq!(env.get_user("Alice").unwrap().message_buffer.len(), 0);
+
+        env.set_online("Bob", true).unwrap();
+        let bob = env.get_user("Bob").unwrap();
+        assert!(bob.pending_messages.is_empty());
+        assert_eq!(bob.message_buffer.len(), 2);
+        assert!(matches!(
+            bob.message_buffer[0].get_message_type(),
+            MessageType::PublicKey
+        ));
+        assert_eq!(bob.message_buffer[1].get_id(), id);
+        assert_eq!(
+            env.set_online("Carol", true),
+            Err(SendError::ReceiverNotFound(String::from("Carol")))
+        );
+    }
+
+    #[test]
+    fn test_invalid_key_exchange() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let message = Message::new("Alice", 0, "Bob", "1 x", MessageType::KeyExchange);
+        env.send_message(message).unwrap();
+        let lines = env.log_lines().unwrap();
+        assert!(
+            lines
+                .last()
+                .unwrap()
+                .starts_with("error: invalid key exchange")
+        );
+        assert!(
+            env.get_user("Bob")
+                .unwrap()
+                .get_shared_secret("Alice")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_with_buffer_capacity() {
+        let mut env: Env<RSA> = Env::in_memory().with_buffer_capacity(3);
+        env.create_user("Alice");
+        env.create_user("Bob");
+        assert_eq!(env.get_user("Bob").unwrap().get_capacity(), Some(3));
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            let message = Message::new("Alice", 1, "Bob", &i.to_string(), MessageType::Message);
+            ids.push(message.get_id());
+            env.send_message(message).unwrap();
+        }
+
+        let bob = env.get_user("Bob").unwrap();
+        let remaining: Vec<MessageId> = bob.message_buffer.iter().map(|m| m.get_id()).collect();
+        assert_eq!(remaining, ids[2..]);
+        assert_eq!(bob.message_buffer[0].get_message(), "2");
+    }
+
+    #[test]
+    fn test_broadcast_skips_sender() {
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+
+        let bob = env.get_user("Bob").unwrap();
+        assert!(bob.message_buffer.is_empty());
+        assert!(bob.public_key_cache.contains_key("Bob"));
+        let alice = env.get_user("Alice").unwrap();
+        assert_eq!(alice.message_buffer.len(), 1);
+        assert!(alice.public_key_cache.contains_key("Bob"));
+
+        let mut env: Env<RSA> = Env::in_memory().with_self_delivery(true);
+        env.create_user("Bob");
+        let key = env.get_mut_user("Bob").unwrap().create_keys();
+        env.send_message(key).unwrap();
+        assert_eq!(env.get_user("Bob").unwrap().message_buffer.len(), 1);
+    }
+
+    #[test]
+    fn test_sender_proof_covers_header() {
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD;
+
+        let mut env: Env<RSA> = Env::in_memory()
+            .with_sender_verification(true)
+            .with_replay_protection(Duration::from_secs(60));
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let identity = env.get_mut_user("Alice").unwrap().create_identity();
+        env.send_message(identity).unwrap();
+        let old_key = env.get_mut_user("Alice").unwrap().create_keys();
+        env.send_message(old_key.clone()).unwrap();
+        let new_key = env.get_mut_user("Alice").unwrap().create_keys();
+        env.send_message(new_key).unwrap();
+
+        let mut bytes = STANDARD.decode(old_key.to_wire()).unwrap();
+        for byte in &mut bytes[8..16] {
+            *byte ^= 0xff;
+        }
+        let replayed = Message::from_wire(&STANDARD.encode(bytes)).unwrap();
+        assert_eq!(replayed.get_sender_proof(), old_key.get_sender_proof());
+        assert_eq!(
+            env.send_message(replayed),
+            Err(SendError::SenderNotVerified(String::from("Alice")))
+        );
+        assert_eq!(env.get_user("Bob").unwrap().session_key_cache["Alice"], 2);
+    }
+
+    #[test]
+    fn test_replay_protection() {
+        let mut env: Env<RSA> = Env::in_memory().with_replay_protection(Duration::from_secs(60));
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        assert_eq!(env.send_message(message.clone()), Ok(()));
+        assert_eq!(
+            env.send_message(message.clone()),
+            Err(SendError::ReplayDetected(String::from("Alice")))
+        );
+        assert_eq!(
+            env.send_to_recipients(message, &["Bob"]),
+            Err(String::from("replayed message from sender 'Alice'"))
+        );
+        assert_eq!(env.get_user("Bob").unwrap().message_buffer.len(), 1);
+
+        let mut old_message = Message::new("Alice", 1, "Bob", "Hello!", MessageType::Message);
+        old_message.set_timestamp(SystemTime::now() - Duration::from_secs(120));
+        assert_eq!(
+            env.send_message(old_message),
+            Err(SendError::ReplayDetected(String::from("Alice")))
+        );
+        let new_message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        assert_eq!(env.send_message(new_message), Ok(()));
+
+        let mut env: Env<RSA> = Env::in_memory();
+        env.create_user("Alice");
+        env.create_user("Bob");
+        let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        assert_eq!(env.send_message(message.clone()), Ok(()));
+        assert_eq!(env.send_message(message), Ok(()));
+    }
 }
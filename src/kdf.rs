@@ -0,0 +1,63 @@
+//! Key derivation
+//!
+//! This module derives keys from passphrases with PBKDF2-HMAC-SHA256 (RFC 8018),
+//! e.g. to protect exported private keys (see `User::export_private_keys`).
+
+use crate::hashing::hmac_sha256;
+
+/// Derives a 32-byte key from the passphrase with PBKDF2-HMAC-SHA256.
+///
+/// The result is deterministic for the same passphrase, salt and number of iterations.
+/// The salt should be random and stored together with the protected data;
+/// more iterations make guessing the passphrase slower.
+pub fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut block = salt.to_vec();
+    block.extend_from_slice(&1_u32.to_be_bytes());
+    let mut u = hmac_sha256(passphrase.as_bytes(), &block);
+    let mut key = u;
+    for _ in 1..iterations {
+        u = hmac_sha256(passphrase.as_bytes(), &u);
+        for (byte, new_byte) in key.iter_mut().zip(u) {
+            *byte ^= new_byte;
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashing::to_hex;
+    use crate::kdf::derive_key;
+
+    #[test]
+    fn test_derive_key_vectors() {
+        assert_eq!(
+            to_hex(&derive_key("password", b"salt", 1)),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+        assert_eq!(
+            to_hex(&derive_key("password", b"salt", 2)),
+            "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+        );
+        assert_eq!(
+            to_hex(&derive_key("password", b"salt", 4096)),
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+    }
+
+    #[test]
+    fn test_derive_key_deterministic() {
+        assert_eq!(
+            derive_key("passphrase", b"salt", 100),
+            derive_key("passphrase", b"salt", 100)
+        );
+        assert_ne!(
+            derive_key("passphrase", b"salt", 100),
+            derive_key("passphrase", b"pepper", 100)
+        );
+        assert_ne!(
+            derive_key("passphrase", b"salt", 100),
+            derive_key("passphrase", b"salt", 101)
+        );
+    }
+}
@@ -26,7 +26,7 @@
 //!     .create_keys();
 //!
 //! // The environment broadcasts the public key to all users.
-//!  env.send_message(key);
+//!  env.send_message(key).unwrap();
 //!
 //! let user1 = env.get_user("Alice").expect("name not found");
 //! let user2 = env.get_user("Bob").expect("name not found");
@@ -44,7 +44,7 @@
 //! //env.create_user("Alice");
 //!
 //! // To create an encrypted message, we specify the receiver and the text of the message.
-//! let sent_message: Message = user1.create_message("Bob", "Hello, Bob!");
+//! let sent_message: Message = user1.create_message("Bob", "Hello, Bob!").unwrap();
 //! println!(
 //!     "User '{0}' sent a message to user '{1}': '{2}'",
 //!     sent_message.get_sender(),
@@ -54,11 +54,11 @@
 //!
 //! // The environment sends the message from Alice to Bob.
 //! // Note that information about all of the encrypted messages is written to the log.
-//! env.send_message(sent_message);
+//! env.send_message(sent_message).unwrap();
 //!
 //! // Bob reads the message
 //! let user2 = env.get_user("Bob").expect("name not found");
-//! let received_message: Message = user2.read_last_message();
+//! let received_message: Message = user2.read_last_message().unwrap();
 //! println!(
 //!     "User '{0}' got a message from user '{1}': '{2}'",
 //!     received_message.get_receiver(),
@@ -73,8 +73,19 @@
 //!         .unwrap()
 //! );
 //!```
+pub mod any_protocol;
+pub mod caesar;
+pub mod diffie_hellman;
+pub mod elgamal;
 pub mod encryption_protocol;
 pub mod env;
+pub mod hashing;
+pub mod kdf;
 pub mod message;
+pub mod one_time_pad;
+pub mod paillier;
 pub mod rsa;
+pub mod secret_sharing;
 pub mod user;
+pub mod vigenere;
+pub mod xor_cipher;
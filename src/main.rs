@@ -20,7 +20,7 @@ fn main() {
         .create_keys();
 
     // The environment broadcasts the public key to all users
-    env.send_message(key);
+    env.send_message(key).unwrap();
 
     let user1 = env.get_user("Alice").expect("name not found");
     let user2 = env.get_user("Bob").expect("name not found");
@@ -38,7 +38,7 @@ fn main() {
     //env.create_user("Alice");
 
     // To create an encrypted message, we specify the receiver and the text of the message.
-    let sent_message: Message = user1.create_message("Bob", "Hello, Bob!");
+    let sent_message: Message = user1.create_message("Bob", "Hello, Bob!").unwrap();
     println!(
         "User '{0}' sent a message to user '{1}': '{2}'",
         sent_message.get_sender(),
@@ -48,11 +48,11 @@ fn main() {
 
     // The environment sends the message from Alice to Bob.
     // Note that information about all of the encrypted messages is written to the log.
-    env.send_message(sent_message);
+    env.send_message(sent_message).unwrap();
 
     // Bob reads the message
     let user2 = env.get_user("Bob").expect("name not found");
-    let received_message: Message = user2.read_last_message();
+    let received_message: Message = user2.read_last_message().unwrap();
     println!(
         "User '{0}' got a message from user '{1}': '{2}'",
         received_message.get_receiver(),
@@ -67,40 +67,16 @@ fn main() {
             .unwrap()
     );
 
-    // In order to change the key pair, we simply execute the corresponding method once again.
-    let new_key = env
-        .get_mut_user("Bob")
-        .expect("name not found")
-        .create_keys();
-
-    // We shouldn't forget to notify others about the change
-    env.send_message(new_key);
-
-    // Let's read the last message from the buffer.
-    // It is the broadcast message with public key that Bob has just sent.
-    let user3 = env.get_user("Bob").expect("name not found");
-    let received_message: Message = user3.read_last_message();
-    println!(
-        "User '{0}' got a message from user '{1}': '{2}'",
-        received_message.get_receiver(),
-        received_message.get_sender(),
-        received_message.get_message()
-    );
-    println!(
-        "Timestamp: {:?}",
-        received_message
-            .get_timestamp()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-    );
+    // In order to change the key pair, we rotate the keys. The environment creates
+    // a new key pair and notifies others about the change.
+    env.rotate_keys("Bob").unwrap();
 
-    // Let's delete this message from the buffer.
-    // The old message is still present there.
-    let user4 = env.get_mut_user("Bob").expect("name not found");
-    user4.delete_last_message();
+    // The sender does not receive their own broadcast, so the old message
+    // is still the last message in Bob's buffer.
+    let user4 = env.get_user("Bob").expect("name not found");
 
     // Let's check that the old message still decrypts with the correct key
-    let received_message: Message = user4.read_last_message();
+    let received_message: Message = user4.read_last_message().unwrap();
     println!(
         "User '{0}' got a message from user '{1}': '{2}'",
         received_message.get_receiver(),
@@ -1,20 +1,60 @@
 //! Infrastructure for messages
 //!
-//! This module contains a struct for messages and a enum for message types.
+//! This module contains a struct for messages, a builder for them and a enum for message types.
+use crate::hashing::{constant_time_eq, hmac_sha256, sha256_hex, to_hex};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::Rng;
 use std::fmt;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Type for message identifiers. Ids are random 128-bit numbers, so messages created
+/// in different processes (e.g. restored from a log) do not collide.
+pub type MessageId = u128;
 
 /// Type of the message.
 ///
-/// A message can have two types:
+/// A message can have the following types:
 /// 1. Ordinary message
 /// 2. Public key
-#[derive(Clone)]
+/// 3. Delete receipt
+/// 4. Self-destructing message
+/// 5. Key exchange
+/// 6. Attachment
+/// 7. Acknowledgement
+/// 8. Identity key
+/// 9. Envelope
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageType {
     /// Ordinary message (it is sent only to the receiver).
     Message,
     /// Public key (it is broadcasted to all users).
     PublicKey,
+    /// Delete receipt (it notifies the sender that their message was deleted).
+    /// The text of the receipt contains the id of the deleted message.
+    DeleteReceipt,
+    /// Self-destructing message (it is sent only to the receiver).
+    /// The expiry of the message is encrypted together with its text.
+    SelfDestruct,
+    /// Key exchange (it is sent only to the receiver). Contains the public
+    /// Diffie-Hellman parameters of the sender.
+    KeyExchange,
+    /// Binary attachment (it is sent only to the receiver). The bytes are encrypted
+    /// block by block, and only their length is shown in the log.
+    Attachment,
+    /// Delivery acknowledgement (it notifies the sender that their message was received).
+    /// The acknowledgement is a reply to the received message (see `Message::get_in_reply_to`).
+    Ack,
+    /// Public identity key for signatures (it is broadcasted to all users).
+    /// Unlike encryption keys, the identity key is long-term.
+    Identity,
+    /// Envelope for several recipients (it is sent to a list of recipients,
+    /// see `Env::send_to_recipients`).
+    /// The text is encrypted once with a random symmetric key, and the key is encrypted
+    /// with the public key of every recipient.
+    Envelope,
 }
 
 impl fmt::Display for MessageType {
@@ -22,22 +62,112 @@ impl fmt::Display for MessageType {
         match self {
             MessageType::Message => write!(f, "Message"),
             MessageType::PublicKey => write!(f, "Public key"),
+            MessageType::DeleteReceipt => write!(f, "Delete receipt"),
+            MessageType::SelfDestruct => write!(f, "Self-destructing message"),
+            MessageType::KeyExchange => write!(f, "Key exchange"),
+            MessageType::Attachment => write!(f, "Attachment"),
+            MessageType::Ack => write!(f, "Ack"),
+            MessageType::Identity => write!(f, "Identity"),
+            MessageType::Envelope => write!(f, "Envelope"),
+        }
+    }
+}
+
+impl FromStr for MessageType {
+    type Err = String;
+
+    /// Parses the message type from its `Display` form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Message" => Ok(MessageType::Message),
+            "Public key" => Ok(MessageType::PublicKey),
+            "Delete receipt" => Ok(MessageType::DeleteReceipt),
+            "Self-destructing message" => Ok(MessageType::SelfDestruct),
+            "Key exchange" => Ok(MessageType::KeyExchange),
+            "Attachment" => Ok(MessageType::Attachment),
+            "Ack" => Ok(MessageType::Ack),
+            "Identity" => Ok(MessageType::Identity),
+            "Envelope" => Ok(MessageType::Envelope),
+            _ => Err(format!("unknown message type '{}'", s)),
         }
     }
 }
 
 /// Message struct.
 ///
-/// Contains information about sender, session key, receiver, text of the message,
-/// message type and timestamp.
-#[derive(Clone)]
+/// Contains information about id, sender, session key, receiver, text of the message,
+/// message type, timestamp, optional signature, optional integrity digest,
+/// optional authentication tag, optional time to live, an optional id of the message
+/// it replies to and a random nonce.
+///
+/// With the `serde` feature, messages can be serialized. The timestamp is serialized
+/// as the number of nanoseconds since the Unix epoch.
+///
+/// Messages are equal if all of their fields are equal. Timestamps are compared exactly,
+/// so two messages created at different moments are never equal, while a message
+/// is always equal to its clone.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
+    id: MessageId,
+    nonce: u64,
     sender: String,
     session_key: usize,
     receiver: String,
     message: String,
     message_type: MessageType,
+    #[cfg_attr(feature = "serde", serde(with = "timestamp_nanos"))]
     timestamp: SystemTime,
+    signature: Option<String>,
+    sender_proof: Option<String>,
+    digest: Option<String>,
+    mac: Option<String>,
+    ttl: Option<Duration>,
+    in_reply_to: Option<MessageId>,
+    attachment_size: Option<u64>,
+    sequence_number: Option<u64>,
+    decrypt_error: Option<String>,
+}
+
+/// Metadata of a message that is available without decryption (see `User::peek`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageHeader {
+    /// Name of the sender.
+    pub sender: String,
+    /// Name of the receiver; empty for broadcasts.
+    pub receiver: String,
+    /// Type of the message.
+    pub message_type: MessageType,
+    /// Session key of the receiver that the message was encrypted for.
+    pub session_key: usize,
+    /// Time when the message was created.
+    pub timestamp: SystemTime,
+}
+
+#[cfg(feature = "serde")]
+mod timestamp_nanos {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub(super) fn serialize<S: Serializer>(
+        timestamp: &SystemTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let nanos = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_err(serde::ser::Error::custom)?
+            .as_nanos();
+        u64::try_from(nanos)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SystemTime, D::Error> {
+        let nanos = u64::deserialize(deserializer)?;
+        Ok(UNIX_EPOCH + Duration::from_nanos(nanos))
+    }
 }
 
 impl Message {
@@ -49,15 +179,37 @@ impl Message {
         message_type: MessageType,
     ) -> Message {
         Message {
+            id: rand::thread_rng().r#gen(),
+            nonce: rand::thread_rng().r#gen(),
             sender: String::from(sender),
             session_key,
             receiver: String::from(receiver),
             message: String::from(message),
             message_type,
             timestamp: SystemTime::now(),
+            signature: None,
+            sender_proof: None,
+            digest: None,
+            mac: None,
+            ttl: None,
+            in_reply_to: None,
+            attachment_size: None,
+            sequence_number: None,
+            decrypt_error: None,
         }
     }
 
+    /// Returns the id of the message. The id is random and is preserved on cloning.
+    pub fn get_id(&self) -> MessageId {
+        self.id
+    }
+
+    /// Returns the random nonce of the message. Like the id, it is preserved on cloning,
+    /// so the environment can detect replayed messages.
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+
     /// Returns the name of the sender.
     pub fn get_sender(&self) -> &String {
         &self.sender
@@ -72,11 +224,19 @@ impl Message {
         &self.receiver
     }
 
+    pub(crate) fn set_receiver(&mut self, receiver: &str) {
+        self.receiver = String::from(receiver);
+    }
+
     /// Returns the text of the message.
     pub fn get_message(&self) -> &String {
         &self.message
     }
 
+    pub(crate) fn set_message(&mut self, message: &str) {
+        self.message = String::from(message);
+    }
+
     /// Returns the message type.
     pub fn get_message_type(&self) -> MessageType {
         self.message_type.clone()
@@ -86,19 +246,781 @@ impl Message {
     pub fn get_timestamp(&self) -> SystemTime {
         self.timestamp
     }
+
+    pub(crate) fn set_timestamp(&mut self, timestamp: SystemTime) {
+        self.timestamp = timestamp;
+    }
+
+    /// Sets the time to live of the message. The message expires when `ttl` passes
+    /// after its timestamp; a zero `ttl` means that the message expires immediately.
+    ///
+    /// The time to live is covered by the integrity digest, the authentication tag and
+    /// the sender proof, so setting it on a created message invalidates them.
+    /// Use `User::set_message_ttl` to create messages with a time to live.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub(crate) fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+    }
+
+    /// Returns the time to live of the message. `None` means that it never expires.
+    pub fn get_ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Checks whether the time to live of the message has passed.
+    /// A time to live too large to be added to the timestamp never passes.
+    pub fn is_expired(&self) -> bool {
+        self.ttl.is_some_and(|ttl| {
+            self.timestamp
+                .checked_add(ttl)
+                .is_some_and(|expiry| expiry <= SystemTime::now())
+        })
+    }
+
+    /// Returns the id of the message this message replies to, if it is a reply.
+    pub fn get_in_reply_to(&self) -> Option<MessageId> {
+        self.in_reply_to
+    }
+
+    pub(crate) fn set_in_reply_to(&mut self, id: MessageId) {
+        self.in_reply_to = Some(id);
+    }
+
+    /// Returns the size of the attachment in bytes, if the message is an attachment.
+    pub fn get_attachment_size(&self) -> Option<u64> {
+        self.attachment_size
+    }
+
+    pub(crate) fn set_attachment_size(&mut self, size: u64) {
+        self.attachment_size = Some(size);
+    }
+
+    /// Returns the sequence number of the message, if it is set.
+    ///
+    /// The environment numbers the messages from every sender to every receiver from 1
+    /// when they are sent, before they reach the network (see `User::missing_sequence_numbers`).
+    /// The number is assigned after the message is created, so it is not covered
+    /// by the digest, the authentication tag or the sender proof.
+    pub fn get_sequence_number(&self) -> Option<u64> {
+        self.sequence_number
+    }
+
+    pub(crate) fn set_sequence_number(&mut self, sequence_number: u64) {
+        self.sequence_number = Some(sequence_number);
+    }
+
+    /// Returns a copy of the message with the text replaced with `<redacted len=N>`
+    /// (`N` - length of the text in bytes).
+    pub(crate) fn redacted(&self) -> Message {
+        let mut redacted = self.clone();
+        redacted.message = format!("<redacted len={}>", self.message.len());
+        redacted.attachment_size = None;
+        redacted
+    }
+
+    /// Returns the text of the message as it is written to the log.
+    /// The encrypted bytes of attachments are replaced with their size.
+    fn logged_text(&self) -> String {
+        match (&self.message_type, self.attachment_size) {
+            (MessageType::Attachment, Some(size)) => format!("<{} bytes>", size),
+            _ => self.message.clone(),
+        }
+    }
+
+    /// Returns the proof of the sender, if it is set. The proof is the signature
+    /// of `Message::proof_input` made with the identity key of the sender, so the
+    /// environment can check the sender without decrypting the message.
+    pub fn get_sender_proof(&self) -> Option<&String> {
+        self.sender_proof.as_ref()
+    }
+
+    /// Returns the text signed by the sender proof: the SHA-256 hash (in hex) of the
+    /// message written with `to_wire` without the proof and the sequence number.
+    /// The proof therefore covers every field set by the sender, including the nonce,
+    /// the timestamp and the type.
+    pub fn proof_input(&self) -> String {
+        sha256_hex(&self.wire_bytes(true))
+    }
+
+    pub(crate) fn set_sender_proof(&mut self, proof: &str) {
+        self.sender_proof = Some(String::from(proof));
+    }
+
+    /// Returns the signature of the message, if the message is signed.
+    pub fn get_signature(&self) -> Option<&String> {
+        self.signature.as_ref()
+    }
+
+    pub(crate) fn set_signature(&mut self, signature: &str) {
+        self.signature = Some(String::from(signature));
+    }
+
+    /// Parses a line of the text log written by the environment (the `Display` form
+    /// of a message). The id of the message is restored if it is present in the line,
+    /// otherwise the parsed message gets a new id. The signature is not restored.
+    pub(crate) fn from_log_line(line: &str) -> Result<Message, String> {
+        let invalid = || format!("invalid log line '{}'", line);
+        let (id, rest) = match line.strip_prefix("id: '") {
+            Some(rest) => {
+                let (id, rest) = rest.split_once("'; ").ok_or_else(invalid)?;
+                (Some(id.parse::<MessageId>().map_err(|_| invalid())?), rest)
+            }
+            None => (None, line),
+        };
+        let rest = rest.strip_prefix("sender: '").ok_or_else(invalid)?;
+        let (sender, rest) = rest.split_once("'; receiver: '").ok_or_else(invalid)?;
+        let (receiver, rest) = rest.split_once("'; message type: '").ok_or_else(invalid)?;
+        let (message_type, rest) = rest.split_once("'; message text: '").ok_or_else(invalid)?;
+        let (message, rest) = rest.rsplit_once("'; session key: '").ok_or_else(invalid)?;
+        let (session_key, rest) = rest.split_once("'; timestamp: '").ok_or_else(invalid)?;
+        let timestamp = rest.strip_suffix("s'").ok_or_else(invalid)?;
+        let (secs, frac) = timestamp.split_once('.').unwrap_or((timestamp, "0"));
+        if frac.len() > 9 {
+            return Err(invalid());
+        }
+        let secs: u64 = secs.parse().map_err(|_| invalid())?;
+        let nanos: u32 = format!("{:0<9}", frac).parse().map_err(|_| invalid())?;
+
+        let mut parsed = Message::new(
+            sender,
+            session_key.parse().map_err(|_| invalid())?,
+            receiver,
+            message,
+            message_type.parse()?,
+        );
+        parsed.set_timestamp(UNIX_EPOCH + Duration::new(secs, nanos));
+        if let Some(id) = id {
+            parsed.id = id;
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the integrity digest of the message, if it is set.
+    ///
+    /// The digest is the SHA-256 hash (in hex) of the sender, the receiver, the text,
+    /// the type, the timestamp and the time to live of the message.
+    pub fn get_digest(&self) -> Option<&String> {
+        self.digest.as_ref()
+    }
+
+    /// Computes the integrity digest of the message and stores it.
+    pub(crate) fn set_digest(&mut self) {
+        self.digest = Some(self.compute_digest());
+    }
+
+    /// Checks the integrity digest of the message. Messages without a digest
+    /// are not intact, so the digest cannot be stripped to skip the check.
+    pub fn verify_digest(&self) -> bool {
+        match &self.digest {
+            Some(digest) => *digest == self.compute_digest(),
+            None => false,
+        }
+    }
+
+    fn compute_digest(&self) -> String {
+        sha256_hex(&self.authenticated_bytes())
+    }
+
+    /// Returns the HMAC-SHA256 authentication tag of the message (in hex), if it is set.
+    ///
+    /// The tag covers the same fields as the integrity digest.
+    pub fn get_mac(&self) -> Option<&String> {
+        self.mac.as_ref()
+    }
+
+    /// Computes the authentication tag of the message with the given key and stores it.
+    pub(crate) fn set_mac(&mut self, mac_key: &[u8]) {
+        self.mac = Some(to_hex(&hmac_sha256(mac_key, &self.authenticated_bytes())));
+    }
+
+    /// Checks the authentication tag of the message with the given key.
+    /// Messages without a tag are not authentic.
+    pub fn verify_mac(&self, mac_key: &[u8]) -> bool {
+        match &self.mac {
+            Some(mac) => {
+                let expected = to_hex(&hmac_sha256(mac_key, &self.authenticated_bytes()));
+                constant_time_eq(mac.as_bytes(), expected.as_bytes())
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the sender, the receiver, the text and the type of the message, each prefixed
+    /// with its length, followed by the timestamp and the time to live, so different
+    /// messages never produce the same bytes.
+    fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        for field in [&self.sender, &self.receiver, &self.message] {
+            put_str(&mut bytes, field);
+        }
+        put_str(&mut bytes, &self.message_type.to_string());
+        put_duration(
+            &mut bytes,
+            self.timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        );
+        bytes.push(self.ttl.is_some() as u8);
+        if let Some(ttl) = self.ttl {
+            put_duration(&mut bytes, ttl);
+        }
+        bytes
+    }
+
+    /// Returns the reason why the message could not be decrypted, if decryption failed.
+    /// In this case, the text of the message stays encrypted.
+    pub fn get_decrypt_error(&self) -> Option<&String> {
+        self.decrypt_error.as_ref()
+    }
+
+    pub(crate) fn set_decrypt_error(&mut self, error: &str) {
+        self.decrypt_error = Some(String::from(error));
+    }
+
+    /// Converts the message to a single-line JSON object. The message type is written
+    /// as the name of the variant, the id as a decimal string (it does not fit into
+    /// a double) and the timestamp as the number of nanoseconds since the Unix epoch. The encrypted bytes of attachments are replaced with their size.
+    pub fn to_json(&self) -> String {
+        let message_type = match self.message_type {
+            MessageType::Message => "Message",
+            MessageType::PublicKey => "PublicKey",
+            MessageType::DeleteReceipt => "DeleteReceipt",
+            MessageType::SelfDestruct => "SelfDestruct",
+            MessageType::KeyExchange => "KeyExchange",
+            MessageType::Attachment => "Attachment",
+            MessageType::Ack => "Ack",
+            MessageType::Identity => "Identity",
+            MessageType::Envelope => "Envelope",
+        };
+        let signature = match &self.signature {
+            Some(signature) => json_string(signature),
+            None => String::from("null"),
+        };
+        format!(
+            "{{\"id\":\"{}\",\"sender\":{},\"receiver\":{},\"message_type\":\"{}\",\"session_key\":{},\"timestamp\":{},\"message\":{},\"signature\":{}}}",
+            self.id,
+            json_string(&self.sender),
+            json_string(&self.receiver),
+            message_type,
+            self.session_key,
+            self.timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+            json_string(&self.logged_text()),
+            signature
+        )
+    }
+
+    /// Converts the message to a compact base64 string for sending over text channels.
+    ///
+    /// The fields of the message are written in binary: numbers as 8 big-endian bytes
+    /// (16 bytes for ids), strings prefixed with their length, optional fields prefixed
+    /// with a flag byte, and the timestamp as the number of nanoseconds since the Unix epoch.
+    /// The decrypt error is local to the receiver and is not written.
+    pub fn to_wire(&self) -> String {
+        STANDARD.encode(self.wire_bytes(false))
+    }
+
+    /// Writes the fields of the message. For the proof input, the fields that are set
+    /// after the message is sealed are skipped.
+    fn wire_bytes(&self, proof_input: bool) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        put_u128(&mut bytes, self.id);
+        put_u64(&mut bytes, self.nonce);
+        put_str(&mut bytes, &self.sender);
+        put_u64(&mut bytes, self.session_key as u64);
+        put_str(&mut bytes, &self.receiver);
+        put_str(&mut bytes, &self.message);
+        put_str(&mut bytes, &self.message_type.to_string());
+        let nanos = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        put_u64(&mut bytes, nanos as u64);
+        let sender_proof = self.sender_proof.as_ref().filter(|_| !proof_input);
+        for field in [
+            self.signature.as_ref(),
+            self.digest.as_ref(),
+            self.mac.as_ref(),
+            sender_proof,
+        ] {
+            bytes.push(field.is_some() as u8);
+            if let Some(field) = field {
+                put_str(&mut bytes, field);
+            }
+        }
+        bytes.push(self.in_reply_to.is_some() as u8);
+        if let Some(in_reply_to) = self.in_reply_to {
+            put_u128(&mut bytes, in_reply_to);
+        }
+        for field in [
+            self.ttl.map(|ttl| ttl.as_nanos() as u64),
+            self.attachment_size,
+            self.sequence_number.filter(|_| !proof_input),
+        ] {
+            bytes.push(field.is_some() as u8);
+            if let Some(field) = field {
+                put_u64(&mut bytes, field);
+            }
+        }
+        bytes
+    }
+
+    /// Parses a message written with `to_wire`. The id of the message is preserved.
+    ///
+    /// Returns an error if the string is not valid base64 or does not contain a message.
+    pub fn from_wire(wire: &str) -> Result<Message, String> {
+        let bytes = STANDARD
+            .decode(wire.trim())
+            .map_err(|_| String::from("invalid base64 in message"))?;
+        let mut reader = WireReader { bytes: &bytes };
+        let id = reader.read_u128()?;
+        let nonce = reader.read_u64()?;
+        let sender = reader.read_string()?;
+        let session_key = reader.read_u64()? as usize;
+        let receiver = reader.read_string()?;
+        let message = reader.read_string()?;
+        let message_type: MessageType = reader.read_string()?.parse()?;
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(reader.read_u64()?);
+        let mut strings: Vec<Option<String>> = Vec::new();
+        for _ in 0..4 {
+            strings.push(match reader.read_flag()? {
+                true => Some(reader.read_string()?),
+                false => None,
+            });
+        }
+        let in_reply_to = match reader.read_flag()? {
+            true => Some(reader.read_u128()?),
+            false => None,
+        };
+        let mut numbers: Vec<Option<u64>> = Vec::new();
+        for _ in 0..3 {
+            numbers.push(match reader.read_flag()? {
+                true => Some(reader.read_u64()?),
+                false => None,
+            });
+        }
+        if !reader.bytes.is_empty() {
+            return Err(String::from("invalid message"));
+        }
+        let [signature, digest, mac, sender_proof]: [Option<String>; 4] =
+            strings.try_into().unwrap();
+        Ok(Message {
+            id,
+            nonce,
+            sender,
+            session_key,
+            receiver,
+            message,
+            message_type,
+            timestamp,
+            signature,
+            sender_proof,
+            digest,
+            mac,
+            ttl: numbers[0].map(Duration::from_nanos),
+            in_reply_to,
+            attachment_size: numbers[1],
+            sequence_number: numbers[2],
+            decrypt_error: None,
+        })
+    }
+}
+
+fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u128(bytes: &mut Vec<u8>, value: u128) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_duration(bytes: &mut Vec<u8>, value: Duration) {
+    put_u64(bytes, value.as_secs());
+    bytes.extend_from_slice(&value.subsec_nanos().to_be_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, value: &str) {
+    put_u64(bytes, value.len() as u64);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Reads the fields written by `Message::to_wire` from the front of the bytes.
+struct WireReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl WireReader<'_> {
+    fn read_bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        if self.bytes.len() < len {
+            return Err(String::from("invalid message"));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_u128(&mut self) -> Result<u128, String> {
+        Ok(u128::from_be_bytes(
+            self.read_bytes(16)?.try_into().unwrap(),
+        ))
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        let len = usize::try_from(self.read_u64()?).map_err(|_| String::from("invalid message"))?;
+        String::from_utf8(self.read_bytes(len)?.to_vec())
+            .map_err(|_| String::from("invalid message"))
+    }
+
+    fn read_flag(&mut self) -> Result<bool, String> {
+        match self.read_bytes(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(String::from("invalid message")),
+        }
+    }
+}
+
+/// Converts the text to a quoted JSON string, escaping special characters.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
 }
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "sender: '{}'; receiver: '{}'; message type: '{}'; message text: '{}'; session key: '{}'; timestamp: '{:?}'",
+            "id: '{}'; sender: '{}'; receiver: '{}'; message type: '{}'; message text: '{}'; session key: '{}'; timestamp: '{:?}'",
+            self.id,
             self.sender,
             self.receiver,
             self.message_type,
-            self.message,
+            self.logged_text(),
             self.session_key,
             self.timestamp.duration_since(UNIX_EPOCH).unwrap()
         )
     }
 }
+
+/// Builder for messages.
+///
+/// The sender, the text and the type of the message are required. The receiver
+/// defaults to an empty string (as in broadcasted public keys), and the session key
+/// defaults to 0. The built message is not encrypted: the text is stored as is.
+///
+/// # Examples
+///
+/// ```
+/// use cryptography_sandbox::message::{MessageBuilder, MessageType};
+///
+/// let message = MessageBuilder::new()
+///     .sender("Alice")
+///     .receiver("Bob")
+///     .text("Hello, Bob!")
+///     .message_type(MessageType::Message)
+///     .session_key(1)
+///     .build()
+///     .unwrap();
+/// assert_eq!(message.get_receiver(), "Bob");
+/// ```
+#[derive(Default)]
+pub struct MessageBuilder {
+    sender: Option<String>,
+    receiver: Option<String>,
+    text: Option<String>,
+    message_type: Option<MessageType>,
+    session_key: usize,
+}
+
+impl MessageBuilder {
+    /// Creates a builder with no fields set.
+    pub fn new() -> MessageBuilder {
+        MessageBuilder::default()
+    }
+
+    /// Sets the sender of the message.
+    pub fn sender(mut self, sender: &str) -> Self {
+        self.sender = Some(String::from(sender));
+        self
+    }
+
+    /// Sets the receiver of the message.
+    pub fn receiver(mut self, receiver: &str) -> Self {
+        self.receiver = Some(String::from(receiver));
+        self
+    }
+
+    /// Sets the text of the message.
+    pub fn text(mut self, text: &str) -> Self {
+        self.text = Some(String::from(text));
+        self
+    }
+
+    /// Sets the type of the message.
+    pub fn message_type(mut self, message_type: MessageType) -> Self {
+        self.message_type = Some(message_type);
+        self
+    }
+
+    /// Sets the session key of the message.
+    pub fn session_key(mut self, session_key: usize) -> Self {
+        self.session_key = session_key;
+        self
+    }
+
+    /// Builds the message. The message gets a new id and the current timestamp.
+    ///
+    /// Returns an error if the sender, the text or the type of the message is not set,
+    /// or if the sender is empty.
+    pub fn build(self) -> Result<Message, String> {
+        let sender = self
+            .sender
+            .ok_or_else(|| String::from("sender is not set"))?;
+        if sender.is_empty() {
+            return Err(String::from("sender should not be empty"));
+        }
+        let text = self.text.ok_or_else(|| String::from("text is not set"))?;
+        let message_type = self
+            .message_type
+            .ok_or_else(|| String::from("message type is not set"))?;
+        Ok(Message::new(
+            &sender,
+            self.session_key,
+            &self.receiver.unwrap_or_default(),
+            &text,
+            message_type,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::{Message, MessageBuilder, MessageType};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn message_with_text(text: &str) -> Message {
+        Message {
+            id: 1,
+            nonce: 2,
+            sender: String::from("Alice"),
+            session_key: 1,
+            receiver: String::from("Bob"),
+            message: String::from(text),
+            message_type: MessageType::Message,
+            timestamp: UNIX_EPOCH + Duration::from_secs(1000),
+            signature: None,
+            sender_proof: None,
+            digest: None,
+            mac: None,
+            ttl: None,
+            in_reply_to: None,
+            attachment_size: None,
+            sequence_number: None,
+            decrypt_error: None,
+        }
+    }
+
+    #[test]
+    fn test_message_eq() {
+        let message = message_with_text("Hello, Bob!");
+        assert_eq!(message, message_with_text("Hello, Bob!"));
+        assert_eq!(message, message.clone());
+        assert_ne!(message, message_with_text("Hello, Alice!"));
+
+        let mut other = message.clone();
+        other.set_timestamp(UNIX_EPOCH + Duration::from_secs(1001));
+        assert_ne!(message, other);
+        assert_eq!(MessageType::Message, MessageType::Message);
+        assert_ne!(MessageType::Message, MessageType::PublicKey);
+        assert_eq!(format!("{:?}", MessageType::KeyExchange), "KeyExchange");
+    }
+
+    #[test]
+    fn test_message_builder() {
+        let message = MessageBuilder::new()
+            .sender("Alice")
+            .receiver("Bob")
+            .text("Hello, Bob!")
+            .message_type(MessageType::Message)
+            .session_key(3)
+            .build()
+            .unwrap();
+        assert_eq!(message.get_sender(), "Alice");
+        assert_eq!(message.get_receiver(), "Bob");
+        assert_eq!(message.get_message(), "Hello, Bob!");
+        assert_eq!(message.get_message_type(), MessageType::Message);
+        assert_eq!(message.get_session_key(), 3);
+
+        let key = MessageBuilder::new()
+            .sender("Alice")
+            .text("1 2")
+            .message_type(MessageType::PublicKey)
+            .build()
+            .unwrap();
+        assert_eq!(key.get_receiver(), "");
+        assert_eq!(key.get_session_key(), 0);
+    }
+
+    #[test]
+    fn test_message_builder_missing_fields() {
+        let without_sender = MessageBuilder::new()
+            .receiver("Bob")
+            .text("Hello, Bob!")
+            .message_type(MessageType::Message)
+            .build();
+        assert_eq!(without_sender, Err(String::from("sender is not set")));
+
+        let empty_sender = MessageBuilder::new()
+            .sender("")
+            .text("Hello, Bob!")
+            .message_type(MessageType::Message)
+            .build();
+        assert_eq!(
+            empty_sender,
+            Err(String::from("sender should not be empty"))
+        );
+
+        let without_text = MessageBuilder::new()
+            .sender("Alice")
+            .message_type(MessageType::Message)
+            .build();
+        assert_eq!(without_text, Err(String::from("text is not set")));
+
+        let without_type = MessageBuilder::new().sender("Alice").text("Hi").build();
+        assert_eq!(without_type, Err(String::from("message type is not set")));
+    }
+
+    #[test]
+    fn test_digest_covers_expiry() {
+        let mut message = message_with_text("Hello, Bob!");
+        message.set_digest();
+        message.set_mac(b"secret");
+        assert!(message.verify_digest());
+        assert!(message.verify_mac(b"secret"));
+
+        let extended = message.clone().with_ttl(Duration::from_secs(60));
+        assert!(!extended.verify_digest());
+        assert!(!extended.verify_mac(b"secret"));
+        let mut delayed = message.clone();
+        delayed.set_timestamp(UNIX_EPOCH + Duration::from_secs(2000));
+        assert!(!delayed.verify_digest());
+        assert!(!delayed.verify_mac(b"secret"));
+    }
+
+    #[test]
+    fn test_wire_round_trip() {
+        let mut message = message_with_text("Hello, Bob!\nHow are you? 🦀");
+        message.message_type = MessageType::SelfDestruct;
+        message.timestamp = UNIX_EPOCH + Duration::new(1000, 123_456_789);
+        message.set_signature("12345");
+        message.set_sender_proof("67890");
+        message.set_digest();
+        message.set_mac(b"secret");
+        message.set_in_reply_to(7);
+        message.set_sequence_number(3);
+        let message = message.with_ttl(Duration::from_millis(1500));
+
+        let parsed = Message::from_wire(&message.to_wire()).unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.get_timestamp(), message.get_timestamp());
+        assert_eq!(parsed.get_message_type(), MessageType::SelfDestruct);
+
+        let plain = message_with_text("");
+        assert_eq!(Message::from_wire(&plain.to_wire()).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_attachment_log_text() {
+        let mut message = message_with_text("AAEC AwQF");
+        message.message_type = MessageType::Attachment;
+        message.set_attachment_size(6);
+        assert!(message.to_string().contains("message text: '<6 bytes>'"));
+        assert!(message.to_json().contains("\"message\":\"<6 bytes>\""));
+        assert!(!message.to_string().contains("AAEC"));
+        assert_eq!(Message::from_wire(&message.to_wire()).unwrap(), message);
+    }
+
+    #[test]
+    fn test_from_wire_invalid() {
+        assert_eq!(
+            Message::from_wire("not base64!"),
+            Err(String::from("invalid base64 in message"))
+        );
+        assert_eq!(
+            Message::from_wire("AAAA"),
+            Err(String::from("invalid message"))
+        );
+        let mut wire = message_with_text("Hello").to_wire();
+        wire.truncate(wire.len() - 8);
+        assert!(Message::from_wire(&wire).is_err());
+    }
+
+    #[test]
+    fn test_message_ids() {
+        let first = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        let second = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        assert_ne!(first.get_id(), second.get_id());
+        assert_eq!(first.clone().get_id(), first.get_id());
+    }
+
+    #[test]
+    fn test_log_line() {
+        let message = Message::new("Alice", 1, "Bob", "Hello, Bob!", MessageType::Message);
+        let line = message.to_string();
+        assert!(line.starts_with(&format!("id: '{}'; sender: 'Alice'", message.get_id())));
+
+        let parsed = Message::from_log_line(&line).unwrap();
+        assert_eq!(parsed.get_id(), message.get_id());
+        assert_eq!(parsed.get_timestamp(), message.get_timestamp());
+        let without_id = line.split_once("; ").unwrap().1;
+        let parsed = Message::from_log_line(without_id).unwrap();
+        assert_ne!(parsed.get_id(), message.get_id());
+        assert_eq!(parsed.get_message(), "Hello, Bob!");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut message = Message::new("Alice", 3, "Bob", "Hello, Bob!", MessageType::SelfDestruct);
+        message.set_signature("123");
+        let json = serde_json::to_string(&message).unwrap();
+        assert!(json.contains("\"message_type\":\"SelfDestruct\""));
+        let restored: Message = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.get_id(), message.get_id());
+        assert_eq!(restored.get_sender(), message.get_sender());
+        assert_eq!(restored.get_session_key(), message.get_session_key());
+        assert_eq!(restored.get_receiver(), message.get_receiver());
+        assert_eq!(restored.get_message(), message.get_message());
+        assert!(matches!(
+            restored.get_message_type(),
+            MessageType::SelfDestruct
+        ));
+        assert_eq!(restored.get_timestamp(), message.get_timestamp());
+        assert_eq!(restored.get_signature(), message.get_signature());
+    }
+}
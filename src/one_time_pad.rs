@@ -0,0 +1,385 @@
+//! Implementaion of one-time pad
+//!
+//! This module contains the implementation of the trait `EncryptionProtocol`.
+//! In the sandbox, the pad is broadcasted to all users as a public key, so this
+//! implementation is intended only for simulation purposes.
+use crate::encryption_protocol::{EncryptionProtocol, ProtocolError};
+use crate::hashing::to_hex;
+use rand::Rng;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default length of the pad (in bytes).
+pub const DEFAULT_PAD_LENGTH: usize = 1024;
+
+/// Struct for public key in one-time pad.
+///
+/// The key consists of a random byte pad and the offset of the first unused byte.
+/// The offset is advanced on every encryption and is shared by the clones of the key.
+/// It is part of the string form of the key, so a key parsed from a saved or
+/// broadcasted string continues from the saved offset. Copies parsed separately
+/// do not share the offset; if they reuse pad bytes, the receiver rejects the message
+/// (see `PrivateKey`).
+#[derive(Clone)]
+pub struct PublicKey {
+    pub(crate) pad: Vec<u8>,
+    pub(crate) offset: Arc<AtomicUsize>,
+}
+
+/// Struct for private key in one-time pad.
+///
+/// The key consists of a random byte pad and the number of bytes consumed by decryption.
+/// Consumed bytes are never used again, so every message can be decrypted only once,
+/// and messages must be decrypted in the order of their offsets.
+#[derive(Clone)]
+pub struct PrivateKey {
+    pub(crate) pad: Vec<u8>,
+    pub(crate) consumed: Cell<usize>,
+}
+
+impl PublicKey {
+    /// Returns the offset of the first unused byte of the pad.
+    pub fn get_offset(&self) -> usize {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    fn new(pad: Vec<u8>, offset: usize) -> Self {
+        Self {
+            pad,
+            offset: Arc::new(AtomicUsize::new(offset)),
+        }
+    }
+}
+
+impl PrivateKey {
+    /// Returns the number of bytes of the pad consumed by decryption.
+    pub fn get_consumed(&self) -> usize {
+        self.consumed.get()
+    }
+}
+
+/// Implementation of the trait `EncryptionProtocol`.
+///
+/// Contains fallible versions of encryption and decryption, helper methods
+/// for creating keys and the implementation of trait methods.
+pub struct OneTimePad {}
+
+impl OneTimePad {
+    /// Creates keys with a random pad of the given length.
+    pub fn create_keys_with_length(length: usize) -> (PublicKey, PrivateKey) {
+        let mut pad: Vec<u8> = vec![0; length];
+        rand::thread_rng().fill(&mut pad[..]);
+
+        (
+            PublicKey::new(pad.clone(), 0),
+            PrivateKey {
+                pad,
+                consumed: Cell::new(0),
+            },
+        )
+    }
+
+    /// Encrypts the message and advances the offset of the key.
+    ///
+    /// The result is the string `"offset:hex"`. Returns an error if the pad is exhausted.
+    pub fn try_encrypt(message: &str, pub_key: &PublicKey) -> Result<String, ProtocolError> {
+        Self::encrypt(message, pub_key)
+    }
+
+    /// Decrypts the message using the pad from the offset stored in the message.
+    ///
+    /// Returns an error if the message is malformed, the pad is exhausted
+    /// or the pad bytes were already consumed.
+    pub fn try_decrypt(message: &str, priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Self::decrypt(message, priv_key)
+    }
+}
+
+impl EncryptionProtocol for OneTimePad {
+    /// Implementation of `PublicKey` for one-time pad is used.
+    type PublicKey = PublicKey;
+    /// Implementation of `PrivateKey` for one-time pad is used.
+    type PrivateKey = PrivateKey;
+    /// `ProtocolError` is used.
+    type Error = ProtocolError;
+
+    /// The bytes of the message are XORed with the unused bytes of the pad,
+    /// and the offset of the key is advanced.
+    ///
+    /// The result is the string `"offset:hex"`. Returns an error if the pad is exhausted.
+    fn encrypt_bytes(message: &[u8], pub_key: &PublicKey) -> Result<Vec<u8>, ProtocolError> {
+        let offset = pub_key
+            .offset
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |offset| {
+                offset
+                    .checked_add(message.len())
+                    .filter(|end| *end <= pub_key.pad.len())
+            })
+            .map_err(|_| ProtocolError::KeyExhausted)?;
+        let end = offset + message.len();
+
+        let mut encrypted_message: String = offset.to_string() + ":";
+        for (byte, pad_byte) in message.iter().zip(&pub_key.pad[offset..end]) {
+            encrypted_message += &format!("{:02x}", byte ^ pad_byte);
+        }
+
+        Ok(encrypted_message.into_bytes())
+    }
+
+    /// The bytes of the message are XORed with the bytes of the pad from the offset
+    /// stored in the message, and these bytes are marked as consumed.
+    ///
+    /// Returns an error if the message is malformed, the pad is exhausted
+    /// or the offset is below the number of consumed bytes.
+    fn decrypt_bytes(message: &[u8], priv_key: &PrivateKey) -> Result<Vec<u8>, ProtocolError> {
+        let invalid = || ProtocolError::InvalidCiphertext(String::from("expected \"offset:hex\""));
+        let message = std::str::from_utf8(message).map_err(|_| invalid())?;
+        let (offset, hex) = message.split_once(':').ok_or_else(invalid)?;
+        let offset: usize = offset.parse().map_err(|_| invalid())?;
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let end = offset.checked_add(hex.len() / 2).ok_or_else(invalid)?;
+        if end > priv_key.pad.len() {
+            return Err(ProtocolError::KeyExhausted);
+        }
+        if offset < priv_key.consumed.get() {
+            return Err(ProtocolError::InvalidCiphertext(String::from(
+                "pad bytes were already consumed",
+            )));
+        }
+
+        let mut decrypted_bytes: Vec<u8> = Vec::with_capacity(hex.len() / 2);
+        for (i, pad_byte) in priv_key.pad[offset..end].iter().enumerate() {
+            let byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+            decrypted_bytes.push(byte ^ pad_byte);
+        }
+        priv_key.consumed.set(end);
+
+        Ok(decrypted_bytes)
+    }
+
+    /// The method generates a random pad of `DEFAULT_PAD_LENGTH` bytes.
+    fn create_keys() -> (PublicKey, PrivateKey) {
+        Self::create_keys_with_length(DEFAULT_PAD_LENGTH)
+    }
+
+    /// Parses a string `"pad offset"` (the hex string of the pad and the offset)
+    /// to public key. If the offset is missing, it is 0.
+    fn to_public_key(message: &str) -> Result<PublicKey, ProtocolError> {
+        let (message, offset) = match message.split_once(' ') {
+            Some((pad, offset)) => (
+                pad,
+                offset.parse().map_err(|_| {
+                    ProtocolError::InvalidPublicKey(String::from("expected \"pad offset\""))
+                })?,
+            ),
+            None => (message, 0),
+        };
+        if message.is_empty() || !message.len().is_multiple_of(2) || !message.is_ascii() {
+            return Err(ProtocolError::InvalidPublicKey(String::from(
+                "expected a non-empty hex string",
+            )));
+        }
+        let pad: Vec<u8> = (0..message.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&message[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| ProtocolError::InvalidPublicKey(String::from("expected a hex string")))?;
+        if offset > pad.len() {
+            return Err(ProtocolError::InvalidPublicKey(String::from(
+                "offset is out of range",
+            )));
+        }
+
+        Ok(PublicKey::new(pad, offset))
+    }
+
+    /// Creates a string from public key: the hex string of the pad
+    /// and the offset separated by a space.
+    fn to_string(pub_key: &Self::PublicKey) -> String {
+        to_hex(&pub_key.pad) + " " + &pub_key.get_offset().to_string()
+    }
+
+    /// Creates a string from private key: the hex string of the pad
+    /// and the number of consumed bytes separated by a space.
+    fn private_key_to_string(priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Ok(to_hex(&priv_key.pad) + " " + &priv_key.consumed.get().to_string())
+    }
+
+    /// Parses a string `"pad consumed"` to private key.
+    fn to_private_key(message: &str) -> Result<PrivateKey, ProtocolError> {
+        let invalid =
+            || ProtocolError::InvalidPrivateKey(String::from("expected \"pad consumed\""));
+        let (pad, consumed) = message.split_once(' ').ok_or_else(invalid)?;
+        let pad = Self::to_public_key(pad).map_err(|_| invalid())?.pad;
+        let consumed: usize = consumed.parse().map_err(|_| invalid())?;
+        if consumed > pad.len() {
+            return Err(invalid());
+        }
+
+        Ok(PrivateKey {
+            pad,
+            consumed: Cell::new(consumed),
+        })
+    }
+
+    fn protocol_name() -> &'static str {
+        "One-time pad"
+    }
+
+    /// The key size is the size of the default pad (`DEFAULT_PAD_LENGTH` bytes).
+    fn key_size_bits() -> usize {
+        DEFAULT_PAD_LENGTH * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption_protocol::{EncryptionProtocol, ProtocolError};
+    use crate::one_time_pad::OneTimePad;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let (public_key, private_key) = OneTimePad::create_keys();
+        let encrypted_message = OneTimePad::encrypt("hello", &public_key).unwrap();
+        let decrypted_message = OneTimePad::decrypt(&encrypted_message, &private_key).unwrap();
+        assert_eq!(decrypted_message, "hello");
+    }
+
+    #[test]
+    fn test_offset_advances() {
+        let (public_key, private_key) = OneTimePad::create_keys();
+        let first_message = OneTimePad::encrypt("hello", &public_key).unwrap();
+        assert_eq!(public_key.get_offset(), 5);
+        let second_message = OneTimePad::encrypt("hello", &public_key).unwrap();
+        assert_eq!(public_key.get_offset(), 10);
+        assert_ne!(first_message, second_message);
+
+        assert_eq!(
+            OneTimePad::decrypt(&first_message, &private_key).unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            OneTimePad::decrypt(&second_message, &private_key).unwrap(),
+            "hello"
+        );
+        assert_eq!(private_key.get_consumed(), 10);
+    }
+
+    #[test]
+    fn test_offset_shared_between_clones() {
+        let (public_key, private_key) = OneTimePad::create_keys_with_length(32);
+        let alice_key = public_key.clone();
+        let carol_key = public_key.clone();
+        let alice_message = OneTimePad::encrypt("hello", &alice_key).unwrap();
+        let carol_message = OneTimePad::encrypt("hello", &carol_key).unwrap();
+        assert!(alice_message.starts_with("0:"));
+        assert!(carol_message.starts_with("5:"));
+        assert_eq!(public_key.get_offset(), 10);
+        assert_eq!(
+            OneTimePad::decrypt(&alice_message, &private_key).unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            OneTimePad::decrypt(&carol_message, &private_key).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn test_offset_restored_from_string() {
+        let (public_key, private_key) = OneTimePad::create_keys_with_length(32);
+        OneTimePad::encrypt("hello", &public_key).unwrap();
+        let restored = OneTimePad::to_public_key(&OneTimePad::to_string(&public_key)).unwrap();
+        assert_eq!(restored.get_offset(), 5);
+        let message = OneTimePad::encrypt("world", &restored).unwrap();
+        assert!(message.starts_with("5:"));
+        assert_eq!(
+            OneTimePad::decrypt(&message, &private_key).unwrap(),
+            "world"
+        );
+    }
+
+    #[test]
+    fn test_consumed_bytes_rejected() {
+        let (public_key, private_key) = OneTimePad::create_keys_with_length(32);
+        let first_copy = OneTimePad::to_public_key(&OneTimePad::to_string(&public_key)).unwrap();
+        let second_copy = OneTimePad::to_public_key(&OneTimePad::to_string(&public_key)).unwrap();
+        let first_message = OneTimePad::encrypt("hello", &first_copy).unwrap();
+        let second_message = OneTimePad::encrypt("world", &second_copy).unwrap();
+        assert_eq!(
+            OneTimePad::decrypt(&first_message, &private_key).unwrap(),
+            "hello"
+        );
+        let consumed = Err(ProtocolError::InvalidCiphertext(String::from(
+            "pad bytes were already consumed",
+        )));
+        assert_eq!(OneTimePad::decrypt(&second_message, &private_key), consumed);
+        assert_eq!(OneTimePad::decrypt(&first_message, &private_key), consumed);
+        assert_eq!(private_key.get_consumed(), 5);
+    }
+
+    #[test]
+    fn test_pad_exhausted() {
+        let (public_key, _private_key) = OneTimePad::create_keys_with_length(8);
+        assert!(OneTimePad::try_encrypt("12345", &public_key).is_ok());
+        assert_eq!(
+            OneTimePad::try_encrypt("12345", &public_key),
+            Err(ProtocolError::KeyExhausted)
+        );
+        assert_eq!(
+            OneTimePad::encrypt("12345", &public_key),
+            Err(ProtocolError::KeyExhausted)
+        );
+        assert_eq!(public_key.get_offset(), 5);
+    }
+
+    #[test]
+    fn test_to_public_key() {
+        let key = OneTimePad::to_public_key("00ff10").unwrap();
+
+        assert_eq!(key.pad, vec![0, 255, 16]);
+        assert_eq!(key.get_offset(), 0);
+        assert_eq!(OneTimePad::to_string(&key), "00ff10 0");
+
+        let key = OneTimePad::to_public_key("00ff10 2").unwrap();
+        assert_eq!(key.get_offset(), 2);
+        assert_eq!(OneTimePad::to_string(&key), "00ff10 2");
+        assert!(OneTimePad::to_public_key("00ff10 4").is_err());
+        assert!(OneTimePad::to_public_key("00ff10 x").is_err());
+    }
+
+    #[test]
+    fn test_to_private_key() {
+        let (public_key, private_key) = OneTimePad::create_keys_with_length(16);
+        let encrypted_message = OneTimePad::encrypt("hello", &public_key).unwrap();
+        OneTimePad::decrypt(&encrypted_message, &private_key).unwrap();
+
+        let text = OneTimePad::private_key_to_string(&private_key).unwrap();
+        assert!(text.ends_with(" 5"));
+        let restored = OneTimePad::to_private_key(&text).unwrap();
+        assert_eq!(restored.pad, private_key.pad);
+        assert_eq!(restored.get_consumed(), 5);
+        assert!(OneTimePad::to_private_key("00ff10 4").is_err());
+        assert!(OneTimePad::to_private_key("00ff10").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_random_bytes() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let (public_key, private_key) = OneTimePad::create_keys_with_length(64);
+        for length in [0, 1, 7, 50] {
+            let mut bytes: Vec<u8> = vec![0; length];
+            rng.fill(&mut bytes[..]);
+            let encrypted = OneTimePad::encrypt_bytes(&bytes, &public_key).unwrap();
+            assert_eq!(
+                OneTimePad::decrypt_bytes(&encrypted, &private_key).unwrap(),
+                bytes
+            );
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Implementaion of Paillier encryption protocol
+//!
+//! This module contains the implementation of the trait `EncryptionProtocol`.
+//! Paillier protocol is additively homomorphic: the product of two ciphertexts
+//! is decrypted to the sum of the plaintexts.
+use crate::encryption_protocol::{EncryptionProtocol, ProtocolError, parse_numbers};
+use crate::rsa::RSA;
+use num_bigint::BigUint;
+use num_bigint::ToBigUint;
+use num_traits::cast::ToPrimitive;
+use rand::Rng;
+
+/// Struct for public key in Paillier.
+///
+/// Paillier public key consists of a number `n = p * q` (`p, q` - primes)
+/// and a generator `g = n + 1`.
+#[derive(Clone)]
+pub struct PublicKey {
+    pub(crate) n: u128,
+    pub(crate) g: u128,
+}
+
+/// Struct for private key in Paillier.
+///
+/// Paillier private key consists of a number `n = p * q` (`p, q` - primes),
+/// `\lambda = lcm(p - 1, q - 1)` and `\mu = \lambda^(-1) % n`.
+#[derive(Clone)]
+pub struct PrivateKey {
+    pub(crate) n: u128,
+    pub(crate) lambda: u128,
+    pub(crate) mu: u128,
+}
+
+/// Implementation of the trait `EncryptionProtocol`.
+///
+/// Contains the homomorphic addition of ciphertexts, helper methods
+/// and the implementation of trait methods.
+pub struct Paillier {}
+
+impl Paillier {
+    /// Adds two ciphertexts homomorphically: `c1, c2 -> c1 * c2 % n^2`.
+    ///
+    /// The result is decrypted to the sum of the plaintexts modulo `n`.
+    /// Returns an error if a ciphertext is not a number or the public key is invalid.
+    pub fn add_ciphertexts(
+        c1: &str,
+        c2: &str,
+        pub_key: &PublicKey,
+    ) -> Result<String, ProtocolError> {
+        let invalid = || ProtocolError::InvalidCiphertext(String::from("expected a number"));
+        let c1: u128 = c1.parse().map_err(|_| invalid())?;
+        let c2: u128 = c2.parse().map_err(|_| invalid())?;
+        let n_squared = Self::n_squared(pub_key.n).ok_or_else(|| {
+            ProtocolError::InvalidPublicKey(String::from("modulus is out of range"))
+        })?;
+
+        Ok(Self::mulmod(c1, c2, n_squared).to_string())
+    }
+
+    /// Returns `n^2`, or `None` if `n < 2` or `n^2` does not fit into `u128`.
+    fn n_squared(n: u128) -> Option<u128> {
+        n.checked_mul(n).filter(|_| n >= 2)
+    }
+
+    fn mulmod(a: u128, b: u128, modulo: u128) -> u128 {
+        let big_a: BigUint = a.to_biguint().unwrap();
+        let big_b: BigUint = b.to_biguint().unwrap();
+        let big_modulo: BigUint = modulo.to_biguint().unwrap();
+        let res: BigUint = big_a * big_b % big_modulo;
+        res.to_u128().unwrap()
+    }
+}
+
+impl EncryptionProtocol for Paillier {
+    /// Implementation of `PublicKey` for Paillier is used.
+    type PublicKey = PublicKey;
+    /// Implementation of `PrivateKey` for Paillier is used.
+    type PrivateKey = PrivateKey;
+    /// `ProtocolError` is used.
+    type Error = ProtocolError;
+
+    /// The message is a decimal integer `m < n`. It is encrypted using Paillier protocol:
+    /// `m -> g^m * r^n % n^2` (`r` - random number). Returns an error if `m >= n`.
+    fn encrypt_bytes(message: &[u8], pub_key: &PublicKey) -> Result<Vec<u8>, ProtocolError> {
+        let m: u128 = std::str::from_utf8(message)
+            .ok()
+            .and_then(|message| message.parse().ok())
+            .ok_or_else(|| ProtocolError::InvalidPlaintext(String::from("expected a number")))?;
+        let n_squared = Self::n_squared(pub_key.n).ok_or_else(|| {
+            ProtocolError::InvalidPublicKey(String::from("modulus is out of range"))
+        })?;
+        if m >= pub_key.n {
+            return Err(ProtocolError::InvalidPlaintext(String::from(
+                "the number should be less than n",
+            )));
+        }
+        let mut r: u128 = rand::thread_rng().gen_range(1..pub_key.n);
+        while RSA::gcd(r, pub_key.n) != 1 {
+            r = rand::thread_rng().gen_range(1..pub_key.n);
+        }
+        let res = Self::mulmod(
+            RSA::expmod(pub_key.g, m, n_squared),
+            RSA::expmod(r, pub_key.n, n_squared),
+            n_squared,
+        );
+
+        Ok(res.to_string().into_bytes())
+    }
+
+    /// The message is decrypted using Paillier protocol:
+    /// `c -> L(c^\lambda % n^2) * \mu % n` (`L(x) = (x - 1) / n`).
+    /// Returns an error unless `c < n^2` and `gcd(c, n) = 1`.
+    fn decrypt_bytes(message: &[u8], priv_key: &PrivateKey) -> Result<Vec<u8>, ProtocolError> {
+        let invalid = || ProtocolError::InvalidCiphertext(String::from("expected a number"));
+        let c: u128 = std::str::from_utf8(message)
+            .map_err(|_| invalid())?
+            .parse()
+            .map_err(|_| invalid())?;
+        let out_of_range = || {
+            ProtocolError::InvalidCiphertext(String::from(
+                "expected a number less than n^2 and coprime with n",
+            ))
+        };
+        let n_squared = Self::n_squared(priv_key.n).ok_or_else(out_of_range)?;
+        if c >= n_squared || RSA::gcd(c, priv_key.n) != 1 {
+            return Err(out_of_range());
+        }
+        let l = RSA::expmod(c, priv_key.lambda, n_squared)
+            .checked_sub(1)
+            .ok_or_else(out_of_range)?
+            / priv_key.n;
+
+        Ok(Self::mulmod(l, priv_key.mu, priv_key.n)
+            .to_string()
+            .into_bytes())
+    }
+
+    /// The method generates 62-bit keys for Paillier.
+    ///
+    /// The method generates two prime numbers `p` and `q`, calculates `n = p * q`,
+    /// `\lambda = lcm(p - 1, q - 1)` and `\mu = \lambda^(-1) % n`.
+    fn create_keys() -> (PublicKey, PrivateKey) {
+        // The two highest bits of p and q are set, so n always has 62 bits.
+        let lower_bound: u128 = 2_u128.pow(30) + 2_u128.pow(29);
+        let upper_bound: u128 = 2_u128.pow(31) - 1;
+
+        let first_primes = RSA::first_primes();
+        let p = RSA::generate_prime(lower_bound, upper_bound, first_primes);
+        let mut q = RSA::generate_prime(lower_bound, upper_bound, first_primes);
+        while q == p {
+            q = RSA::generate_prime(lower_bound, upper_bound, first_primes);
+        }
+
+        let n = p * q;
+        let lambda = (p - 1) * (q - 1) / RSA::gcd(p - 1, q - 1);
+        let mut x: i128 = 0;
+        let mut y: i128 = 0;
+        RSA::calculate_inverse(lambda % n, n, &mut x, &mut y);
+        let mu = x.rem_euclid(n as i128) as u128;
+
+        (PublicKey { n, g: n + 1 }, PrivateKey { n, lambda, mu })
+    }
+
+    /// Parses a string `"a b"` to public key (`n = a, g = b`).
+    fn to_public_key(message: &str) -> Result<PublicKey, ProtocolError> {
+        let invalid = || ProtocolError::InvalidPublicKey(String::from("expected \"n g\""));
+        let (num, generator) = message.split_once(' ').ok_or_else(invalid)?;
+        let n: u128 = num.parse().map_err(|_| invalid())?;
+        let g: u128 = generator.parse().map_err(|_| invalid())?;
+
+        Ok(PublicKey { n, g })
+    }
+
+    /// Creates a string from public key: `n, g -> "n g"`.
+    fn to_string(pub_key: &Self::PublicKey) -> String {
+        pub_key.n.to_string() + " " + &pub_key.g.to_string()
+    }
+
+    /// Creates a string from private key: `n, lambda, mu -> "n lambda mu"`.
+    fn private_key_to_string(priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Ok(priv_key.n.to_string()
+            + " "
+            + &priv_key.lambda.to_string()
+            + " "
+            + &priv_key.mu.to_string())
+    }
+
+    /// Parses a string `"n lambda mu"` to private key.
+    fn to_private_key(message: &str) -> Result<PrivateKey, ProtocolError> {
+        match parse_numbers(message).as_deref() {
+            Some(&[n, lambda, mu]) => Ok(PrivateKey { n, lambda, mu }),
+            _ => Err(ProtocolError::InvalidPrivateKey(String::from(
+                "expected \"n lambda mu\"",
+            ))),
+        }
+    }
+
+    fn protocol_name() -> &'static str {
+        "Paillier"
+    }
+
+    /// The key size is the size of the number `n` created by `create_keys`,
+    /// which is always 62 bits.
+    fn key_size_bits() -> usize {
+        62
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption_protocol::{EncryptionProtocol, ProtocolError};
+    use crate::paillier::Paillier;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let (public_key, private_key) = Paillier::create_keys();
+        let encrypted_message = Paillier::encrypt("12345", &public_key).unwrap();
+        let decrypted_message = Paillier::decrypt(&encrypted_message, &private_key).unwrap();
+        assert_eq!(decrypted_message, "12345");
+    }
+
+    #[test]
+    fn test_key_size_bits() {
+        for _i in 0..20 {
+            let (public_key, _private_key) = Paillier::create_keys();
+            assert_eq!(
+                (u128::BITS - public_key.n.leading_zeros()) as usize,
+                Paillier::key_size_bits()
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_message() {
+        let (public_key, private_key) = Paillier::create_keys();
+        let n = public_key.n;
+        assert_eq!(
+            Paillier::encrypt(&n.to_string(), &public_key),
+            Err(ProtocolError::InvalidPlaintext(String::from(
+                "the number should be less than n"
+            )))
+        );
+        for c in [0, n, 2 * n, n * n, n * n + 1] {
+            assert!(Paillier::decrypt(&c.to_string(), &private_key).is_err());
+        }
+        let huge_key = Paillier::to_public_key(&format!("{} 1", u128::MAX)).unwrap();
+        assert!(Paillier::encrypt("1", &huge_key).is_err());
+    }
+
+    #[test]
+    fn test_add_ciphertexts() {
+        let (public_key, private_key) = Paillier::create_keys();
+        let c1 = Paillier::encrypt("7", &public_key).unwrap();
+        let c2 = Paillier::encrypt("5", &public_key).unwrap();
+        let sum = Paillier::add_ciphertexts(&c1, &c2, &public_key).unwrap();
+        assert_eq!(Paillier::decrypt(&sum, &private_key).unwrap(), "12");
+        assert!(Paillier::add_ciphertexts(&c1, "x", &public_key).is_err());
+    }
+
+    #[test]
+    fn test_to_public_key() {
+        let key = Paillier::to_public_key("123 124").unwrap();
+
+        assert_eq!(key.n, 123);
+        assert_eq!(key.g, 124);
+        assert_eq!(Paillier::to_string(&key), "123 124");
+    }
+}
@@ -0,0 +1,201 @@
+//! Secret sharing
+//!
+//! This module contains Shamir's secret sharing over the prime field of `PRIME` elements.
+//! A secret is split into `n` shares so that any `k` of them reconstruct the secret,
+//! while fewer than `k` shares reveal nothing about it. This can be used for key escrow:
+//! a user splits their private key between trustees (see `User::export_private_keys`).
+
+use rand::Rng;
+
+/// Order of the prime field. Every byte of the secret is shared separately
+/// as an element of the field.
+pub const PRIME: u32 = 257;
+
+/// Share of a secret.
+///
+/// The share consists of its index `x`, the values `f(x)` of the polynomials
+/// for every byte of the secret, and the number of shares required for reconstruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    index: u16,
+    values: Vec<u16>,
+    threshold: usize,
+}
+
+impl Share {
+    /// Returns the index of the share (from 1 to the number of shares).
+    pub fn get_index(&self) -> u16 {
+        self.index
+    }
+
+    /// Returns the values of the share, one for every byte of the secret.
+    pub fn get_values(&self) -> &[u16] {
+        &self.values
+    }
+
+    /// Returns the number of shares required for reconstruction.
+    pub fn get_threshold(&self) -> usize {
+        self.threshold
+    }
+}
+
+/// Splits the secret into `n` shares so that any `k` of them reconstruct it.
+/// Returns an error unless `1 <= k <= n <= 256`.
+pub fn split(secret: &[u8], n: usize, k: usize) -> Result<Vec<Share>, String> {
+    if k == 0 || k > n {
+        return Err(String::from(
+            "threshold should be from 1 to the number of shares",
+        ));
+    }
+    if n >= PRIME as usize {
+        return Err(format!("number of shares should be less than {}", PRIME));
+    }
+
+    let mut rng = rand::thread_rng();
+    let polynomials: Vec<Vec<u32>> = secret
+        .iter()
+        .map(|&byte| {
+            let mut coefficients: Vec<u32> = vec![byte as u32];
+            coefficients.extend((1..k).map(|_| rng.gen_range(0..PRIME)));
+            coefficients
+        })
+        .collect();
+
+    Ok((1..=n as u32)
+        .map(|x| Share {
+            index: x as u16,
+            values: polynomials
+                .iter()
+                .map(|coefficients| evaluate(coefficients, x) as u16)
+                .collect(),
+            threshold: k,
+        })
+        .collect())
+}
+
+/// Reconstructs the secret from the shares. Returns an error if there are fewer shares
+/// than the threshold, or the shares are inconsistent (e.g. duplicate or from different secrets).
+pub fn reconstruct(shares: &[Share]) -> Result<Vec<u8>, String> {
+    let first = shares
+        .first()
+        .ok_or_else(|| String::from("not enough shares"))?;
+    if shares.len() < first.threshold {
+        return Err(String::from("not enough shares"));
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if share.threshold != first.threshold || share.values.len() != first.values.len() {
+            return Err(String::from("shares belong to different secrets"));
+        }
+        if share.index == 0 || shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(String::from("invalid share index"));
+        }
+    }
+
+    interpolate(&shares[..first.threshold])
+        .into_iter()
+        .map(|value| u8::try_from(value).map_err(|_| String::from("invalid shares")))
+        .collect()
+}
+
+/// Computes `f(0)` for every byte from the shares using Lagrange interpolation.
+fn interpolate(shares: &[Share]) -> Vec<u32> {
+    let len = shares.first().map_or(0, |share| share.values.len());
+    (0..len)
+        .map(|byte| {
+            shares.iter().fold(0, |sum, share| {
+                let x = share.index as u32;
+                let basis = shares
+                    .iter()
+                    .filter(|other| other.index != share.index)
+                    .fold(1, |product, other| {
+                        let other_x = other.index as u32;
+                        product * other_x % PRIME * inverse((other_x + PRIME - x) % PRIME) % PRIME
+                    });
+                (sum + share.values[byte] as u32 * basis) % PRIME
+            })
+        })
+        .collect()
+}
+
+/// Evaluates the polynomial with the given coefficients (from the constant term) at `x`.
+fn evaluate(coefficients: &[u32], x: u32) -> u32 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |result, coefficient| (result * x + coefficient) % PRIME)
+}
+
+/// Computes the multiplicative inverse in the field: `a^(PRIME - 2) % PRIME`.
+fn inverse(a: u32) -> u32 {
+    let mut result = 1;
+    let mut base = a % PRIME;
+    let mut exp = PRIME - 2;
+    while exp > 0 {
+        if exp % 2 == 1 {
+            result = result * base % PRIME;
+        }
+        base = base * base % PRIME;
+        exp /= 2;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::secret_sharing::{PRIME, interpolate, inverse, reconstruct, split};
+
+    #[test]
+    fn test_inverse() {
+        for a in 1..PRIME {
+            assert_eq!(a * inverse(a) % PRIME, 1);
+        }
+    }
+
+    #[test]
+    fn test_reconstruct_with_threshold() {
+        let secret: Vec<u8> = (0..=255).collect();
+        let shares = split(&secret, 5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        assert_eq!(reconstruct(&shares[..3]).unwrap(), secret);
+        assert_eq!(reconstruct(&shares[2..]).unwrap(), secret);
+        assert_eq!(
+            reconstruct(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]).unwrap(),
+            secret
+        );
+        assert_eq!(reconstruct(&shares).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_fewer_shares_than_threshold() {
+        let secret = b"private exponent of Alice, 32 b.";
+        let shares = split(secret, 5, 3).unwrap();
+
+        assert_eq!(
+            reconstruct(&shares[..2]),
+            Err(String::from("not enough shares"))
+        );
+        let guess: Vec<u32> = interpolate(&shares[..2]);
+        let secret: Vec<u32> = secret.iter().map(|&byte| byte as u32).collect();
+        assert_ne!(guess, secret);
+    }
+
+    #[test]
+    fn test_invalid_shares() {
+        assert!(split(b"secret", 3, 4).is_err());
+        assert!(split(b"secret", 3, 0).is_err());
+        assert!(split(b"secret", 300, 2).is_err());
+
+        let shares = split(b"secret", 3, 2).unwrap();
+        assert_eq!(
+            reconstruct(&[shares[0].clone(), shares[0].clone()]),
+            Err(String::from("invalid share index"))
+        );
+        let other_shares = split(b"other secret", 3, 2).unwrap();
+        assert_eq!(
+            reconstruct(&[shares[0].clone(), other_shares[1].clone()]),
+            Err(String::from("shares belong to different secrets"))
+        );
+        assert_eq!(reconstruct(&[]), Err(String::from("not enough shares")));
+    }
+}
@@ -1,9 +1,114 @@
 //! User infrastructure
 //!
 //! A user is responsible for creating keys, creating and reading messages.
+use crate::any_protocol::{AnyProtocol, ProtocolKind};
+use crate::diffie_hellman;
 use crate::encryption_protocol::EncryptionProtocol;
-use crate::message::{Message, MessageType};
-use std::collections::HashMap;
+use crate::hashing::{apply_hmac_keystream, constant_time_eq, hmac_sha256, to_hex};
+use crate::kdf::derive_key;
+use crate::message::{Message, MessageHeader, MessageId, MessageType};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::Rng;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Error in the key caches of a user.
+#[derive(Debug, PartialEq)]
+pub enum CacheError {
+    /// The public key of the user is cached, but the session key is not.
+    MissingSessionKey(String),
+    /// The session key of the user is cached, but the public key is not.
+    MissingPublicKey(String),
+    /// The cached public key of the user does not survive string conversion.
+    InvalidPublicKey(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::MissingSessionKey(name) => {
+                write!(f, "session key of user '{}' not found", name)
+            }
+            CacheError::MissingPublicKey(name) => {
+                write!(f, "public key of user '{}' not found", name)
+            }
+            CacheError::InvalidPublicKey(name) => {
+                write!(f, "public key of user '{}' is invalid", name)
+            }
+        }
+    }
+}
+
+/// Error returned when an index is out of the bounds of the message buffer.
+#[derive(Debug, PartialEq)]
+pub struct IndexError {
+    /// The requested index.
+    pub index: usize,
+    /// The length of the buffer.
+    pub len: usize,
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "index {} is out of bounds for buffer of length {}",
+            self.index, self.len
+        )
+    }
+}
+
+/// Error returned when the authenticity of a message cannot be verified
+/// (see `User::read_message_verified`).
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    /// The message cannot be decrypted. Contains the decrypt error.
+    DecryptFailed(String),
+    /// The message is not signed.
+    Unsigned,
+    /// The identity key of the sender is not cached. Contains the name of the sender.
+    UnknownIdentity(String),
+    /// The signature does not match the identity key of the sender.
+    InvalidSignature,
+    /// The index is out of the bounds of the message buffer.
+    InvalidIndex(IndexError),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::DecryptFailed(error) => write!(f, "failed to decrypt message: {}", error),
+            VerifyError::Unsigned => write!(f, "message is not signed"),
+            VerifyError::UnknownIdentity(name) => {
+                write!(f, "identity of user '{}' not found", name)
+            }
+            VerifyError::InvalidSignature => write!(f, "invalid signature"),
+            VerifyError::InvalidIndex(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Flag of the messages that are compressed before encryption.
+///
+/// Every plaintext starts with a header: the digit `'0' + flags`, so the flags
+/// never depend on the text of the message.
+const COMPRESSED_FLAG: u8 = 1;
+
+/// Session key of the messages encrypted with a shared secret instead of a public key
+/// (see `User::complete_exchange`). Key pairs are numbered from 1.
+const SHARED_SECRET_SESSION: usize = 0;
+
+/// Maximum size (in bytes) of a decompressed message. Longer messages are not compressed,
+/// and compressed messages that inflate beyond it are rejected.
+#[cfg(feature = "compression")]
+const MAX_DECOMPRESSED_SIZE: usize = 1 << 20;
+
+/// Number of PBKDF2 iterations for deriving the key of exported private keys.
+const KEY_EXPORT_ITERATIONS: u32 = 10_000;
 
 /// User struct.
 ///
@@ -15,11 +120,56 @@ use std::collections::HashMap;
 pub struct User<T: EncryptionProtocol> {
     name: String,
     private_key_map: HashMap<usize, T::PrivateKey>,
+    key_created: HashMap<usize, SystemTime>,
+    key_lifetime: Option<Duration>,
     public_key: Option<T::PublicKey>,
     session_key: usize,
     pub(crate) public_key_cache: HashMap<String, T::PublicKey>,
     pub(crate) session_key_cache: HashMap<String, usize>,
+    identity: Option<(T::PublicKey, T::PrivateKey)>,
+    pub(crate) identity_cache: HashMap<String, T::PublicKey>,
     pub(crate) message_buffer: Vec<Message>,
+    read_ids: RefCell<HashSet<MessageId>>,
+    capacity: Option<usize>,
+    overflow_callback: Option<Box<dyn FnMut(Message) + Send>>,
+    pending_exchanges: HashMap<String, (u128, u128)>,
+    shared_secrets: HashMap<String, u128>,
+    long_message_threshold: Option<usize>,
+    message_ttl: Option<Duration>,
+    #[cfg(feature = "compression")]
+    compression: bool,
+    online: bool,
+    pub(crate) pending_messages: Vec<Message>,
+    blocked: HashSet<String>,
+}
+
+/// Serializable state of a user (see `EnvSnapshot`). Keys are stored as strings,
+/// so the protocol has to support `EncryptionProtocol::private_key_to_string`.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct UserState {
+    name: String,
+    private_key_map: HashMap<usize, String>,
+    key_created: HashMap<usize, SystemTime>,
+    key_lifetime: Option<Duration>,
+    public_key: Option<String>,
+    session_key: usize,
+    public_key_cache: HashMap<String, String>,
+    session_key_cache: HashMap<String, usize>,
+    identity: Option<(String, String)>,
+    identity_cache: HashMap<String, String>,
+    message_buffer: Vec<Message>,
+    read_ids: HashSet<MessageId>,
+    capacity: Option<usize>,
+    pending_exchanges: HashMap<String, (u128, u128)>,
+    shared_secrets: HashMap<String, u128>,
+    long_message_threshold: Option<usize>,
+    message_ttl: Option<Duration>,
+    #[cfg(feature = "compression")]
+    compression: bool,
+    online: bool,
+    pending_messages: Vec<Message>,
+    blocked: HashSet<String>,
 }
 
 impl<T: EncryptionProtocol> User<T> {
@@ -27,322 +177,1946 @@ impl<T: EncryptionProtocol> User<T> {
         Self {
             name: String::from(user_name),
             private_key_map: HashMap::new(),
+            key_created: HashMap::new(),
+            key_lifetime: None,
             public_key: None,
             session_key: 0,
             public_key_cache: HashMap::new(),
             session_key_cache: HashMap::new(),
+            identity: None,
+            identity_cache: HashMap::new(),
             message_buffer: Vec::new(),
+            read_ids: RefCell::new(HashSet::new()),
+            capacity: None,
+            overflow_callback: None,
+            pending_exchanges: HashMap::new(),
+            shared_secrets: HashMap::new(),
+            long_message_threshold: None,
+            message_ttl: None,
+            #[cfg(feature = "compression")]
+            compression: false,
+            online: true,
+            pending_messages: Vec::new(),
+            blocked: HashSet::new(),
+        }
+    }
+
+    /// Copies the state of the user: keys, caches, buffers and settings.
+    /// The overflow callback cannot be copied and is not set in the copy.
+    pub(crate) fn snapshot(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            private_key_map: self.private_key_map.clone(),
+            key_created: self.key_created.clone(),
+            key_lifetime: self.key_lifetime,
+            public_key: self.public_key.clone(),
+            session_key: self.session_key,
+            public_key_cache: self.public_key_cache.clone(),
+            session_key_cache: self.session_key_cache.clone(),
+            identity: self.identity.clone(),
+            identity_cache: self.identity_cache.clone(),
+            message_buffer: self.message_buffer.clone(),
+            read_ids: self.read_ids.clone(),
+            capacity: self.capacity,
+            overflow_callback: None,
+            pending_exchanges: self.pending_exchanges.clone(),
+            shared_secrets: self.shared_secrets.clone(),
+            long_message_threshold: self.long_message_threshold,
+            message_ttl: self.message_ttl,
+            #[cfg(feature = "compression")]
+            compression: self.compression,
+            online: self.online,
+            pending_messages: self.pending_messages.clone(),
+            blocked: self.blocked.clone(),
         }
     }
 
+    /// Converts the user to a serializable state. Returns an error if a private key
+    /// cannot be converted to a string.
+    #[cfg(feature = "serde")]
+    pub(crate) fn to_state(&self) -> Result<UserState, String> {
+        let public_keys = |cache: &HashMap<String, T::PublicKey>| -> HashMap<String, String> {
+            cache
+                .iter()
+                .map(|(name, key)| (name.clone(), T::to_string(key)))
+                .collect()
+        };
+        Ok(UserState {
+            name: self.name.clone(),
+            private_key_map: self
+                .private_key_map
+                .iter()
+                .map(|(session_key, key)| {
+                    Ok((
+                        *session_key,
+                        T::private_key_to_string(key).map_err(|e| e.to_string())?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+            key_created: self.key_created.clone(),
+            key_lifetime: self.key_lifetime,
+            public_key: self.public_key.as_ref().map(T::to_string),
+            session_key: self.session_key,
+            public_key_cache: public_keys(&self.public_key_cache),
+            session_key_cache: self.session_key_cache.clone(),
+            identity: match &self.identity {
+                Some((public_key, private_key)) => Some((
+                    T::to_string(public_key),
+                    T::private_key_to_string(private_key).map_err(|e| e.to_string())?,
+                )),
+                None => None,
+            },
+            identity_cache: public_keys(&self.identity_cache),
+            message_buffer: self.message_buffer.clone(),
+            read_ids: self.read_ids.borrow().clone(),
+            capacity: self.capacity,
+            pending_exchanges: self.pending_exchanges.clone(),
+            shared_secrets: self.shared_secrets.clone(),
+            long_message_threshold: self.long_message_threshold,
+            message_ttl: self.message_ttl,
+            #[cfg(feature = "compression")]
+            compression: self.compression,
+            online: self.online,
+            pending_messages: self.pending_messages.clone(),
+            blocked: self.blocked.clone(),
+        })
+    }
+
+    /// Creates a user from the serializable state. Returns an error if a key
+    /// cannot be converted from a string.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_state(state: UserState) -> Result<Self, String> {
+        let public_key = |key: &str| T::to_public_key(key).map_err(|error| error.to_string());
+        let public_keys = |cache: HashMap<String, String>| {
+            cache
+                .into_iter()
+                .map(|(name, key)| Ok((name, public_key(&key)?)))
+                .collect::<Result<HashMap<String, T::PublicKey>, String>>()
+        };
+        Ok(Self {
+            name: state.name,
+            private_key_map: state
+                .private_key_map
+                .into_iter()
+                .map(|(session_key, key)| {
+                    Ok((
+                        session_key,
+                        T::to_private_key(&key).map_err(|e| e.to_string())?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+            key_created: state.key_created,
+            key_lifetime: state.key_lifetime,
+            public_key: state.public_key.as_deref().map(public_key).transpose()?,
+            session_key: state.session_key,
+            public_key_cache: public_keys(state.public_key_cache)?,
+            session_key_cache: state.session_key_cache,
+            identity: match state.identity {
+                Some((public, private)) => Some((
+                    public_key(&public)?,
+                    T::to_private_key(&private).map_err(|e| e.to_string())?,
+                )),
+                None => None,
+            },
+            identity_cache: public_keys(state.identity_cache)?,
+            message_buffer: state.message_buffer,
+            read_ids: RefCell::new(state.read_ids),
+            capacity: state.capacity,
+            overflow_callback: None,
+            pending_exchanges: state.pending_exchanges,
+            shared_secrets: state.shared_secrets,
+            long_message_threshold: state.long_message_threshold,
+            message_ttl: state.message_ttl,
+            #[cfg(feature = "compression")]
+            compression: state.compression,
+            online: state.online,
+            pending_messages: state.pending_messages,
+            blocked: state.blocked,
+        })
+    }
+
     /// Returns the name of the user.
     pub fn get_name(&self) -> &String {
         &self.name
     }
 
+    pub(crate) fn set_name(&mut self, user_name: &str) {
+        self.name = String::from(user_name);
+    }
+
     /// Returns the public key of the user.
     pub fn get_public_key(&self) -> Option<&T::PublicKey> {
         self.public_key.as_ref()
     }
 
-    fn decrypt_message(&self, mes: Message) -> Message {
-        match mes.get_message_type() {
-            MessageType::Message => {
-                let private_key: &T::PrivateKey =
-                    self.private_key_map.get(&mes.get_session_key()).unwrap();
-                let trimmed_message = mes.get_message().trim();
-                let chunks = trimmed_message.split(' ');
-                let mut decrypted_message: String = String::new();
-                for chunk in chunks {
-                    decrypted_message += &T::decrypt(chunk, private_key);
-                }
-                Message::new(
-                    mes.get_sender(),
-                    mes.get_session_key(),
-                    mes.get_receiver(),
-                    &decrypted_message,
-                    mes.get_message_type(),
-                )
-            }
-            MessageType::PublicKey => mes.clone(),
-        }
+    /// Sets the capacity of the message buffer. If the capacity is reached,
+    /// the oldest messages are evicted. `None` means that the buffer is unbounded.
+    ///
+    /// Note that eviction shifts the indices of the remaining messages: index `0`
+    /// always refers to the oldest message that is still in the buffer.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+        self.evict_overflow();
     }
 
-    /// Reads the last message from the buffer.
-    pub fn read_last_message(&self) -> Message {
-        User::<T>::decrypt_message(
-            self,
-            self.message_buffer[self.message_buffer.len() - 1].clone(),
-        )
+    /// Returns the capacity of the message buffer.
+    pub fn get_capacity(&self) -> Option<usize> {
+        self.capacity
     }
 
-    /// Reads the message by its index in the buffer.
-    pub fn read_message(&self, index: usize) -> Message {
-        User::<T>::decrypt_message(self, self.message_buffer[index].clone())
+    /// Sets the callback that is called for every message evicted from the buffer
+    /// due to capacity. The callback should be `Send`, so the user can be moved
+    /// to another thread (see `SharedEnv`).
+    pub fn on_overflow(&mut self, callback: Box<dyn FnMut(Message) + Send>) {
+        self.overflow_callback = Some(callback);
     }
 
-    /// Reads all messages from the buffer.
-    pub fn read_all_messages(&self) -> Vec<Message> {
-        let mut messages: Vec<Message> = Vec::with_capacity(self.message_buffer.len());
-        for message in &self.message_buffer {
-            messages.push(User::<T>::decrypt_message(self, message.clone()));
-        }
-        messages
+    pub(crate) fn receive_message(&mut self, message: Message) {
+        self.message_buffer.push(message);
+        self.evict_overflow();
     }
 
-    /// Deletes last message from the buffer.
-    pub fn delete_last_message(&mut self) {
-        self.message_buffer.pop();
+    /// Checks whether the user is online. Users are online by default.
+    pub fn is_online(&self) -> bool {
+        self.online
     }
 
-    /// Deletes the message by its index in the buffer.
-    pub fn delete_message(&mut self, index: usize) {
-        self.message_buffer.remove(index);
+    /// Blocks the user: the environment drops the messages and broadcasts sent by
+    /// the blocked user to this user, including public keys.
+    pub fn block(&mut self, user_name: &str) {
+        self.blocked.insert(String::from(user_name));
     }
 
-    /// Deletes all messages from the buffer.
-    pub fn delete_all_messages(&mut self) {
-        self.message_buffer.clear();
+    /// Unblocks the user. Messages dropped while the user was blocked are not restored.
+    pub fn unblock(&mut self, user_name: &str) {
+        self.blocked.remove(user_name);
     }
 
-    /// Creates an encrypted message.
-    ///
-    /// Accepts the name of the receiver and the text of the message as parameters.
-    /// If the public key of the receiver is known by the user, the message
-    /// is encrypted using this key.
-    pub fn create_message(&self, receiver: &str, message: &str) -> Message {
-        let receiver_string: String = String::from(receiver);
-        if !self.public_key_cache.contains_key(&receiver_string) {
-            panic!("receiver's public key not found");
-        }
-        let pub_key = self.public_key_cache.get(&receiver_string).unwrap();
-        let mut cur_mes = message;
-        let mut encrypted_message: String = String::new();
-        for _i in 0..=((message.len() - 1) / 8) {
-            let split = cur_mes.split_at_checked(8);
-            match split {
-                Some(_) => {
-                    let (head, tail) = split.unwrap();
-                    cur_mes = tail;
-                    encrypted_message += &(T::encrypt(head, pub_key) + " ");
+    /// Checks whether the user is blocked.
+    pub fn is_blocked(&self, user_name: &str) -> bool {
+        self.blocked.contains(user_name)
+    }
+
+    /// Delivers the message to the buffer if the user is online,
+    /// otherwise puts it into the queue of pending messages.
+    /// Messages from blocked users are dropped. Returns whether the message was delivered.
+    pub(crate) fn deliver_message(&mut self, message: Message) -> bool {
+        if self.is_blocked(message.get_sender()) {
+            return false;
+        }
+        if self.online {
+            self.receive_message(message);
+        } else {
+            self.pending_messages.push(message);
+        }
+        true
+    }
+
+    /// Sets the online flag. When the user goes online, the pending messages are moved
+    /// to the buffer in the order they were sent, and pending key exchanges are completed.
+    /// Returns the replies to the key exchanges that should be sent back,
+    /// or the errors of the exchanges that failed.
+    pub(crate) fn set_online(&mut self, online: bool) -> Vec<Result<Message, String>> {
+        self.online = online;
+        let mut replies: Vec<Result<Message, String>> = Vec::new();
+        if online {
+            for message in std::mem::take(&mut self.pending_messages) {
+                if let MessageType::KeyExchange = message.get_message_type() {
+                    replies.extend(self.complete_exchange(&message).transpose());
                 }
-                None => {
-                    encrypted_message += &(T::encrypt(cur_mes, pub_key) + " ");
+                self.receive_message(message);
+            }
+        }
+
+        replies
+    }
+
+    fn evict_overflow(&mut self) {
+        if let Some(capacity) = self.capacity {
+            while self.message_buffer.len() > capacity {
+                let evicted = self.message_buffer.remove(0);
+                self.retain_read_ids();
+                if let Some(callback) = self.overflow_callback.as_mut() {
+                    callback(evicted);
                 }
             }
         }
-        Message::new(
-            &self.name.clone(),
-            *self.session_key_cache.get(&receiver_string).unwrap(),
-            receiver,
-            &encrypted_message,
-            MessageType::Message,
-        )
     }
 
-    /// Creates new public/private key pair.
+    /// Sets the length threshold (in bytes) for long messages. Messages longer than the
+    /// threshold are encrypted at once (e.g. using hybrid encryption), if the protocol
+    /// supports it. `None` disables this mode, which is the default.
+    pub fn set_long_message_threshold(&mut self, threshold: Option<usize>) {
+        self.long_message_threshold = threshold;
+    }
+
+    /// Sets the time to live of the messages, self-destructing messages and attachments
+    /// created by the user (see `Message::with_ttl`). `None` disables expiry,
+    /// which is the default.
+    pub fn set_message_ttl(&mut self, ttl: Option<Duration>) {
+        self.message_ttl = ttl;
+    }
+
+    /// Enables or disables compression of messages before encryption (disabled by default).
     ///
-    /// Note that the resulting message should be broadcasted to all users
-    /// through the environment in order for the user to be able to receive encrypted messages.
-    pub fn create_keys(&mut self) -> Message {
-        let (public_key, private_key) = T::create_keys();
-        self.session_key += 1;
-        self.public_key = Some(public_key);
-        self.private_key_map.insert(self.session_key, private_key);
-        let mes: String = T::to_string(self.public_key.as_ref().unwrap());
-        Message::new(
-            &self.name.clone(),
-            self.session_key,
-            "",
-            &mes,
-            MessageType::PublicKey,
-        )
+    /// A message is sent compressed only if it becomes shorter and is at most
+    /// `MAX_DECOMPRESSED_SIZE` bytes long. Compressed messages are decompressed
+    /// transparently when they are read.
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::message::MessageType;
-    use crate::rsa::RSA;
-    use crate::user::User;
+    /// Validates the integrity of the key caches.
+    ///
+    /// Checks that every cached public key has a corresponding session key and vice versa,
+    /// and that every cached public key can be converted to a string and back.
+    pub fn validate_caches(&self) -> Result<(), CacheError> {
+        for (name, public_key) in &self.public_key_cache {
+            if !self.session_key_cache.contains_key(name) {
+                return Err(CacheError::MissingSessionKey(name.clone()));
+            }
+            let key_string = T::to_string(public_key);
+            match T::to_public_key(&key_string) {
+                Ok(parsed_key) if T::to_string(&parsed_key) == key_string => {}
+                _ => return Err(CacheError::InvalidPublicKey(name.clone())),
+            }
+        }
+        for name in self.session_key_cache.keys() {
+            if !self.public_key_cache.contains_key(name) {
+                return Err(CacheError::MissingPublicKey(name.clone()));
+            }
+        }
 
-    #[test]
-    fn test_create_keys() {
-        let mut user: User<RSA> = User::new("Alice");
-        let mes = user.create_keys();
+        Ok(())
+    }
 
-        assert!(user.public_key.is_some());
-        assert!(!user.private_key_map.is_empty());
-        assert_eq!(mes.get_sender(), "Alice");
-        assert_eq!(mes.get_receiver(), "");
-        let is_public_key_type = match mes.get_message_type() {
-            MessageType::Message => false,
-            MessageType::PublicKey => true,
-        };
-        assert!(is_public_key_type);
+    /// Returns the names of the users whose public keys are cached (sorted by name),
+    /// i.e. the users this user can send encrypted messages to.
+    pub fn known_contacts(&self) -> Vec<String> {
+        let mut contacts: Vec<String> = self.public_key_cache.keys().cloned().collect();
+        contacts.sort();
+        contacts
+    }
 
-        let (num, exp) = mes.get_message().split_once(' ').unwrap();
-        let n: u128 = num.parse().unwrap();
-        let public_exp: u128 = exp.parse().unwrap();
+    /// Checks whether the public key of the given user is cached. Messages to users
+    /// without a cached key cannot be created (see `create_message`).
+    pub fn has_key_for(&self, user_name: &str) -> bool {
+        self.public_key_cache.contains_key(user_name)
+    }
 
-        assert_eq!(user.get_public_key().unwrap().n, n);
-        assert_eq!(user.get_public_key().unwrap().public_exp, public_exp);
+    /// Returns the fingerprint of the cached public key of the given user,
+    /// or `None` if the key is not cached.
+    pub fn contact_fingerprint(&self, user_name: &str) -> Option<String> {
+        self.public_key_cache.get(user_name).map(T::fingerprint)
     }
 
-    #[test]
-    #[should_panic(expected = "receiver's public key not found")]
-    fn test_nonexisting_receiver() {
-        let user: User<RSA> = User::new("Alice");
-        user.create_message("Bob", "Hello, Bob!");
+    /// Returns the shared secret negotiated with the given user.
+    pub fn get_shared_secret(&self, user_name: &str) -> Option<u128> {
+        self.shared_secrets.get(user_name).copied()
     }
 
-    #[test]
-    fn test_send_to_myself() {
-        let mut user: User<RSA> = User::new("Alice");
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let encrypted_message = user.create_message("Alice", "Hello, me!");
-        assert_eq!(encrypted_message.get_sender(), "Alice");
-        assert_eq!(encrypted_message.get_receiver(), "Alice");
-        assert_eq!(encrypted_message.get_session_key(), 1);
-        let is_message_type = match encrypted_message.get_message_type() {
-            MessageType::Message => true,
-            MessageType::PublicKey => false,
+    /// Initiates Diffie-Hellman key exchange with the receiver.
+    ///
+    /// The method generates a safe prime `p`, a generator `g` and a secret exponent `a`,
+    /// and returns a message containing `"p g A"` (`A = g^a % p`). The message should be
+    /// sent to the receiver through the environment.
+    pub fn initiate_exchange(&mut self, receiver: &str) -> Message {
+        let (p, g) = diffie_hellman::generate_group();
+        let a = diffie_hellman::generate_exponent(p);
+        self.pending_exchanges
+            .insert(String::from(receiver), (p, a));
+        let mes = p.to_string()
+            + " "
+            + &g.to_string()
+            + " "
+            + &diffie_hellman::power(g, a, p).to_string();
+        self.seal(self.new_message(0, receiver, &mes, MessageType::KeyExchange))
+    }
+
+    /// Completes Diffie-Hellman key exchange using the message of the other user.
+    ///
+    /// If the exchange was initiated by this user, the shared secret is derived and
+    /// `None` is returned. Otherwise, the shared secret is derived and the reply
+    /// for the initiator is returned.
+    ///
+    /// Returns an error if the message is not of the form `"p g A"` with `1 < g, A < p`,
+    /// or if `p` differs from the one of the exchange initiated by this user.
+    pub fn complete_exchange(&mut self, message: &Message) -> Result<Option<Message>, String> {
+        let invalid = || String::from("invalid key exchange: expected \"p g A\"");
+        let components: Vec<u128> =
+            crate::encryption_protocol::parse_numbers(message.get_message()).ok_or_else(invalid)?;
+        let [p, g, other_public] = components[..] else {
+            return Err(invalid());
         };
-        assert!(is_message_type);
-        let decrypted_message = user.decrypt_message(encrypted_message);
-        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+        if p < 5 || !(2..p).contains(&g) || !(2..p).contains(&other_public) {
+            return Err(invalid());
+        }
+        let peer: String = message.get_sender().clone();
+        match self.pending_exchanges.get(&peer) {
+            Some(&(stored_p, _)) if stored_p != p => Err(String::from(
+                "invalid key exchange: the prime differs from the initiated exchange",
+            )),
+            Some(&(_, a)) => {
+                self.pending_exchanges.remove(&peer);
+                self.shared_secrets
+                    .insert(peer, diffie_hellman::power(other_public, a, p));
+                Ok(None)
+            }
+            None => {
+                let b = diffie_hellman::generate_exponent(p);
+                self.shared_secrets
+                    .insert(peer.clone(), diffie_hellman::power(other_public, b, p));
+                let mes = p.to_string()
+                    + " "
+                    + &g.to_string()
+                    + " "
+                    + &diffie_hellman::power(g, b, p).to_string();
+                let reply = self.new_message(0, &peer, &mes, MessageType::KeyExchange);
+                Ok(Some(self.seal(reply)))
+            }
+        }
     }
 
-    #[test]
-    fn test_change_keys() {
-        let mut user: User<RSA> = User::new("Alice");
-        user.create_keys();
-        assert_eq!(user.session_key, 1);
-        assert_eq!(user.private_key_map.len(), 1);
-        assert!(user.private_key_map.contains_key(&1));
-        assert!(!user.private_key_map.contains_key(&2));
-        user.create_keys();
-        assert_eq!(user.session_key, 2);
-        assert_eq!(user.private_key_map.len(), 2);
-        assert!(user.private_key_map.contains_key(&1));
-        assert!(user.private_key_map.contains_key(&2));
+    /// Encrypts the plaintext with the key derived from the shared secret
+    /// and the nonce of the message, and writes the result in hex.
+    fn encrypt_with_secret(secret: u128, nonce: u64, plaintext: &str) -> String {
+        let key = diffie_hellman::message_key(secret, nonce);
+        to_hex(&apply_hmac_keystream(&key, plaintext.as_bytes()))
     }
 
-    #[test]
-    fn test_send_to_myself_and_change_keys() {
-        let mut user: User<RSA> = User::new("Alice");
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let encrypted_message = user.create_message("Alice", "Hello, me!");
+    /// Decrypts the text of the message encrypted with the shared secret
+    /// negotiated with its sender (see `encrypt_with_secret`).
+    fn decrypt_with_secret(&self, message: &Message) -> Result<String, String> {
+        let sender = message.get_sender();
+        let secret = self
+            .shared_secrets
+            .get(sender)
+            .ok_or_else(|| format!("shared secret with '{}' not found", sender))?;
+        let invalid = || String::from("invalid ciphertext");
+        let hex = message.get_message();
+        if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let key = diffie_hellman::message_key(*secret, message.get_nonce());
+        String::from_utf8(apply_hmac_keystream(&key, &bytes)).map_err(|_| invalid())
+    }
 
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
+    /// Deflates the message and writes the result in base64.
+    #[cfg(feature = "compression")]
+    fn compress(message: &str) -> String {
+        let compressed = miniz_oxide::deflate::compress_to_vec(message.as_bytes(), 6);
+        STANDARD.encode(compressed)
+    }
 
-        let decrypted_message = user.decrypt_message(encrypted_message);
-        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+    /// Inflates the message written by `compress`. Returns an error if the message is
+    /// invalid or inflates beyond `MAX_DECOMPRESSED_SIZE` bytes.
+    #[cfg(feature = "compression")]
+    fn decompress(message: &str) -> Result<String, String> {
+        let invalid = || String::from("invalid compressed message");
+        let compressed = STANDARD.decode(message).map_err(|_| invalid())?;
+        let decompressed =
+            miniz_oxide::inflate::decompress_to_vec_with_limit(&compressed, MAX_DECOMPRESSED_SIZE)
+                .map_err(|_| invalid())?;
+        Ok(String::from_utf8_lossy(&decompressed).into_owned())
     }
 
-    #[test]
-    fn test_read_last_message() {
-        let mut user: User<RSA> = User::new("Alice");
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let encrypted_message = user.create_message("Alice", "Hello, me!");
-        assert_eq!(encrypted_message.get_session_key(), 1);
-        user.message_buffer.push(encrypted_message);
-        let decrypted_message = user.read_last_message();
-        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+    #[cfg(not(feature = "compression"))]
+    fn decompress(_message: &str) -> Result<String, String> {
+        Err(String::from(
+            "compressed message requires the compression feature",
+        ))
+    }
 
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let new_encrypted_message = user.create_message("Alice", "Hello, again!");
-        assert_eq!(new_encrypted_message.get_session_key(), 2);
-        user.message_buffer.push(new_encrypted_message);
-        let new_decrypted_message = user.read_last_message();
-        assert_eq!(new_decrypted_message.get_message(), "Hello, again!");
+    /// Reads the header of the decrypted plaintext and undoes the compression
+    /// recorded in it.
+    fn decode_plaintext(plaintext: &str) -> Result<String, String> {
+        let mut chars = plaintext.chars();
+        let flags = chars
+            .next()
+            .and_then(|header| header.to_digit(10))
+            .filter(|flags| *flags <= COMPRESSED_FLAG as u32)
+            .ok_or_else(|| String::from("invalid message header"))? as u8;
+        let mut message = String::from(chars.as_str());
+        if flags & COMPRESSED_FLAG != 0 {
+            message = Self::decompress(&message)?;
+        }
+        Ok(message)
     }
 
-    #[test]
-    fn test_read_message() {
-        let mut user: User<RSA> = User::new("Alice");
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let encrypted_message = user.create_message("Alice", "Hello, me!");
-        user.message_buffer.push(encrypted_message);
-        let decrypted_message = user.read_message(0);
-        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+    /// Decrypts the message and decompresses it if it was compressed. The embedded expiry
+    /// of self-destructing messages is checked and removed from the text. If the message
+    /// has expired, the integrity digest does not match, the private key of the session
+    /// is not found or the protocol fails to decrypt it, the message is returned as is
+    /// with a decrypt error (see `Message::get_decrypt_error`).
+    fn decrypt_message(&self, mes: Message) -> Message {
+        if mes.is_expired() {
+            let mut expired = mes.clone();
+            expired.set_decrypt_error("message expired");
+            return expired;
+        }
+        match mes.get_message_type() {
+            MessageType::Message | MessageType::SelfDestruct => {
+                if !mes.verify_digest() {
+                    let mut failed = mes.clone();
+                    failed.set_decrypt_error("integrity check failed");
+                    return failed;
+                }
+                let decrypted_message = if mes.get_session_key() == SHARED_SECRET_SESSION {
+                    self.decrypt_with_secret(&mes)
+                } else {
+                    self.session_private_key(mes.get_session_key())
+                        .and_then(|private_key| {
+                            let chunks = T::split_ciphertext(mes.get_message());
+                            T::decrypt_blocks(&chunks, private_key).map_err(|e| e.to_string())
+                        })
+                };
+                let decrypted_message = decrypted_message
+                    .and_then(|plaintext| Self::decode_plaintext(&plaintext))
+                    .and_then(|decoded| match mes.get_message_type() {
+                        MessageType::SelfDestruct => Self::open_self_destruct(&decoded),
+                        _ => Ok(decoded),
+                    });
+                let decrypted_message = match decrypted_message {
+                    Ok(decrypted_message) => decrypted_message,
+                    Err(error) => {
+                        let mut failed = mes.clone();
+                        failed.set_decrypt_error(&error);
+                        return failed;
+                    }
+                };
+                let mut decrypted = mes.clone();
+                decrypted.set_message(&decrypted_message);
+                decrypted
+            }
+            MessageType::Envelope => match self.open_envelope(&mes) {
+                Ok(text) => {
+                    let mut decrypted = mes.clone();
+                    decrypted.set_message(&text);
+                    decrypted
+                }
+                Err(error) => {
+                    let mut failed = mes.clone();
+                    failed.set_decrypt_error(&error);
+                    failed
+                }
+            },
+            MessageType::PublicKey
+            | MessageType::DeleteReceipt
+            | MessageType::KeyExchange
+            | MessageType::Attachment
+            | MessageType::Ack
+            | MessageType::Identity => mes.clone(),
+        }
+    }
 
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let new_encrypted_message = user.create_message("Alice", "Hello, again!");
-        user.message_buffer.push(new_encrypted_message);
-        let new_decrypted_message = user.read_message(1);
-        assert_eq!(new_decrypted_message.get_message(), "Hello, again!");
-        let old_decrypted_message = user.read_message(0);
-        assert_eq!(old_decrypted_message.get_message(), "Hello, me!");
+    /// Reads the last message from the buffer and marks it as read.
+    /// Returns `None` if the buffer is empty. If the message cannot be decrypted,
+    /// the decrypt error is set (see `Message::get_decrypt_error`).
+    pub fn read_last_message(&self) -> Option<Message> {
+        let index = self.message_buffer.len().checked_sub(1)?;
+        Some(self.read_message(index))
     }
 
-    #[test]
-    #[should_panic(expected = "index out of bounds")]
-    fn test_read_message_out_of_bounds() {
-        let mut user: User<RSA> = User::new("Alice");
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let encrypted_message = user.create_message("Alice", "Hello, me!");
-        user.message_buffer.push(encrypted_message);
-        user.read_message(1);
+    /// Reads the message by its index in the buffer and marks it as read.
+    /// If the message cannot be decrypted, the decrypt error is set
+    /// (see `Message::get_decrypt_error`).
+    pub fn read_message(&self, index: usize) -> Message {
+        self.read_ids
+            .borrow_mut()
+            .insert(self.message_buffer[index].get_id());
+        User::<T>::decrypt_message(self, self.message_buffer[index].clone())
     }
 
-    fn setup() -> User<RSA> {
-        let mut user: User<RSA> = User::new("Alice");
-        user.create_keys();
-        user.public_key_cache
-            .insert("Alice".to_string(), user.public_key.clone().unwrap());
-        user.session_key_cache
-            .insert("Alice".to_string(), user.session_key);
-        let encrypted_message = user.create_message("Alice", "Hello, me!");
-        user.message_buffer.push(encrypted_message);
+    /// Returns the metadata of the message by its index in the buffer without decrypting it.
+    /// The message is not marked as read.
+    pub fn peek(&self, index: usize) -> Result<MessageHeader, IndexError> {
+        let message = self.check_index(index)?;
+        Ok(MessageHeader {
+            sender: message.get_sender().clone(),
+            receiver: message.get_receiver().clone(),
+            message_type: message.get_message_type(),
+            session_key: message.get_session_key(),
+            timestamp: message.get_timestamp(),
+        })
+    }
 
-        user.create_keys();
-        user.public_key_cache
+    /// Reads the message by its index in the buffer and marks it as read.
+    /// Returns `None` if the index is out of bounds.
+    pub fn try_read_message(&self, index: usize) -> Option<Message> {
+        if index < self.message_buffer.len() {
+            Some(self.read_message(index))
+        } else {
+            None
+        }
+    }
+
+    /// Reads the message by its index in the buffer and verifies that it was signed
+    /// by its sender.
+    ///
+    /// The signature is checked against the cached identity key of the sender
+    /// (see `create_identity`), so a message with a spoofed sender name is rejected.
+    /// Returns an error if the index is out of bounds, the message cannot be decrypted,
+    /// is not signed, the identity of the sender is unknown or the signature is invalid.
+    pub fn read_message_verified(&self, index: usize) -> Result<Message, VerifyError> {
+        self.check_index(index).map_err(VerifyError::InvalidIndex)?;
+        let message = self.read_message(index);
+        if let Some(error) = message.get_decrypt_error() {
+            return Err(VerifyError::DecryptFailed(error.clone()));
+        }
+        let signature = message.get_signature().ok_or(VerifyError::Unsigned)?;
+        let identity = self
+            .identity_cache
+            .get(message.get_sender())
+            .ok_or_else(|| VerifyError::UnknownIdentity(message.get_sender().clone()))?;
+        if !T::verify(message.get_message(), signature, identity).unwrap_or(false) {
+            return Err(VerifyError::InvalidSignature);
+        }
+        Ok(message)
+    }
+
+    /// Marks the message with the given index in the buffer as read.
+    /// Returns an error if the index is out of bounds.
+    pub fn mark_read(&mut self, index: usize) -> Result<(), IndexError> {
+        let id = self.check_index(index)?.get_id();
+        self.read_ids.get_mut().insert(id);
+        Ok(())
+    }
+
+    /// Checks whether the message with the given index in the buffer has been read.
+    /// Returns an error if the index is out of bounds.
+    pub fn is_read(&self, index: usize) -> Result<bool, IndexError> {
+        let id = self.check_index(index)?.get_id();
+        Ok(self.read_ids.borrow().contains(&id))
+    }
+
+    /// Returns the number of unread messages in the buffer.
+    /// Read flags are kept by message id, so copies of a message are read together.
+    pub fn unread_count(&self) -> usize {
+        let read_ids = self.read_ids.borrow();
+        self.message_buffer
+            .iter()
+            .filter(|message| !read_ids.contains(&message.get_id()))
+            .count()
+    }
+
+    fn check_index(&self, index: usize) -> Result<&Message, IndexError> {
+        self.message_buffer.get(index).ok_or(IndexError {
+            index,
+            len: self.message_buffer.len(),
+        })
+    }
+
+    /// Forgets the read flags of the messages that are no longer in the buffer.
+    fn retain_read_ids(&mut self) {
+        let ids: HashSet<MessageId> = self
+            .message_buffer
+            .iter()
+            .map(|message| message.get_id())
+            .collect();
+        self.read_ids.get_mut().retain(|id| ids.contains(id));
+    }
+
+    /// Reads the self-destructing message by its index in the buffer.
+    ///
+    /// Returns an error if the message is not a self-destructing message,
+    /// if it cannot be decrypted, if the embedded expiry cannot be parsed,
+    /// if the message has expired, or if the index is out of bounds.
+    pub fn read_self_destruct_message(&self, index: usize) -> Result<Message, String> {
+        let message = self.check_index(index).map_err(|e| e.to_string())?;
+        if !matches!(message.get_message_type(), MessageType::SelfDestruct) {
+            return Err(String::from("not a self-destruct message"));
+        }
+        let decrypted = User::<T>::decrypt_message(self, message.clone());
+        match decrypted.get_decrypt_error() {
+            Some(error) => Err(error.clone()),
+            None => Ok(decrypted),
+        }
+    }
+
+    /// Splits the decrypted body `"expiry|text"` of a self-destructing message
+    /// (`expiry` - nanoseconds since the Unix epoch) and returns the text.
+    /// Returns an error if the body is invalid or the message has expired.
+    fn open_self_destruct(body: &str) -> Result<String, String> {
+        let invalid = || String::from("invalid self-destruct message");
+        let (expiry, text) = body.split_once('|').ok_or_else(invalid)?;
+        let expiry_nanos: u128 = expiry.parse().map_err(|_| invalid())?;
+        let secs = u64::try_from(expiry_nanos / 1_000_000_000).map_err(|_| invalid())?;
+        let expiry = UNIX_EPOCH
+            .checked_add(Duration::new(secs, (expiry_nanos % 1_000_000_000) as u32))
+            .ok_or_else(invalid)?;
+        if expiry < SystemTime::now() {
+            return Err(String::from("message expired"));
+        }
+        Ok(String::from(text))
+    }
+
+    /// Reads the attachment by its index in the buffer and marks it as read.
+    ///
+    /// Returns an error if the message is not an attachment, if it has expired,
+    /// if the integrity digest does not match, if the bytes cannot be decrypted,
+    /// or if the index is out of bounds.
+    pub fn read_attachment(&self, index: usize) -> Result<Vec<u8>, String> {
+        let message = self.check_index(index).map_err(|e| e.to_string())?;
+        if !matches!(message.get_message_type(), MessageType::Attachment) {
+            return Err(String::from("not an attachment"));
+        }
+        self.read_ids.borrow_mut().insert(message.get_id());
+        if message.is_expired() {
+            return Err(String::from("message expired"));
+        }
+        if !message.verify_digest() {
+            return Err(String::from("integrity check failed"));
+        }
+        let private_key = self.session_private_key(message.get_session_key())?;
+        let blocks: Vec<Vec<u8>> = message
+            .get_message()
+            .split_whitespace()
+            .map(|block| STANDARD.decode(block))
+            .collect::<Result<_, _>>()
+            .map_err(|_| String::from("invalid attachment"))?;
+        let data = T::decrypt_byte_blocks(&blocks, private_key).map_err(|e| e.to_string())?;
+        if message.get_attachment_size() != Some(data.len() as u64) {
+            return Err(String::from("invalid attachment"));
+        }
+        Ok(data)
+    }
+
+    /// Reads all messages from the buffer.
+    pub fn read_all_messages(&self) -> Vec<Message> {
+        let mut messages: Vec<Message> = Vec::with_capacity(self.message_buffer.len());
+        for message in &self.message_buffer {
+            messages.push(User::<T>::decrypt_message(self, message.clone()));
+        }
+        messages
+    }
+
+    /// Writes the decrypted messages from the buffer to the file, one message per line.
+    /// The file is overwritten. Unlike the log of the environment, which contains
+    /// the traffic, the file contains the state of the buffer of this user.
+    pub fn export_buffer(&self, file_name: &str) -> Result<(), String> {
+        User::<T>::write_messages(file_name, &self.read_all_messages())
+    }
+
+    /// Writes the messages from the buffer to the file as they are stored, i.e. encrypted.
+    /// The file is overwritten.
+    pub fn export_buffer_encrypted(&self, file_name: &str) -> Result<(), String> {
+        User::<T>::write_messages(file_name, &self.message_buffer)
+    }
+
+    fn write_messages(file_name: &str, messages: &[Message]) -> Result<(), String> {
+        let contents: String = messages
+            .iter()
+            .map(|message| format!("{}\n", message))
+            .collect();
+        fs::write(file_name, contents).map_err(|error| error.to_string())
+    }
+
+    /// Reads all messages from the given sender in the order of the buffer.
+    ///
+    /// Public keys and key exchanges are included only if `include_keys` is `true`.
+    pub fn messages_from(&self, sender: &str, include_keys: bool) -> Vec<Message> {
+        self.message_buffer
+            .iter()
+            .filter(|message| message.get_sender() == sender)
+            .filter(|message| {
+                include_keys
+                    || !matches!(
+                        message.get_message_type(),
+                        MessageType::PublicKey | MessageType::KeyExchange
+                    )
+            })
+            .map(|message| self.decrypt_message(message.clone()))
+            .collect()
+    }
+
+    /// Reads all messages of the given type in the order of the buffer.
+    pub fn messages_of_type(&self, message_type: MessageType) -> Vec<Message> {
+        self.message_buffer
+            .iter()
+            .filter(|message| message.get_message_type() == message_type)
+            .map(|message| self.decrypt_message(message.clone()))
+            .collect()
+    }
+
+    /// Reads all messages sent from `start` to `end` (inclusive) in the order of the buffer.
+    /// If `start` is later than `end`, no messages are returned.
+    pub fn messages_between(&self, start: SystemTime, end: SystemTime) -> Vec<Message> {
+        self.message_buffer
+            .iter()
+            .filter(|message| (start..=end).contains(&message.get_timestamp()))
+            .map(|message| self.decrypt_message(message.clone()))
+            .collect()
+    }
+
+    /// Reads the reply chain that ends with the message with the given id.
+    ///
+    /// The chain is reconstructed from the buffer by following the ids of the replied
+    /// messages, and is returned starting from the first message of the thread.
+    /// The chain stops at the first message that is not in the buffer.
+    /// Returns an empty vector if the message with the given id is not in the buffer.
+    pub fn thread_of(&self, id: MessageId) -> Vec<Message> {
+        let mut thread: Vec<Message> = Vec::new();
+        let mut next: Option<MessageId> = Some(id);
+        while let Some(id) = next {
+            let Some(message) = self
+                .message_buffer
+                .iter()
+                .find(|message| message.get_id() == id)
+            else {
+                break;
+            };
+            if thread.iter().any(|read| read.get_id() == id) {
+                break;
+            }
+            thread.push(self.decrypt_message(message.clone()));
+            next = message.get_in_reply_to();
+        }
+        thread.reverse();
+        thread
+    }
+
+    /// Returns the approximate number of bytes used by the message buffer.
+    ///
+    /// The estimate includes the lengths of the strings stored in the messages
+    /// and the fixed size of each message struct.
+    pub fn approx_memory_bytes(&self) -> usize {
+        let mut total: usize = size_of::<Vec<Message>>();
+        for message in &self.message_buffer {
+            total += size_of::<Message>()
+                + message.get_sender().len()
+                + message.get_receiver().len()
+                + message.get_message().len();
+        }
+        total
+    }
+
+    /// Returns the number of buffered direct messages per sender.
+    ///
+    /// The messages are not decrypted, so the method is cheap to call.
+    pub fn conversation_counts(&self) -> HashMap<String, usize> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for message in &self.message_buffer {
+            if let MessageType::Message = message.get_message_type() {
+                *counts.entry(message.get_sender().clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Deletes all expired messages (see `Message::with_ttl`) from the buffer.
+    /// Returns the number of deleted messages.
+    pub fn purge_expired(&mut self) -> usize {
+        let len = self.message_buffer.len();
+        self.message_buffer.retain(|message| !message.is_expired());
+        self.retain_read_ids();
+        len - self.message_buffer.len()
+    }
+
+    /// Returns the sequence numbers of the messages from the given sender that are
+    /// missing in the buffer, i.e. the gaps before the largest received number
+    /// (see `Message::get_sequence_number`). The gaps indicate dropped messages,
+    /// or messages that are delayed or were deleted from the buffer.
+    pub fn missing_sequence_numbers(&self, sender: &str) -> Vec<u64> {
+        let received: HashSet<u64> = self
+            .message_buffer
+            .iter()
+            .filter(|message| {
+                message.get_sender() == sender && message.get_receiver() == &self.name
+            })
+            .filter_map(|message| message.get_sequence_number())
+            .collect();
+        let last = received.iter().copied().max().unwrap_or(0);
+        (1..last)
+            .filter(|sequence_number| !received.contains(sequence_number))
+            .collect()
+    }
+
+    /// Sorts the buffer by the timestamps of the messages, from the oldest to the newest.
+    /// Messages with equal timestamps keep their order.
+    ///
+    /// Note that sorting changes the indices of the messages, so the indices obtained
+    /// before sorting (e.g. for `read_message` or `delete_message`) should not be reused.
+    pub fn sort_by_timestamp(&mut self) {
+        self.message_buffer
+            .sort_by_key(|message| message.get_timestamp());
+    }
+
+    /// Deletes duplicate messages from the buffer, keeping the first occurrence.
+    /// Messages are duplicates if they have the same sender, receiver, type and ciphertext
+    /// (e.g. a public key that was broadcasted several times).
+    /// Returns the number of deleted messages.
+    ///
+    /// Like sorting, deduplication shifts the indices of the remaining messages.
+    pub fn dedup(&mut self) -> usize {
+        let len = self.message_buffer.len();
+        let mut kept: Vec<Message> = Vec::with_capacity(len);
+        for message in std::mem::take(&mut self.message_buffer) {
+            let duplicate = kept.iter().any(|other| {
+                other.get_sender() == message.get_sender()
+                    && other.get_receiver() == message.get_receiver()
+                    && other.get_message_type() == message.get_message_type()
+                    && other.get_message() == message.get_message()
+            });
+            if !duplicate {
+                kept.push(message);
+            }
+        }
+        self.message_buffer = kept;
+        self.retain_read_ids();
+        len - self.message_buffer.len()
+    }
+
+    /// Deletes last message from the buffer.
+    pub fn delete_last_message(&mut self) {
+        self.message_buffer.pop();
+        self.retain_read_ids();
+    }
+
+    /// Deletes the message by its index in the buffer.
+    pub fn delete_message(&mut self, index: usize) {
+        self.message_buffer.remove(index);
+        self.retain_read_ids();
+    }
+
+    /// Deletes the message by its index in the buffer.
+    /// Returns an error if the index is out of bounds.
+    pub fn try_delete_message(&mut self, index: usize) -> Result<(), IndexError> {
+        self.check_index(index)?;
+        self.delete_message(index);
+        Ok(())
+    }
+
+    /// Deletes the message by its index in the buffer and creates a delete receipt.
+    ///
+    /// Returns the deleted message and a receipt addressed to its sender.
+    /// The receipt contains the id of the deleted message and should be sent
+    /// through the environment. Receipts are created only for ordinary messages.
+    pub fn delete_and_notify(&mut self, index: usize) -> (Message, Option<Message>) {
+        let deleted = self.message_buffer.remove(index);
+        self.retain_read_ids();
+        let receipt = match deleted.get_message_type() {
+            MessageType::Message => Some(self.seal(self.new_message(
+                0,
+                deleted.get_sender(),
+                &deleted.get_id().to_string(),
+                MessageType::DeleteReceipt,
+            ))),
+            _ => None,
+        };
+        (deleted, receipt)
+    }
+
+    /// Creates a delivery acknowledgement of the received message.
+    ///
+    /// The acknowledgement is addressed to the sender of the message and references it
+    /// by its id (see `Message::get_in_reply_to`). It should be sent through the environment.
+    pub fn acknowledge(&self, original: &Message) -> Message {
+        let mut ack = self.new_message(
+            0,
+            original.get_sender(),
+            &original.get_id().to_string(),
+            MessageType::Ack,
+        );
+        ack.set_in_reply_to(original.get_id());
+        self.seal(ack)
+    }
+
+    /// Returns the ids of the messages that were acknowledged by their receivers,
+    /// in the order the acknowledgements were received. Every id is returned once.
+    pub fn acked_ids(&self) -> Vec<MessageId> {
+        let mut ids: Vec<MessageId> = Vec::new();
+        for message in &self.message_buffer {
+            if let (MessageType::Ack, Some(id)) =
+                (message.get_message_type(), message.get_in_reply_to())
+                && !ids.contains(&id)
+            {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Deletes all messages from the buffer.
+    pub fn delete_all_messages(&mut self) {
+        self.message_buffer.clear();
+        self.read_ids.get_mut().clear();
+    }
+
+    /// Creates an encrypted message.
+    ///
+    /// Accepts the name of the receiver and the text of the message as parameters.
+    /// If the user has negotiated a shared secret with the receiver, the message
+    /// is encrypted with a key derived from this secret and the nonce of the message.
+    /// Otherwise, it is encrypted using the cached public key of the receiver.
+    /// The text of the message may be empty. Returns an error if there is no shared secret
+    /// and the public key of the receiver is not cached or invalid, or the protocol
+    /// fails to encrypt the message.
+    pub fn create_message(&self, receiver: &str, message: &str) -> Result<Message, String> {
+        let encrypted = self.encrypt_message(receiver, message, MessageType::Message)?;
+        Ok(self.seal(encrypted))
+    }
+
+    /// Creates an encrypted reply to the message.
+    ///
+    /// The reply is sent to the sender of the original message and references it
+    /// by its id (see `Message::get_in_reply_to`).
+    pub fn create_reply(&self, original: &Message, message: &str) -> Result<Message, String> {
+        let mut reply =
+            self.encrypt_message(original.get_sender(), message, MessageType::Message)?;
+        reply.set_in_reply_to(original.get_id());
+        Ok(self.seal(reply))
+    }
+
+    /// Creates an encrypted message signed by the user.
+    ///
+    /// The text of the message is signed using the identity key of the user
+    /// (see `create_identity`) or, if there is no identity, the current private key,
+    /// so the receiver can verify the signature with `verify_message`.
+    ///
+    /// Returns an error if the user has neither an identity nor keys, if the protocol
+    /// does not support signatures, or if the message cannot be created.
+    pub fn create_signed_message(&self, receiver: &str, message: &str) -> Result<Message, String> {
+        let private_key: &T::PrivateKey = match &self.identity {
+            Some((_, identity_key)) => identity_key,
+            None => self
+                .private_key_map
+                .get(&self.session_key)
+                .ok_or_else(|| String::from("keys not created"))?,
+        };
+        let signature = T::sign(message, private_key).map_err(|e| e.to_string())?;
+        let mut signed_message = self.encrypt_message(receiver, message, MessageType::Message)?;
+        signed_message.set_signature(&signature);
+        Ok(self.seal(signed_message))
+    }
+
+    /// Verifies the signature of a decrypted message against the cached identity key
+    /// of its sender or, if the identity is unknown, the cached public key.
+    /// Returns `false` if the message is not signed, the keys of the sender
+    /// are unknown or the signature is invalid.
+    pub fn verify_message(&self, message: &Message) -> bool {
+        let sender = message.get_sender();
+        match (
+            message.get_signature(),
+            self.identity_cache
+                .get(sender)
+                .or_else(|| self.public_key_cache.get(sender)),
+        ) {
+            (Some(signature), Some(pub_key)) => {
+                T::verify(message.get_message(), signature, pub_key).unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    /// Creates an encrypted message with an HMAC-SHA256 authentication tag.
+    ///
+    /// The tag is computed over the encrypted message with the given key, which should be
+    /// shared with the receiver in advance. The receiver checks it with `verify_and_read`.
+    pub fn create_authenticated_message(
+        &self,
+        receiver: &str,
+        message: &str,
+        mac_key: &[u8],
+    ) -> Result<Message, String> {
+        let mut authenticated_message =
+            self.encrypt_message(receiver, message, MessageType::Message)?;
+        authenticated_message.set_mac(mac_key);
+        Ok(self.seal(authenticated_message))
+    }
+
+    /// Checks the authentication tag of the message by its index in the buffer
+    /// and reads the message. The message is decrypted only if the tag is valid.
+    ///
+    /// Returns an error if the index is out of bounds, if the tag is missing or invalid,
+    /// or if the message cannot be decrypted.
+    pub fn verify_and_read(&self, index: usize, mac_key: &[u8]) -> Result<Message, String> {
+        let message = self.check_index(index).map_err(|e| e.to_string())?;
+        if !message.verify_mac(mac_key) {
+            return Err(String::from("authentication failed"));
+        }
+        let message = self.read_message(index);
+        match message.get_decrypt_error() {
+            Some(error) => Err(error.clone()),
+            None => Ok(message),
+        }
+    }
+
+    /// Creates an encrypted message that expires at the given time.
+    ///
+    /// The expiry is encrypted together with the text of the message, so it is not visible
+    /// in the log, and every reader of the buffer (e.g. `read_message`) enforces it.
+    /// Note that the expiry is not authenticated: with block-wise encryption, its blocks
+    /// can be replaced with the blocks of another message to the same receiver.
+    /// Returns an error if the expiry is before the Unix epoch.
+    pub fn create_self_destruct_message(
+        &self,
+        receiver: &str,
+        message: &str,
+        expiry: SystemTime,
+    ) -> Result<Message, String> {
+        let expiry_nanos: u128 = expiry
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| String::from("expiry should not be before the Unix epoch"))?
+            .as_nanos();
+        let body: String = expiry_nanos.to_string() + "|" + message;
+        let encrypted = self.encrypt_message(receiver, &body, MessageType::SelfDestruct)?;
+        Ok(self.seal(encrypted))
+    }
+
+    /// Creates a message with an encrypted binary attachment.
+    ///
+    /// The bytes are encrypted with `EncryptionProtocol::encrypt_byte_blocks`,
+    /// and every block is written in base64. The size of the attachment
+    /// is stored in the message, so that the log shows it instead of the encrypted bytes.
+    /// The attachment should be read with `read_attachment`. Returns an error if the public key
+    /// of the receiver is invalid or the protocol fails to encrypt the bytes.
+    pub fn create_attachment_message(
+        &self,
+        receiver: &str,
+        data: &[u8],
+    ) -> Result<Message, String> {
+        let (session_key, pub_key) = self.cached_key(receiver)?;
+        T::validate_public_key(pub_key).map_err(|e| e.to_string())?;
+        let blocks: Vec<String> = T::encrypt_byte_blocks(data, pub_key)
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|block| STANDARD.encode(block))
+            .collect();
+        let mut encrypted = self.new_message(
+            session_key,
+            receiver,
+            &blocks.join(" "),
+            MessageType::Attachment,
+        );
+        encrypted.set_attachment_size(data.len() as u64);
+        encrypted.set_ttl(self.message_ttl);
+        encrypted.set_digest();
+        Ok(self.seal(encrypted))
+    }
+
+    /// Creates an envelope for several recipients (see `MessageType::Envelope`).
+    ///
+    /// The text is encrypted once with a random symmetric key (an HMAC-SHA256 keystream),
+    /// and the symmetric key is encrypted with `EncryptionProtocol::encrypt_byte_blocks`
+    /// under the cached public key of every recipient. The text of the envelope consists
+    /// of one entry `name:session:key` per recipient (the name and the blocks of the key
+    /// in base64) and the encrypted text. The receiver of the envelope is the sorted list
+    /// of recipients, and the envelope should be sent with `Env::send_to_recipients`.
+    ///
+    /// Returns an error if the list of recipients is empty, the name of a recipient
+    /// contains `", "`, the public key of a recipient is not cached or invalid,
+    /// or the protocol fails to encrypt the key.
+    pub fn create_envelope_message(
+        &self,
+        recipients: &[&str],
+        message: &str,
+    ) -> Result<Message, String> {
+        if recipients.is_empty() {
+            return Err(String::from("list of recipients should not be empty"));
+        }
+        if let Some(name) = recipients.iter().find(|name| name.contains(", ")) {
+            return Err(format!("name '{}' should not contain \", \"", name));
+        }
+        let mut recipients: Vec<&str> = recipients.to_vec();
+        recipients.sort();
+        recipients.dedup();
+
+        let symmetric_key: [u8; 32] = rand::thread_rng().r#gen();
+        let mut entries: Vec<String> = Vec::with_capacity(recipients.len() + 1);
+        for recipient in &recipients {
+            let (session_key, pub_key) = self.cached_key(recipient)?;
+            T::validate_public_key(pub_key).map_err(|e| e.to_string())?;
+            let wrapped: Vec<String> = T::encrypt_byte_blocks(&symmetric_key, pub_key)
+                .map_err(|e| e.to_string())?
+                .iter()
+                .map(|block| STANDARD.encode(block))
+                .collect();
+            entries.push(format!(
+                "{}:{}:{}",
+                STANDARD.encode(recipient),
+                session_key,
+                wrapped.join(",")
+            ));
+        }
+        let encrypted = apply_hmac_keystream(&symmetric_key, message.as_bytes());
+        let tag = hmac_sha256(&symmetric_key, &encrypted);
+        entries.push(STANDARD.encode([&tag[..], &encrypted].concat()));
+
+        let mut envelope = self.new_message(
+            0,
+            &recipients.join(", "),
+            &entries.join(" "),
+            MessageType::Envelope,
+        );
+        envelope.set_digest();
+        Ok(self.seal(envelope))
+    }
+
+    /// Decrypts the entry of the user in the envelope and the text of the envelope.
+    fn open_envelope(&self, envelope: &Message) -> Result<String, String> {
+        if !envelope.verify_digest() {
+            return Err(String::from("integrity check failed"));
+        }
+        let invalid = || String::from("invalid envelope");
+        let mut entries: Vec<&str> = envelope.get_message().split(' ').collect();
+        let sealed = entries.pop().ok_or_else(invalid)?;
+        let name = STANDARD.encode(&self.name);
+        let (session_key, wrapped) = entries
+            .iter()
+            .filter_map(|entry| entry.split_once(':'))
+            .find(|(entry_name, _)| *entry_name == name)
+            .and_then(|(_, rest)| rest.split_once(':'))
+            .ok_or_else(|| String::from("not a recipient of the envelope"))?;
+        let session_key: usize = session_key.parse().map_err(|_| invalid())?;
+        let private_key = self.session_private_key(session_key)?;
+        let blocks: Vec<Vec<u8>> = wrapped
+            .split(',')
+            .map(|block| STANDARD.decode(block))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let symmetric_key =
+            T::decrypt_byte_blocks(&blocks, private_key).map_err(|e| e.to_string())?;
+        if symmetric_key.len() != 32 {
+            return Err(invalid());
+        }
+
+        let sealed = STANDARD.decode(sealed).map_err(|_| invalid())?;
+        if sealed.len() < 32 {
+            return Err(invalid());
+        }
+        let (tag, encrypted) = sealed.split_at(32);
+        if !constant_time_eq(&hmac_sha256(&symmetric_key, encrypted), tag) {
+            return Err(invalid());
+        }
+        String::from_utf8(apply_hmac_keystream(&symmetric_key, encrypted)).map_err(|_| invalid())
+    }
+
+    /// Returns the cached session key and public key of the user. Both keys are taken
+    /// together, so a message always carries the session key of the public key
+    /// that was used to encrypt it.
+    fn cached_key(&self, user_name: &str) -> Result<(usize, &T::PublicKey), String> {
+        let pub_key = self
+            .public_key_cache
+            .get(user_name)
+            .ok_or_else(|| format!("public key of '{}' not found", user_name))?;
+        let session_key = self
+            .session_key_cache
+            .get(user_name)
+            .ok_or_else(|| String::from("receiver's session key not found"))?;
+        Ok((*session_key, pub_key))
+    }
+
+    fn encrypt_message(
+        &self,
+        receiver: &str,
+        message: &str,
+        message_type: MessageType,
+    ) -> Result<Message, String> {
+        #[cfg_attr(not(feature = "compression"), allow(unused_mut))]
+        let mut flags: u8 = 0;
+        #[cfg(feature = "compression")]
+        let compressed_message: String;
+        #[cfg(feature = "compression")]
+        let message: &str = if self.compression && message.len() <= MAX_DECOMPRESSED_SIZE {
+            compressed_message = Self::compress(message);
+            if compressed_message.len() < message.len() {
+                flags |= COMPRESSED_FLAG;
+                &compressed_message
+            } else {
+                message
+            }
+        } else {
+            message
+        };
+        let plaintext: String = String::from(char::from(b'0' + flags)) + message;
+        let mut encrypted = match self.shared_secrets.get(receiver) {
+            Some(secret) => {
+                let mut encrypted =
+                    self.new_message(SHARED_SECRET_SESSION, receiver, "", message_type);
+                let encrypted_message =
+                    Self::encrypt_with_secret(*secret, encrypted.get_nonce(), &plaintext);
+                encrypted.set_message(&encrypted_message);
+                encrypted
+            }
+            None => {
+                let (session_key, pub_key) = self.cached_key(receiver)?;
+                T::validate_public_key(pub_key).map_err(|e| e.to_string())?;
+                let long_message = match self.long_message_threshold {
+                    Some(threshold) if plaintext.len() > threshold => {
+                        T::encrypt_long(&plaintext, pub_key)
+                    }
+                    _ => None,
+                };
+                let encrypted_message: String = match long_message {
+                    Some(encrypted_message) => encrypted_message,
+                    None => T::join_ciphertext(
+                        &T::encrypt_blocks(&plaintext, pub_key)
+                            .map_err(|error| error.to_string())?,
+                    ),
+                };
+                self.new_message(session_key, receiver, &encrypted_message, message_type)
+            }
+        };
+        encrypted.set_ttl(self.message_ttl);
+        encrypted.set_digest();
+        Ok(encrypted)
+    }
+
+    /// Creates a message from the user. The message should be sealed with `seal`
+    /// after all of its fields are set.
+    fn new_message(
+        &self,
+        session_key: usize,
+        receiver: &str,
+        message: &str,
+        message_type: MessageType,
+    ) -> Message {
+        Message::new(&self.name, session_key, receiver, message, message_type)
+    }
+
+    /// Attaches the proof of the sender (see `Message::get_sender_proof`) if the user
+    /// has an identity. The proof covers every field, so it is attached last.
+    fn seal(&self, mut message: Message) -> Message {
+        if let Some((_, identity_key)) = &self.identity
+            && let Ok(proof) = T::sign(&message.proof_input(), identity_key)
+        {
+            message.set_sender_proof(&proof);
+        }
+        message
+    }
+
+    /// Creates a long-term identity key pair for signing messages.
+    ///
+    /// Unlike encryption keys, the identity does not rotate with `create_keys`, so signatures
+    /// stay verifiable after the encryption keys change. The resulting message contains
+    /// the public identity key and should be broadcasted through the environment.
+    pub fn create_identity(&mut self) -> Message {
+        let (public_key, private_key) = T::create_keys();
+        let mes: String = T::to_string(&public_key);
+        self.identity = Some((public_key, private_key));
+        self.seal(self.new_message(0, "", &mes, MessageType::Identity))
+    }
+
+    /// Creates new public/private key pair. If the user already has keys, the new keys
+    /// are of the same kind (see `EncryptionProtocol::create_keys_like`).
+    ///
+    /// Note that the resulting message should be broadcasted to all users
+    /// through the environment in order for the user to be able to receive encrypted messages.
+    pub fn create_keys(&mut self) -> Message {
+        let keys = match &self.public_key {
+            Some(public_key) => T::create_keys_like(public_key),
+            None => T::create_keys(),
+        };
+        self.install_keys(keys)
+    }
+
+    /// Starts a new session with the given public/private key pair
+    /// and returns the message with the public key.
+    pub(crate) fn install_keys(&mut self, keys: (T::PublicKey, T::PrivateKey)) -> Message {
+        let (public_key, private_key) = keys;
+        self.session_key += 1;
+        self.public_key = Some(public_key);
+        self.private_key_map.insert(self.session_key, private_key);
+        self.key_created.insert(self.session_key, SystemTime::now());
+        let mes: String = T::to_string(self.public_key.as_ref().unwrap());
+        self.seal(self.new_message(self.session_key, "", &mes, MessageType::PublicKey))
+    }
+
+    /// Sets the lifetime of the private keys. A private key older than the lifetime
+    /// is expired: messages of its session cannot be decrypted anymore, even if the key
+    /// is still stored (see `prune_expired_keys`). `None` means that the keys
+    /// never expire, which is the default.
+    ///
+    /// Together with key rotation (see `Env::set_rotation_interval`), this models
+    /// forward secrecy: old messages become unreadable once their keys expire.
+    pub fn set_key_lifetime(&mut self, lifetime: Option<Duration>) {
+        self.key_lifetime = lifetime;
+    }
+
+    /// Returns the lifetime of the private keys.
+    pub fn get_key_lifetime(&self) -> Option<Duration> {
+        self.key_lifetime
+    }
+
+    /// Deletes the expired private keys (see `set_key_lifetime`).
+    /// Returns the number of deleted keys.
+    pub fn prune_expired_keys(&mut self) -> usize {
+        let expired: Vec<usize> = self
+            .private_key_map
+            .keys()
+            .copied()
+            .filter(|session_key| self.is_key_expired(*session_key))
+            .collect();
+        for session_key in &expired {
+            self.private_key_map.remove(session_key);
+            self.key_created.remove(session_key);
+        }
+        expired.len()
+    }
+
+    fn is_key_expired(&self, session_key: usize) -> bool {
+        match (self.key_lifetime, self.key_created.get(&session_key)) {
+            (Some(lifetime), Some(created)) => {
+                created.elapsed().is_ok_and(|elapsed| elapsed >= lifetime)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the private key of the session, or an error if the key is not found
+    /// or expired.
+    fn session_private_key(&self, session_key: usize) -> Result<&T::PrivateKey, String> {
+        let private_key = self
+            .private_key_map
+            .get(&session_key)
+            .ok_or_else(|| format!("private key of session {} not found", session_key))?;
+        if self.is_key_expired(session_key) {
+            return Err(format!("private key of session {} expired", session_key));
+        }
+        Ok(private_key)
+    }
+
+    /// Exports the private keys of all sessions, encrypted with a key derived
+    /// from the passphrase (see `import_private_keys`). Returns an error if the protocol
+    /// does not support the conversion of private keys to strings.
+    ///
+    /// The key is derived with PBKDF2-HMAC-SHA256 and a random salt, the keys are
+    /// encrypted with an HMAC-SHA256 keystream and authenticated with HMAC-SHA256.
+    pub fn export_private_keys(&self, passphrase: &str) -> Result<String, String> {
+        let mut sessions: Vec<(&usize, &T::PrivateKey)> = self.private_key_map.iter().collect();
+        sessions.sort_by_key(|(session_key, _)| **session_key);
+        let mut lines: Vec<String> = Vec::with_capacity(sessions.len());
+        for (session_key, private_key) in sessions {
+            lines.push(format!(
+                "{} {}",
+                session_key,
+                T::private_key_to_string(private_key).map_err(|e| e.to_string())?
+            ));
+        }
+
+        let salt: [u8; 16] = rand::thread_rng().r#gen();
+        let (encryption_key, mac_key) = User::<T>::derive_export_keys(passphrase, &salt);
+        let encrypted = apply_hmac_keystream(&encryption_key, lines.join("\n").as_bytes());
+        let tag = hmac_sha256(&mac_key, &[&salt[..], &encrypted].concat());
+        Ok(format!(
+            "{} {} {}",
+            STANDARD.encode(salt),
+            STANDARD.encode(&encrypted),
+            STANDARD.encode(tag)
+        ))
+    }
+
+    /// Imports the private keys exported with `export_private_keys`. The keys replace
+    /// the keys of the same sessions, and new keys are created for later sessions.
+    /// The lifetime of the imported keys (see `set_key_lifetime`) starts at the import.
+    ///
+    /// Returns an error if the passphrase is wrong or the export is malformed;
+    /// in this case, no keys are imported.
+    pub fn import_private_keys(&mut self, exported: &str, passphrase: &str) -> Result<(), String> {
+        let invalid = || String::from("invalid key export");
+        let parts: Vec<Vec<u8>> = exported
+            .split(' ')
+            .map(|part| STANDARD.decode(part))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let [salt, encrypted, tag] = &parts[..] else {
+            return Err(invalid());
+        };
+        let (encryption_key, mac_key) = User::<T>::derive_export_keys(passphrase, salt);
+        let expected_tag = hmac_sha256(&mac_key, &[&salt[..], encrypted].concat());
+        if !constant_time_eq(&expected_tag, tag) {
+            return Err(String::from("wrong passphrase"));
+        }
+
+        let decrypted = String::from_utf8(apply_hmac_keystream(&encryption_key, encrypted))
+            .map_err(|_| invalid())?;
+        let mut keys: Vec<(usize, T::PrivateKey)> = Vec::new();
+        for line in decrypted.lines() {
+            let (session_key, private_key) = line.split_once(' ').ok_or_else(invalid)?;
+            let session_key: usize = session_key.parse().map_err(|_| invalid())?;
+            keys.push((
+                session_key,
+                T::to_private_key(private_key).map_err(|e| e.to_string())?,
+            ));
+        }
+        for (session_key, private_key) in keys {
+            self.session_key = self.session_key.max(session_key);
+            self.private_key_map.insert(session_key, private_key);
+            self.key_created.insert(session_key, SystemTime::now());
+        }
+        Ok(())
+    }
+
+    /// Derives the encryption and authentication keys for exported private keys.
+    fn derive_export_keys(passphrase: &str, salt: &[u8]) -> ([u8; 32], [u8; 32]) {
+        let key = derive_key(passphrase, salt, KEY_EXPORT_ITERATIONS);
+        (
+            hmac_sha256(&key, b"encryption"),
+            hmac_sha256(&key, b"authentication"),
+        )
+    }
+}
+
+impl User<AnyProtocol> {
+    /// Creates new public/private key pair of the given protocol (see `User::create_keys`).
+    /// Later calls of `User::create_keys` (e.g. key rotation in the environment)
+    /// keep the protocol.
+    pub fn create_keys_with(&mut self, kind: ProtocolKind) -> Message {
+        self.install_keys(AnyProtocol::create_keys_for(kind))
+    }
+}
+
+/// Shows the name of the user, the number of buffered messages and known contacts,
+/// and whether the public key is set. Keys are never shown.
+impl<T: EncryptionProtocol> fmt::Display for User<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "name: '{}'; messages: '{}'; known contacts: '{}'; public key: '{}'",
+            self.name,
+            self.message_buffer.len(),
+            self.public_key_cache.len(),
+            if self.public_key.is_some() {
+                "set"
+            } else {
+                "not set"
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::caesar::Caesar;
+    use crate::elgamal::ElGamal;
+    use crate::encryption_protocol::EncryptionProtocol;
+    use crate::message::{Message, MessageBuilder, MessageHeader, MessageId, MessageType};
+    use crate::rsa::RSA;
+    use crate::user::{CacheError, IndexError, User, VerifyError};
+    #[cfg(feature = "compression")]
+    use crate::user::{Engine, MAX_DECOMPRESSED_SIZE, STANDARD};
+    use crate::xor_cipher::XorCipher;
+    use num_bigint::BigUint;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_create_keys() {
+        let mut user: User<RSA> = User::new("Alice");
+        let mes = user.create_keys();
+
+        assert!(user.public_key.is_some());
+        assert!(!user.private_key_map.is_empty());
+        assert_eq!(mes.get_sender(), "Alice");
+        assert_eq!(mes.get_receiver(), "");
+        let is_public_key_type = matches!(mes.get_message_type(), MessageType::PublicKey);
+        assert!(is_public_key_type);
+
+        let (num, exp) = mes.get_message().split_once(' ').unwrap();
+        let n: BigUint = num.parse().unwrap();
+        let public_exp: BigUint = exp.parse().unwrap();
+
+        assert_eq!(user.get_public_key().unwrap().n, n);
+        assert_eq!(user.get_public_key().unwrap().public_exp, public_exp);
+    }
+
+    #[test]
+    fn test_nonexisting_receiver() {
+        let user: User<RSA> = User::new("Alice");
+        assert_eq!(
+            user.create_message("Bob", "Hello, Bob!").err(),
+            Some(String::from("public key of 'Bob' not found"))
+        );
+    }
+
+    #[test]
+    fn test_send_to_myself() {
+        let mut user: User<RSA> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let encrypted_message = user.create_message("Alice", "Hello, me!").unwrap();
+        assert_eq!(encrypted_message.get_sender(), "Alice");
+        assert_eq!(encrypted_message.get_receiver(), "Alice");
+        assert_eq!(encrypted_message.get_session_key(), 1);
+        let is_message_type = matches!(encrypted_message.get_message_type(), MessageType::Message);
+        assert!(is_message_type);
+        let decrypted_message = user.decrypt_message(encrypted_message);
+        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+    }
+
+    #[test]
+    fn test_send_to_myself_unicode() {
+        let mut user: User<RSA> = setup();
+        for text in ["naïve café", "abcdefghijklmnop"] {
+            let encrypted_message = user.create_message("Alice", text).unwrap();
+            user.message_buffer.push(encrypted_message);
+            assert_eq!(user.read_last_message().unwrap().get_message(), text);
+        }
+    }
+
+    #[test]
+    fn test_send_empty_message() {
+        let mut user: User<RSA> = setup();
+        let encrypted_message = user.create_message("Alice", "").unwrap();
+        user.message_buffer.push(encrypted_message);
+        assert_eq!(user.read_last_message().unwrap().get_message(), "");
+    }
+
+    #[test]
+    fn test_change_keys() {
+        let mut user: User<RSA> = User::new("Alice");
+        user.create_keys();
+        assert_eq!(user.session_key, 1);
+        assert_eq!(user.private_key_map.len(), 1);
+        assert!(user.private_key_map.contains_key(&1));
+        assert!(!user.private_key_map.contains_key(&2));
+        user.create_keys();
+        assert_eq!(user.session_key, 2);
+        assert_eq!(user.private_key_map.len(), 2);
+        assert!(user.private_key_map.contains_key(&1));
+        assert!(user.private_key_map.contains_key(&2));
+    }
+
+    #[test]
+    fn test_send_to_myself_and_change_keys() {
+        let mut user: User<RSA> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let encrypted_message = user.create_message("Alice", "Hello, me!").unwrap();
+
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+
+        let decrypted_message = user.decrypt_message(encrypted_message);
+        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+    }
+
+    #[test]
+    fn test_read_last_message() {
+        let mut user: User<RSA> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let encrypted_message = user.create_message("Alice", "Hello, me!").unwrap();
+        assert_eq!(encrypted_message.get_session_key(), 1);
+        user.message_buffer.push(encrypted_message);
+        let decrypted_message = user.read_last_message().unwrap();
+        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let new_encrypted_message = user.create_message("Alice", "Hello, again!").unwrap();
+        assert_eq!(new_encrypted_message.get_session_key(), 2);
+        user.message_buffer.push(new_encrypted_message);
+        let new_decrypted_message = user.read_last_message().unwrap();
+        assert_eq!(new_decrypted_message.get_message(), "Hello, again!");
+    }
+
+    #[test]
+    fn test_read_message() {
+        let mut user: User<RSA> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let encrypted_message = user.create_message("Alice", "Hello, me!").unwrap();
+        user.message_buffer.push(encrypted_message);
+        let decrypted_message = user.read_message(0);
+        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let new_encrypted_message = user.create_message("Alice", "Hello, again!").unwrap();
+        user.message_buffer.push(new_encrypted_message);
+        let new_decrypted_message = user.read_message(1);
+        assert_eq!(new_decrypted_message.get_message(), "Hello, again!");
+        let old_decrypted_message = user.read_message(0);
+        assert_eq!(old_decrypted_message.get_message(), "Hello, me!");
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds")]
+    fn test_read_message_out_of_bounds() {
+        let mut user: User<RSA> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let encrypted_message = user.create_message("Alice", "Hello, me!").unwrap();
+        user.message_buffer.push(encrypted_message);
+        user.read_message(1);
+    }
+
+    fn setup() -> User<RSA> {
+        let mut user: User<RSA> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let encrypted_message = user.create_message("Alice", "Hello, me!").unwrap();
+        user.message_buffer.push(encrypted_message);
+
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let new_encrypted_message = user.create_message("Alice", "Hello, again!").unwrap();
+        user.message_buffer.push(new_encrypted_message);
+
+        user
+    }
+
+    #[test]
+    fn test_export_buffer() {
+        let user: User<RSA> = setup();
+        let file_name = "export_buffer_test.txt";
+        user.export_buffer(file_name).unwrap();
+        let contents = fs::read_to_string(file_name).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("message text: 'Hello, me!'"));
+        assert!(lines[1].contains("message text: 'Hello, again!'"));
+
+        user.export_buffer_encrypted(file_name).unwrap();
+        let contents = fs::read_to_string(file_name).unwrap();
+        fs::remove_file(file_name).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(!contents.contains("Hello"));
+        assert!(contents.contains(user.message_buffer[0].get_message()));
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut user: User<RSA> = setup();
+        let message = MessageBuilder::new()
+            .sender("Bob")
+            .receiver("Alice")
+            .text("12345")
+            .message_type(MessageType::Message)
+            .session_key(42)
+            .build()
+            .unwrap();
+        let timestamp = message.get_timestamp();
+        user.message_buffer.push(message);
+
+        let header = user.peek(2);
+        assert_eq!(
+            header,
+            Ok(MessageHeader {
+                sender: String::from("Bob"),
+                receiver: String::from("Alice"),
+                message_type: MessageType::Message,
+                session_key: 42,
+                timestamp,
+            })
+        );
+        assert_eq!(user.is_read(2), Ok(false));
+        assert!(user.read_message(2).get_decrypt_error().is_some());
+        assert_eq!(
+            user.peek(0).unwrap().session_key,
+            user.message_buffer[0].get_session_key()
+        );
+        assert_eq!(user.peek(3), Err(IndexError { index: 3, len: 3 }));
+    }
+
+    #[test]
+    fn test_sort_by_timestamp() {
+        let mut user: User<RSA> = setup();
+        let mut older = user.create_message("Alice", "Hello, past!").unwrap();
+        older.set_timestamp(UNIX_EPOCH + Duration::from_secs(1));
+        older.set_digest();
+        user.message_buffer.push(older);
+        let mut oldest = user
+            .create_message("Alice", "Hello, distant past!")
+            .unwrap();
+        oldest.set_timestamp(UNIX_EPOCH);
+        oldest.set_digest();
+        user.message_buffer.push(oldest);
+
+        user.sort_by_timestamp();
+        let texts: Vec<String> = user
+            .read_all_messages()
+            .iter()
+            .map(|message| message.get_message().clone())
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Hello, distant past!",
+                "Hello, past!",
+                "Hello, me!",
+                "Hello, again!"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut user: User<RSA> = setup();
+        let key = user.create_keys();
+        user.message_buffer.push(key.clone());
+        user.message_buffer.push(user.message_buffer[0].clone());
+        user.message_buffer.push(key.clone());
+        let mut resent = key.clone();
+        resent.set_timestamp(UNIX_EPOCH);
+        user.message_buffer.push(resent);
+        let mut other_receiver = key;
+        other_receiver.set_receiver("Bob");
+        user.message_buffer.push(other_receiver);
+
+        assert_eq!(user.dedup(), 3);
+        assert_eq!(user.message_buffer.len(), 4);
+        assert_eq!(user.read_message(0).get_message(), "Hello, me!");
+        assert_eq!(user.read_message(1).get_message(), "Hello, again!");
+        assert_eq!(
+            user.message_buffer[2].get_message_type(),
+            MessageType::PublicKey
+        );
+        assert_eq!(user.message_buffer[3].get_receiver(), "Bob");
+        assert_eq!(user.dedup(), 0);
+    }
+
+    #[test]
+    fn test_display() {
+        let user: User<RSA> = User::new("Bob");
+        assert_eq!(
+            user.to_string(),
+            "name: 'Bob'; messages: '0'; known contacts: '0'; public key: 'not set'"
+        );
+
+        let user: User<RSA> = setup();
+        let text = user.to_string();
+        assert_eq!(
+            text,
+            "name: 'Alice'; messages: '2'; known contacts: '1'; public key: 'set'"
+        );
+        assert!(!text.contains(&RSA::to_string(user.public_key.as_ref().unwrap())));
+    }
+
+    #[test]
+    fn test_export_import_private_keys() {
+        let user: User<RSA> = setup();
+        let exported = user.export_private_keys("correct horse").unwrap();
+        assert!(!exported.contains(&user.private_key_map[&1].private_exp.to_string()));
+
+        let mut restored: User<RSA> = User::new("Alice");
+        restored.message_buffer = user.message_buffer.clone();
+        restored
+            .import_private_keys(&exported, "correct horse")
+            .unwrap();
+        assert_eq!(restored.read_message(0).get_message(), "Hello, me!");
+        assert_eq!(restored.read_message(1).get_message(), "Hello, again!");
+        assert_eq!(restored.session_key, 2);
+
+        let export_again = user.export_private_keys("correct horse").unwrap();
+        assert_ne!(exported, export_again);
+    }
+
+    #[test]
+    fn test_import_private_keys_wrong_passphrase() {
+        let user: User<RSA> = setup();
+        let exported = user.export_private_keys("correct horse").unwrap();
+
+        let mut restored: User<RSA> = User::new("Alice");
+        assert_eq!(
+            restored.import_private_keys(&exported, "battery staple"),
+            Err(String::from("wrong passphrase"))
+        );
+        assert!(restored.private_key_map.is_empty());
+        assert_eq!(
+            restored.import_private_keys("not an export", "correct horse"),
+            Err(String::from("invalid key export"))
+        );
+    }
+
+    #[test]
+    fn test_key_lifetime() {
+        let mut user: User<RSA> = setup();
+        user.set_key_lifetime(Some(Duration::from_secs(60)));
+        assert_eq!(user.read_message(0).get_message(), "Hello, me!");
+        assert_eq!(user.prune_expired_keys(), 0);
+
+        user.key_created
+            .insert(1, SystemTime::now() - Duration::from_secs(120));
+        let expired = user.read_message(0);
+        assert_eq!(
+            expired.get_decrypt_error(),
+            Some(&String::from("private key of session 1 expired"))
+        );
+        assert_eq!(user.read_message(1).get_message(), "Hello, again!");
+
+        user.set_key_lifetime(None);
+        assert_eq!(user.read_message(0).get_message(), "Hello, me!");
+        user.set_key_lifetime(Some(Duration::from_secs(60)));
+        assert_eq!(user.prune_expired_keys(), 1);
+        assert!(!user.private_key_map.contains_key(&1));
+        assert_eq!(
+            user.read_message(0).get_decrypt_error(),
+            Some(&String::from("private key of session 1 not found"))
+        );
+    }
+
+    #[test]
+    fn test_envelope_elgamal() {
+        let mut user: User<ElGamal> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
             .insert("Alice".to_string(), user.public_key.clone().unwrap());
         user.session_key_cache
             .insert("Alice".to_string(), user.session_key);
-        let new_encrypted_message = user.create_message("Alice", "Hello, again!");
-        user.message_buffer.push(new_encrypted_message);
-
-        user
+        for _ in 0..8 {
+            let envelope = user
+                .create_envelope_message(&["Alice"], "Hello, me!")
+                .unwrap();
+            user.message_buffer.push(envelope);
+            assert_eq!(
+                user.read_last_message().unwrap().get_message(),
+                "Hello, me!"
+            );
+        }
+        assert_eq!(
+            user.create_envelope_message(&["Alice", "Bob, Carol"], "Hi!")
+                .err(),
+            Some(String::from("name 'Bob, Carol' should not contain \", \""))
+        );
+        assert_eq!(
+            user.create_envelope_message(&["Bob"], "Hello, Bob!").err(),
+            Some(String::from("public key of 'Bob' not found"))
+        );
     }
 
     #[test]
     fn test_read_all_messages() {
         let mut user: User<RSA> = setup();
-        let another_encrypted_message = user.create_message("Alice", "Hello, there!");
+        let another_encrypted_message = user.create_message("Alice", "Hello, there!").unwrap();
         user.message_buffer.push(another_encrypted_message);
         let decrypted_messages = user.read_all_messages();
         assert_eq!(decrypted_messages.len(), 3);
@@ -356,11 +2130,11 @@ mod tests {
         let mut user: User<RSA> = setup();
 
         assert_eq!(user.message_buffer.len(), 2);
-        let decrypted_message = user.read_last_message();
+        let decrypted_message = user.read_last_message().unwrap();
         assert_eq!(decrypted_message.get_message(), "Hello, again!");
         user.delete_last_message();
         assert_eq!(user.message_buffer.len(), 1);
-        let old_decrypted_message = user.read_last_message();
+        let old_decrypted_message = user.read_last_message().unwrap();
         assert_eq!(old_decrypted_message.get_message(), "Hello, me!");
     }
 
@@ -386,11 +2160,440 @@ mod tests {
             .insert("Alice".to_string(), user.public_key.clone().unwrap());
         user.session_key_cache
             .insert("Alice".to_string(), user.session_key);
-        let encrypted_message = user.create_message("Alice", "Hello, me!");
+        let encrypted_message = user.create_message("Alice", "Hello, me!").unwrap();
         user.message_buffer.push(encrypted_message);
         user.delete_message(1);
     }
 
+    #[test]
+    fn test_approx_memory_bytes() {
+        let mut user: User<RSA> = setup();
+        let old_estimate = user.approx_memory_bytes();
+        let large_message = user.create_message("Alice", &"a".repeat(1000)).unwrap();
+        user.message_buffer.push(large_message);
+        assert!(user.approx_memory_bytes() > old_estimate + 1000);
+    }
+
+    #[test]
+    fn test_on_overflow() {
+        let mut user: User<RSA> = setup();
+        let first_id = user.message_buffer[0].get_id();
+        let evicted: Arc<Mutex<Vec<MessageId>>> = Arc::new(Mutex::new(Vec::new()));
+        let evicted_clone = Arc::clone(&evicted);
+        user.delete_last_message();
+        user.set_capacity(Some(1));
+        user.on_overflow(Box::new(move |message| {
+            evicted_clone.lock().unwrap().push(message.get_id())
+        }));
+
+        let message = user.create_message("Alice", "Hello, there!").unwrap();
+        user.receive_message(message);
+        assert_eq!(user.message_buffer.len(), 1);
+        assert_eq!(
+            user.read_last_message().unwrap().get_message(),
+            "Hello, there!"
+        );
+        assert_eq!(*evicted.lock().unwrap(), vec![first_id]);
+    }
+
+    #[test]
+    fn test_validate_caches() {
+        let mut user: User<RSA> = setup();
+        assert_eq!(user.validate_caches(), Ok(()));
+
+        user.session_key_cache.insert("Bob".to_string(), 1);
+        assert_eq!(
+            user.validate_caches(),
+            Err(CacheError::MissingPublicKey("Bob".to_string()))
+        );
+
+        user.session_key_cache.remove("Alice");
+        user.session_key_cache.remove("Bob");
+        assert_eq!(
+            user.validate_caches(),
+            Err(CacheError::MissingSessionKey("Alice".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_conversation_counts() {
+        let mut user: User<RSA> = setup();
+        let key = user.create_keys();
+        user.message_buffer.push(key);
+        user.message_buffer
+            .push(Message::new("Bob", 1, "Alice", "123", MessageType::Message));
+
+        let counts = user.conversation_counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["Alice"], 2);
+        assert_eq!(counts["Bob"], 1);
+    }
+
+    #[test]
+    fn test_self_destruct_message() {
+        let mut user: User<RSA> = setup();
+        let expiry = SystemTime::now() + Duration::from_secs(3600);
+        let message = user
+            .create_self_destruct_message("Alice", "Hello, me!", expiry)
+            .unwrap();
+        user.message_buffer.push(message.clone());
+        let decrypted_message = user.read_self_destruct_message(2).unwrap();
+        assert_eq!(decrypted_message.get_message(), "Hello, me!");
+        assert_eq!(user.read_message(2).get_message(), "Hello, me!");
+
+        let expired = user
+            .create_self_destruct_message("Alice", "Too late!", UNIX_EPOCH)
+            .unwrap();
+        user.message_buffer.push(expired.clone());
+        assert_eq!(
+            user.read_self_destruct_message(3).err(),
+            Some(String::from("message expired"))
+        );
+        let read = user.read_message(3);
+        assert_eq!(
+            read.get_decrypt_error(),
+            Some(&String::from("message expired"))
+        );
+        assert_eq!(read.get_message(), expired.get_message());
+        let self_destruct = user.messages_of_type(MessageType::SelfDestruct);
+        assert_eq!(self_destruct[0].get_message(), "Hello, me!");
+        assert_eq!(
+            self_destruct[1].get_decrypt_error(),
+            Some(&String::from("message expired"))
+        );
+        assert_eq!(
+            user.create_self_destruct_message(
+                "Alice",
+                "Hello, me!",
+                UNIX_EPOCH - Duration::from_secs(1)
+            )
+            .err(),
+            Some(String::from("expiry should not be before the Unix epoch"))
+        );
+
+        let mut tampered_text: Vec<char> = message.get_message().chars().collect();
+        tampered_text[0] = if tampered_text[0] == '9' {
+            '1'
+        } else {
+            (tampered_text[0] as u8 + 1) as char
+        };
+        let mut tampered = message.clone();
+        tampered.set_message(&tampered_text.iter().collect::<String>());
+        user.message_buffer.push(tampered.clone());
+        assert_eq!(
+            user.read_self_destruct_message(4).err(),
+            Some(String::from("integrity check failed"))
+        );
+        tampered.set_digest();
+        user.message_buffer.push(tampered);
+        // The tampered block decrypts to random text, so the error depends on
+        // whether the header, the flags or the expiry breaks first.
+        assert!(user.read_self_destruct_message(5).is_err());
+
+        // The expiry does not fit into 64-bit nanoseconds.
+        let far_expiry = UNIX_EPOCH + Duration::from_secs(20_000_000_000);
+        let message = user
+            .create_self_destruct_message("Alice", "See you!", far_expiry)
+            .unwrap();
+        user.message_buffer.push(message);
+        assert_eq!(
+            user.read_self_destruct_message(6).unwrap().get_message(),
+            "See you!"
+        );
+        assert_eq!(
+            user.read_self_destruct_message(7).err(),
+            Some(String::from(
+                "index 7 is out of bounds for buffer of length 7"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_attachment_message() {
+        let mut user: User<RSA> = setup();
+        let data: Vec<u8> = vec![
+            0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, b'I', b'H',
+            b'D', b'R', 0x00, 0x00, 0x00, 0x01, 0xff, 0xfe, 0x00, 0x00,
+        ];
+        let message = user.create_attachment_message("Alice", &data).unwrap();
+        assert_eq!(message.get_attachment_size(), Some(data.len() as u64));
+        assert!(
+            message
+                .to_string()
+                .contains("message type: 'Attachment'; message text: '<24 bytes>'")
+        );
+        user.message_buffer.push(message);
+        assert_eq!(user.read_attachment(2).unwrap(), data);
+        assert_eq!(
+            user.read_attachment(0).err(),
+            Some(String::from("not an attachment"))
+        );
+
+        let empty = user.create_attachment_message("Alice", &[]).unwrap();
+        user.message_buffer.push(empty);
+        assert_eq!(user.read_attachment(3).unwrap(), Vec::<u8>::new());
+        assert_eq!(
+            user.read_attachment(4).err(),
+            Some(String::from(
+                "index 4 is out of bounds for buffer of length 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_attachment_elgamal() {
+        let mut user: User<ElGamal> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let data: Vec<u8> = vec![1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0xff, 0x00];
+        let message = user.create_attachment_message("Alice", &data).unwrap();
+        user.message_buffer.push(message);
+        assert_eq!(user.read_attachment(0).unwrap(), data);
+    }
+
+    #[test]
+    fn test_attachment_xor_cipher() {
+        let mut user: User<XorCipher> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let data: Vec<u8> = (0..40).collect();
+        let message = user.create_attachment_message("Alice", &data).unwrap();
+        // The key stream continues across the whole attachment.
+        assert!(!message.get_message().contains(' '));
+        user.message_buffer.push(message);
+        assert_eq!(user.read_attachment(0).unwrap(), data);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression() {
+        let mut user: User<RSA> = setup();
+        let text: String = "Hello, me! ".repeat(93);
+        assert!(text.len() > 1000);
+        let plain = user.create_message("Alice", &text).unwrap();
+        user.set_compression(true);
+        let compressed = user.create_message("Alice", &text).unwrap();
+        assert!(compressed.get_message().len() * 4 < plain.get_message().len());
+        user.message_buffer.push(compressed);
+        assert_eq!(user.read_message(2).get_message(), &text);
+
+        let short = user.create_message("Alice", "Hi!").unwrap();
+        user.message_buffer.push(short);
+        assert_eq!(user.read_message(3).get_message(), "Hi!");
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompression_limit() {
+        let bomb = miniz_oxide::deflate::compress_to_vec(&vec![0; MAX_DECOMPRESSED_SIZE + 1], 6);
+        assert!(bomb.len() < 10_000);
+        assert_eq!(
+            User::<RSA>::decompress(&STANDARD.encode(bomb)),
+            Err(String::from("invalid compressed message"))
+        );
+    }
+
+    #[test]
+    fn test_plaintext_header() {
+        let mut user: User<RSA> = setup();
+        for text in ["\u{1}masked?", "\u{2}compressed?", "0", "3abc"] {
+            let message = user.create_message("Alice", text).unwrap();
+            user.message_buffer.push(message);
+            assert_eq!(user.read_last_message().unwrap().get_message(), text);
+        }
+        assert_eq!(
+            User::<RSA>::decode_plaintext("2ab"),
+            Err(String::from("invalid message header"))
+        );
+        assert_eq!(
+            User::<RSA>::decode_plaintext("x"),
+            Err(String::from("invalid message header"))
+        );
+    }
+
+    #[test]
+    fn test_key_exchange() {
+        let mut alice: User<RSA> = User::new("Alice");
+        let mut bob: User<RSA> = User::new("Bob");
+        let request = alice.initiate_exchange("Bob");
+        assert!(matches!(
+            request.get_message_type(),
+            MessageType::KeyExchange
+        ));
+        let reply = bob.complete_exchange(&request).unwrap().unwrap();
+        assert!(alice.complete_exchange(&reply).unwrap().is_none());
+
+        assert!(alice.get_shared_secret("Bob").is_some());
+        assert_eq!(
+            alice.get_shared_secret("Bob"),
+            bob.get_shared_secret("Alice")
+        );
+    }
+
+    #[test]
+    fn test_key_exchange_invalid() {
+        let mut alice: User<RSA> = User::new("Alice");
+        let mut bob: User<RSA> = User::new("Bob");
+        for text in ["", "1 2", "x y z", "23 5 7 9", "3 2 2", "23 1 7", "23 5 23"] {
+            let message = Message::new("Alice", 0, "Bob", text, MessageType::KeyExchange);
+            assert!(bob.complete_exchange(&message).is_err());
+        }
+        assert!(bob.get_shared_secret("Alice").is_none());
+
+        let request = alice.initiate_exchange("Bob");
+        let p: u128 = request
+            .get_message()
+            .split(' ')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let forged = Message::new(
+            "Bob",
+            0,
+            "Alice",
+            &format!("{} 2 2", p + 2),
+            MessageType::KeyExchange,
+        );
+        assert!(alice.complete_exchange(&forged).is_err());
+        let reply = bob.complete_exchange(&request).unwrap().unwrap();
+        assert!(alice.complete_exchange(&reply).unwrap().is_none());
+        assert_eq!(
+            alice.get_shared_secret("Bob"),
+            bob.get_shared_secret("Alice")
+        );
+    }
+
+    #[test]
+    fn test_shared_secret_message() {
+        let mut alice: User<RSA> = User::new("Alice");
+        let mut bob: User<RSA> = User::new("Bob");
+        let request = alice.initiate_exchange("Bob");
+        let reply = bob.complete_exchange(&request).unwrap().unwrap();
+        alice.complete_exchange(&reply).unwrap();
+
+        // No public key is needed once the secret is negotiated.
+        let first = alice.create_message("Bob", "Hello, Bob!").unwrap();
+        let second = alice.create_message("Bob", "Hello, Bob!").unwrap();
+        assert_eq!(first.get_session_key(), 0);
+        assert_ne!(first.get_message(), second.get_message());
+        bob.message_buffer.push(first.clone());
+        bob.message_buffer.push(second);
+        assert_eq!(bob.read_message(0).get_message(), "Hello, Bob!");
+        assert_eq!(bob.read_message(1).get_message(), "Hello, Bob!");
+
+        let mut eve: User<RSA> = User::new("Eve");
+        eve.message_buffer.push(first);
+        assert_eq!(
+            eve.read_message(0).get_decrypt_error(),
+            Some(&String::from("shared secret with 'Alice' not found"))
+        );
+    }
+
+    #[test]
+    fn test_long_message_threshold() {
+        let mut user: User<RSA> = setup();
+        user.set_long_message_threshold(Some(100));
+        let long_text = "abcdefghij".repeat(50);
+        let short_message = user.create_message("Alice", "Hello, me!").unwrap();
+        let long_message = user.create_message("Alice", &long_text).unwrap();
+        assert!(!short_message.get_message().contains('|'));
+        assert!(long_message.get_message().contains('|'));
+        user.message_buffer.push(long_message);
+        assert_eq!(user.read_last_message().unwrap().get_message(), &long_text);
+    }
+
+    #[test]
+    fn test_signed_message() {
+        let mut user: User<RSA> = setup();
+        let signed_message = user.create_signed_message("Alice", "Hello, me!").unwrap();
+        assert!(signed_message.get_signature().is_some());
+        user.message_buffer.push(signed_message);
+        let decrypted_message = user.read_last_message().unwrap();
+        assert!(user.verify_message(&decrypted_message));
+
+        let mut tampered_message = decrypted_message.clone();
+        tampered_message.set_message("Hello, you!");
+        assert!(!user.verify_message(&tampered_message));
+
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        assert!(!user.verify_message(&decrypted_message));
+
+        let unsigned_message = user.read_message(0);
+        assert!(!user.verify_message(&unsigned_message));
+
+        let user: User<RSA> = User::new("Bob");
+        assert_eq!(
+            user.create_signed_message("Alice", "Hello!").err(),
+            Some(String::from("keys not created"))
+        );
+        let mut user: User<Caesar> = User::new("Alice");
+        user.create_keys();
+        user.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        user.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        assert_eq!(
+            user.create_signed_message("Alice", "Hello, me!").err(),
+            Some(String::from("signing is not supported"))
+        );
+    }
+
+    #[test]
+    fn test_read_message_verified() {
+        let mut user: User<RSA> = setup();
+        user.create_identity();
+        let identity = user.identity.as_ref().unwrap().0.clone();
+        user.identity_cache.insert("Alice".to_string(), identity);
+        let signed_message = user.create_signed_message("Alice", "Hello, me!").unwrap();
+        user.message_buffer.push(signed_message);
+        assert_eq!(
+            user.read_message_verified(2).unwrap().get_message(),
+            "Hello, me!"
+        );
+        assert_eq!(user.read_message_verified(0), Err(VerifyError::Unsigned));
+
+        let mut eve: User<RSA> = User::new("Eve");
+        eve.create_identity();
+        eve.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        eve.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let eve_message = eve.create_signed_message("Alice", "I am Alice!").unwrap();
+        user.message_buffer.push(eve_message.clone());
+        assert_eq!(
+            user.read_message_verified(3),
+            Err(VerifyError::UnknownIdentity(String::from("Eve")))
+        );
+
+        let mut spoofed = Message::new(
+            "Alice",
+            user.session_key,
+            "Alice",
+            eve_message.get_message(),
+            MessageType::Message,
+        );
+        spoofed.set_signature(eve_message.get_signature().unwrap());
+        spoofed.set_digest();
+        user.message_buffer.push(spoofed);
+        assert_eq!(
+            user.read_message_verified(4),
+            Err(VerifyError::InvalidSignature)
+        );
+        assert_eq!(
+            user.read_message_verified(5),
+            Err(VerifyError::InvalidIndex(IndexError { index: 5, len: 5 }))
+        );
+    }
+
     #[test]
     fn test_delete_all_messages() {
         let mut user: User<RSA> = setup();
@@ -399,4 +2602,402 @@ mod tests {
         user.delete_all_messages();
         assert_eq!(user.message_buffer.len(), 0);
     }
+
+    #[test]
+    fn test_contact_fingerprint() {
+        let user: User<RSA> = setup();
+        assert_eq!(
+            user.contact_fingerprint("Alice"),
+            Some(RSA::fingerprint(user.public_key.as_ref().unwrap()))
+        );
+        assert_eq!(user.contact_fingerprint("Bob"), None);
+    }
+
+    #[test]
+    fn test_create_message_invalid_key() {
+        let mut user: User<RSA> = setup();
+        user.public_key_cache
+            .insert("Mallory".to_string(), RSA::to_public_key("3233 1").unwrap());
+        user.session_key_cache.insert("Mallory".to_string(), 1);
+
+        assert_eq!(
+            user.create_message("Mallory", "Hello, Mallory!").err(),
+            Some(String::from("invalid public key: modulus is too small"))
+        );
+        assert!(
+            user.create_signed_message("Mallory", "Hello, Mallory!")
+                .is_err()
+        );
+        assert!(user.create_message("Alice", "Hello, me!").is_ok());
+    }
+
+    #[test]
+    fn test_messages_from() {
+        let mut user: User<RSA> = setup();
+        let mut bob: User<RSA> = User::new("Bob");
+        bob.public_key_cache
+            .insert("Alice".to_string(), user.public_key.clone().unwrap());
+        bob.session_key_cache
+            .insert("Alice".to_string(), user.session_key);
+        let bob_key = bob.create_keys();
+        user.message_buffer.push(bob_key);
+        let bob_message = bob.create_message("Alice", "Hello, Alice!").unwrap();
+        user.message_buffer.push(bob_message);
+        let alice_message = user.create_message("Alice", "Hello, me again!").unwrap();
+        user.message_buffer.push(alice_message);
+
+        let from_bob = user.messages_from("Bob", false);
+        assert_eq!(from_bob.len(), 1);
+        assert_eq!(from_bob[0].get_message(), "Hello, Alice!");
+        let from_bob = user.messages_from("Bob", true);
+        assert_eq!(from_bob.len(), 2);
+        assert!(matches!(
+            from_bob[0].get_message_type(),
+            MessageType::PublicKey
+        ));
+
+        let texts: Vec<String> = user
+            .messages_from("Alice", false)
+            .iter()
+            .map(|message| message.get_message().clone())
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["Hello, me!", "Hello, again!", "Hello, me again!"]
+        );
+        assert!(user.messages_from("Carol", true).is_empty());
+    }
+
+    #[test]
+    fn test_messages_of_type() {
+        let mut user: User<RSA> = setup();
+        let key = user.create_keys();
+        user.message_buffer.push(key);
+
+        let messages = user.messages_of_type(MessageType::Message);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].get_message(), "Hello, again!");
+        assert_eq!(user.messages_of_type(MessageType::PublicKey).len(), 1);
+        assert!(user.messages_of_type(MessageType::KeyExchange).is_empty());
+    }
+
+    #[test]
+    fn test_messages_between() {
+        let mut user: User<RSA> = setup();
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        for (index, message) in user.message_buffer.iter_mut().enumerate() {
+            message.set_timestamp(start + Duration::from_secs(10 * index as u64));
+            message.set_digest();
+        }
+        let end = start + Duration::from_secs(10);
+
+        assert_eq!(user.messages_between(start, end).len(), 2);
+        let first = user.messages_between(start, start);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].get_message(), "Hello, me!");
+        let last = user.messages_between(end, end + Duration::from_secs(1));
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].get_message(), "Hello, again!");
+        assert!(
+            user.messages_between(
+                start + Duration::from_nanos(1),
+                end - Duration::from_nanos(1)
+            )
+            .is_empty()
+        );
+        assert!(user.messages_between(end, start).is_empty());
+    }
+
+    #[test]
+    fn test_unread_count() {
+        let mut user: User<RSA> = setup();
+        assert_eq!(user.unread_count(), 2);
+        assert_eq!(user.is_read(1), Ok(false));
+
+        user.read_last_message().unwrap();
+        assert_eq!(user.is_read(1), Ok(true));
+        assert_eq!(user.unread_count(), 1);
+        user.read_last_message().unwrap();
+        assert_eq!(user.unread_count(), 1);
+
+        let new_message = user.create_message("Alice", "Hello, there!").unwrap();
+        user.receive_message(new_message);
+        assert_eq!(user.unread_count(), 2);
+        assert_eq!(user.mark_read(0), Ok(()));
+        assert_eq!(user.unread_count(), 1);
+        assert_eq!(user.mark_read(3), Err(IndexError { index: 3, len: 3 }));
+        assert_eq!(user.is_read(3), Err(IndexError { index: 3, len: 3 }));
+
+        user.message_buffer.swap(0, 2);
+        assert_eq!(user.is_read(2), Ok(true));
+        assert_eq!(user.is_read(0), Ok(false));
+        user.delete_message(0);
+        assert_eq!(user.unread_count(), 0);
+        user.delete_message(0);
+        assert_eq!(user.is_read(0), Ok(true));
+
+        let snapshot = user.snapshot();
+        assert_eq!(snapshot.is_read(0), Ok(true));
+        user.delete_all_messages();
+        assert!(user.read_ids.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_try_read_message() {
+        let mut user: User<RSA> = setup();
+        assert_eq!(
+            user.try_read_message(1).map(|m| m.get_message().clone()),
+            Some(String::from("Hello, again!"))
+        );
+        assert!(user.try_read_message(2).is_none());
+
+        user.delete_all_messages();
+        assert!(user.try_read_message(0).is_none());
+        assert!(user.read_last_message().is_none());
+    }
+
+    #[test]
+    fn test_try_delete_message() {
+        let mut user: User<RSA> = setup();
+        assert_eq!(
+            user.try_delete_message(2),
+            Err(IndexError { index: 2, len: 2 })
+        );
+        assert_eq!(user.try_delete_message(0), Ok(()));
+        assert_eq!(user.message_buffer.len(), 1);
+        assert_eq!(
+            user.read_last_message().unwrap().get_message(),
+            "Hello, again!"
+        );
+
+        user.delete_all_messages();
+        assert_eq!(
+            user.try_delete_message(0),
+            Err(IndexError { index: 0, len: 0 })
+        );
+    }
+
+    #[test]
+    fn test_create_message_missing_session_key() {
+        let mut user: User<RSA> = setup();
+        user.session_key_cache.remove("Alice");
+        assert_eq!(
+            user.create_message("Alice", "Hello, me!").err(),
+            Some(String::from("receiver's session key not found"))
+        );
+    }
+
+    #[test]
+    fn test_read_message_missing_private_key() {
+        let mut user: User<RSA> = setup();
+        user.private_key_map.remove(&1);
+
+        let message = user.read_message(0);
+        assert_eq!(
+            message.get_decrypt_error(),
+            Some(&String::from("private key of session 1 not found"))
+        );
+        assert_eq!(message.get_message(), user.message_buffer[0].get_message());
+        let message = user.read_last_message().unwrap();
+        assert!(message.get_decrypt_error().is_none());
+        assert_eq!(message.get_message(), "Hello, again!");
+
+        let expiry = SystemTime::now() + Duration::from_secs(60);
+        let self_destruct = user
+            .create_self_destruct_message("Alice", "Hello, me!", expiry)
+            .unwrap();
+        user.message_buffer.push(self_destruct);
+        user.private_key_map.remove(&2);
+        assert_eq!(
+            user.read_self_destruct_message(2).err(),
+            Some(String::from("private key of session 2 not found"))
+        );
+    }
+
+    #[test]
+    fn test_read_message_invalid_ciphertext() {
+        let mut user: User<RSA> = setup();
+        let mut message = user.create_message("Alice", "Hello, me!").unwrap();
+        message.set_message("12:5 abc");
+        message.set_digest();
+        user.message_buffer.push(message);
+
+        let message = user.read_last_message().unwrap();
+        assert_eq!(
+            message.get_decrypt_error(),
+            Some(&String::from("invalid ciphertext: expected \"c:l\""))
+        );
+        assert_eq!(message.get_message(), "12:5 abc");
+    }
+
+    #[test]
+    fn test_message_digest() {
+        let mut user: User<RSA> = setup();
+        let message = user.create_message("Alice", "Hello, me!").unwrap();
+        assert_eq!(message.get_digest().map(|digest| digest.len()), Some(64));
+        assert!(message.verify_digest());
+
+        let mut tampered = message.clone();
+        let mut text = tampered.get_message().clone();
+        text.insert(0, '1');
+        tampered.set_message(&text);
+        assert!(!tampered.verify_digest());
+        user.message_buffer.push(tampered);
+        assert_eq!(
+            user.read_last_message().unwrap().get_decrypt_error(),
+            Some(&String::from("integrity check failed"))
+        );
+
+        let stripped = Message::new(
+            message.get_sender(),
+            message.get_session_key(),
+            message.get_receiver(),
+            message.get_message(),
+            MessageType::Message,
+        );
+        assert!(!stripped.verify_digest());
+        user.message_buffer.push(stripped);
+        assert_eq!(
+            user.read_last_message().unwrap().get_decrypt_error(),
+            Some(&String::from("integrity check failed"))
+        );
+
+        user.message_buffer.push(message);
+        let message = user.read_last_message().unwrap();
+        assert!(message.get_decrypt_error().is_none());
+        assert_eq!(message.get_message(), "Hello, me!");
+    }
+
+    #[test]
+    fn test_authenticated_message() {
+        let mut user: User<RSA> = setup();
+        let mac_key = b"shared mac key";
+        let message = user
+            .create_authenticated_message("Alice", "Hello, me!", mac_key)
+            .unwrap();
+        assert_eq!(message.get_mac().map(|mac| mac.len()), Some(64));
+        user.message_buffer.push(message.clone());
+        let read_message = user.verify_and_read(2, mac_key).unwrap();
+        assert_eq!(read_message.get_message(), "Hello, me!");
+
+        assert_eq!(
+            user.verify_and_read(2, b"wrong mac key").err(),
+            Some(String::from("authentication failed"))
+        );
+        assert_eq!(
+            user.verify_and_read(0, mac_key).err(),
+            Some(String::from("authentication failed"))
+        );
+
+        // Copies of a message share its read flag, so a fresh message is tampered.
+        let message = user
+            .create_authenticated_message("Alice", "Hello, me!", mac_key)
+            .unwrap();
+        let mut tampered_text: Vec<u8> = message.get_message().bytes().collect();
+        tampered_text[0] = if tampered_text[0] == b'9' {
+            b'1'
+        } else {
+            tampered_text[0] + 1
+        };
+        let mut tampered = message.clone();
+        tampered.set_message(&String::from_utf8(tampered_text).unwrap());
+        user.message_buffer.push(tampered);
+        assert_eq!(
+            user.verify_and_read(3, mac_key).err(),
+            Some(String::from("authentication failed"))
+        );
+        assert_eq!(user.is_read(3), Ok(false));
+        assert_eq!(
+            user.verify_and_read(4, mac_key).err(),
+            Some(String::from(
+                "index 4 is out of bounds for buffer of length 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_message_ttl() {
+        let mut user: User<RSA> = setup();
+        assert!(!user.message_buffer[0].is_expired());
+
+        user.set_message_ttl(Some(Duration::from_secs(60)));
+        let message = user.create_message("Alice", "Hello, me!").unwrap();
+        assert_eq!(message.get_ttl(), Some(Duration::from_secs(60)));
+        assert!(message.verify_digest());
+        assert!(!message.is_expired());
+        user.message_buffer.push(message.clone());
+        assert_eq!(user.read_message(2).get_message(), "Hello, me!");
+
+        let mut old_message = message.clone();
+        old_message.set_timestamp(SystemTime::now() - Duration::from_secs(61));
+        assert!(old_message.is_expired());
+        user.message_buffer.push(old_message);
+        assert_eq!(
+            user.read_message(3).get_decrypt_error(),
+            Some(&String::from("message expired"))
+        );
+
+        let extended = message.clone().with_ttl(Duration::from_secs(3600));
+        assert!(!extended.verify_digest());
+        user.message_buffer.push(extended);
+        assert_eq!(
+            user.read_last_message().unwrap().get_decrypt_error(),
+            Some(&String::from("integrity check failed"))
+        );
+        user.delete_last_message();
+
+        let forever = message.clone().with_ttl(Duration::MAX);
+        assert!(!forever.is_expired());
+
+        user.set_message_ttl(Some(Duration::ZERO));
+        let instant = user.create_message("Alice", "Gone!").unwrap();
+        assert!(instant.is_expired());
+        user.message_buffer.push(instant);
+        assert!(
+            user.read_last_message()
+                .unwrap()
+                .get_decrypt_error()
+                .is_some()
+        );
+
+        assert_eq!(user.purge_expired(), 2);
+        assert_eq!(user.message_buffer.len(), 3);
+        assert_eq!(user.purge_expired(), 0);
+    }
+
+    #[test]
+    fn test_create_reply() {
+        let mut user: User<RSA> = setup();
+        let original = user.read_message(0);
+        assert_eq!(original.get_in_reply_to(), None);
+
+        let reply = user.create_reply(&original, "Hello, yourself!").unwrap();
+        assert_eq!(reply.get_in_reply_to(), Some(original.get_id()));
+        assert_eq!(reply.get_receiver(), "Alice");
+        user.message_buffer.push(reply);
+        assert_eq!(
+            user.read_last_message().unwrap().get_message(),
+            "Hello, yourself!"
+        );
+    }
+
+    #[test]
+    fn test_thread_of() {
+        let mut user: User<RSA> = setup();
+        let first = user.read_message(1);
+        let second = user.create_reply(&first, "Second").unwrap();
+        user.message_buffer.push(second.clone());
+        let third = user.create_reply(&second, "Third").unwrap();
+        user.message_buffer.push(third.clone());
+
+        let texts: Vec<String> = user
+            .thread_of(third.get_id())
+            .iter()
+            .map(|message| message.get_message().clone())
+            .collect();
+        assert_eq!(texts, vec!["Hello, again!", "Second", "Third"]);
+        assert_eq!(user.thread_of(second.get_id()).len(), 2);
+        assert_eq!(user.thread_of(first.get_id()).len(), 1);
+        assert!(user.thread_of(MessageId::MAX).is_empty());
+    }
 }
@@ -0,0 +1,261 @@
+//! Implementaion of Vigenère cipher
+//!
+//! This module contains the implementation of the trait `EncryptionProtocol`.
+//! Vigenère cipher is not secure and is intended only for educational purposes.
+use crate::encryption_protocol::{EncryptionProtocol, ProtocolError, split_into_blocks};
+use rand::Rng;
+
+/// Default length of the keyword.
+pub const DEFAULT_KEYWORD_LENGTH: usize = 8;
+
+/// Struct for public key in Vigenère cipher.
+///
+/// The key consists of a keyword of uppercase letters `A-Z`.
+#[derive(Clone)]
+pub struct PublicKey {
+    pub(crate) keyword: String,
+}
+
+/// Struct for private key in Vigenère cipher.
+///
+/// The key consists of a keyword of uppercase letters `A-Z`.
+/// Note that the private key is equal to the public key.
+#[derive(Clone)]
+pub struct PrivateKey {
+    pub(crate) keyword: String,
+}
+
+/// Implementation of the trait `EncryptionProtocol`.
+///
+/// Contains helper methods for creating keys and shifting the letters,
+/// and the implementation of trait methods.
+pub struct Vigenere {}
+
+impl Vigenere {
+    /// Creates keys with a random keyword of the given length.
+    pub fn create_keys_with_length(length: usize) -> (PublicKey, PrivateKey) {
+        let mut keyword: String = String::with_capacity(length);
+        for _i in 0..length {
+            keyword.push(rand::thread_rng().gen_range(b'A'..=b'Z') as char);
+        }
+
+        (
+            PublicKey {
+                keyword: keyword.clone(),
+            },
+            PrivateKey { keyword },
+        )
+    }
+
+    fn shift_letters(message: &[u8], keyword: &str, forward: bool) -> Vec<u8> {
+        let shifts: Vec<u8> = keyword.bytes().map(|c| c - b'A').collect();
+        let mut position: usize = 0;
+        message
+            .iter()
+            .map(|&c| {
+                let first = if c.is_ascii_lowercase() {
+                    b'a'
+                } else if c.is_ascii_uppercase() {
+                    b'A'
+                } else {
+                    return c;
+                };
+                let mut shift = shifts[position % shifts.len()];
+                position += 1;
+                if !forward {
+                    shift = 26 - shift;
+                }
+                (c - first + shift) % 26 + first
+            })
+            .collect()
+    }
+}
+
+impl EncryptionProtocol for Vigenere {
+    /// Implementation of `PublicKey` for Vigenère cipher is used.
+    type PublicKey = PublicKey;
+    /// Implementation of `PrivateKey` for Vigenère cipher is used.
+    type PrivateKey = PrivateKey;
+    /// `ProtocolError` is used.
+    type Error = ProtocolError;
+
+    /// Every ASCII letter of the message is shifted forward by the corresponding letter
+    /// of the repeated keyword. Other characters remain unchanged and do not consume
+    /// the letters of the keyword.
+    fn encrypt_bytes(message: &[u8], pub_key: &PublicKey) -> Result<Vec<u8>, ProtocolError> {
+        Ok(Self::shift_letters(message, &pub_key.keyword, true))
+    }
+
+    /// Every ASCII letter of the message is shifted backward by the corresponding letter
+    /// of the repeated keyword. Other characters remain unchanged.
+    fn decrypt_bytes(message: &[u8], priv_key: &PrivateKey) -> Result<Vec<u8>, ProtocolError> {
+        Ok(Self::shift_letters(message, &priv_key.keyword, false))
+    }
+
+    /// The message is encrypted as a single block, so the keyword continues
+    /// across the blocks of `encrypt_blocks` instead of restarting in every block.
+    fn encrypt_blocks(message: &str, pub_key: &PublicKey) -> Result<Vec<String>, ProtocolError> {
+        Ok(vec![Self::encrypt(message, pub_key)?])
+    }
+
+    /// The blocks are concatenated and decrypted at once, as the keyword continues
+    /// across them (see `encrypt_blocks`).
+    fn decrypt_blocks(blocks: &[String], priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Self::decrypt(&blocks.concat(), priv_key)
+    }
+
+    /// The bytes are encrypted as a single block (see `encrypt_blocks`).
+    fn encrypt_byte_blocks(
+        message: &[u8],
+        pub_key: &PublicKey,
+    ) -> Result<Vec<Vec<u8>>, ProtocolError> {
+        Ok(vec![Self::encrypt_bytes(message, pub_key)?])
+    }
+
+    /// The blocks are concatenated and decrypted at once (see `decrypt_blocks`).
+    fn decrypt_byte_blocks(
+        blocks: &[Vec<u8>],
+        priv_key: &PrivateKey,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        Self::decrypt_bytes(&blocks.concat(), priv_key)
+    }
+
+    /// The blocks are concatenated, as the ciphertext may contain spaces.
+    fn join_ciphertext(blocks: &[String]) -> String {
+        blocks.concat()
+    }
+
+    /// The ciphertext has the same length as the plaintext, so it is split
+    /// into the same blocks as the plaintext.
+    fn split_ciphertext(message: &str) -> Vec<String> {
+        split_into_blocks(message)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    /// The method generates a random keyword of `DEFAULT_KEYWORD_LENGTH` letters.
+    fn create_keys() -> (PublicKey, PrivateKey) {
+        Self::create_keys_with_length(DEFAULT_KEYWORD_LENGTH)
+    }
+
+    /// The keyword is used as public key as is. It must be a non-empty string
+    /// of uppercase letters `A-Z`.
+    fn to_public_key(message: &str) -> Result<PublicKey, ProtocolError> {
+        if message.is_empty() || !message.bytes().all(|c| c.is_ascii_uppercase()) {
+            return Err(ProtocolError::InvalidPublicKey(String::from(
+                "expected uppercase letters",
+            )));
+        }
+
+        Ok(PublicKey {
+            keyword: String::from(message),
+        })
+    }
+
+    /// The public key is the keyword itself.
+    fn to_string(pub_key: &Self::PublicKey) -> String {
+        pub_key.keyword.clone()
+    }
+
+    /// The private key is the keyword itself.
+    fn private_key_to_string(priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Ok(priv_key.keyword.clone())
+    }
+
+    /// Parses a keyword of uppercase letters to private key.
+    fn to_private_key(message: &str) -> Result<PrivateKey, ProtocolError> {
+        Self::to_public_key(message)
+            .map(|pub_key| PrivateKey {
+                keyword: pub_key.keyword,
+            })
+            .map_err(|_| {
+                ProtocolError::InvalidPrivateKey(String::from("expected uppercase letters"))
+            })
+    }
+
+    fn protocol_name() -> &'static str {
+        "Vigenère"
+    }
+
+    /// The key size is the size of the default keyword (`DEFAULT_KEYWORD_LENGTH` bytes).
+    fn key_size_bits() -> usize {
+        DEFAULT_KEYWORD_LENGTH * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption_protocol::EncryptionProtocol;
+    use crate::vigenere::{PrivateKey, PublicKey, Vigenere};
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let (public_key, private_key) = Vigenere::create_keys();
+        assert_eq!(public_key.keyword.len(), 8);
+        let encrypted_message = Vigenere::encrypt("Attack at dawn, Bob!", &public_key).unwrap();
+        let decrypted_message = Vigenere::decrypt(&encrypted_message, &private_key).unwrap();
+        assert_eq!(decrypted_message, "Attack at dawn, Bob!");
+    }
+
+    #[test]
+    fn test_keyword_repeats_across_spaces() {
+        let public_key = PublicKey {
+            keyword: String::from("LEMON"),
+        };
+        let private_key = PrivateKey {
+            keyword: String::from("LEMON"),
+        };
+        let encrypted_message = Vigenere::encrypt("ATTACK AT DAWN", &public_key).unwrap();
+        assert_eq!(encrypted_message, "LXFOPV EF RNHR");
+        assert_eq!(
+            Vigenere::decrypt(&encrypted_message, &private_key).unwrap(),
+            "ATTACK AT DAWN"
+        );
+    }
+
+    #[test]
+    fn test_keyword_continues_across_blocks() {
+        let public_key = PublicKey {
+            keyword: String::from("LEMON"),
+        };
+        let private_key = PrivateKey {
+            keyword: String::from("LEMON"),
+        };
+        let message = "ATTACK AT DAWN, ATTACK AT DUSK";
+        let blocks = Vigenere::encrypt_blocks(message, &public_key).unwrap();
+        let encrypted_message = Vigenere::join_ciphertext(&blocks);
+        assert_eq!(
+            encrypted_message,
+            Vigenere::encrypt(message, &public_key).unwrap()
+        );
+        let blocks = Vigenere::split_ciphertext(&encrypted_message);
+        assert!(blocks.len() > 1);
+        assert_eq!(
+            Vigenere::decrypt_blocks(&blocks, &private_key).unwrap(),
+            message
+        );
+
+        let blocks = Vigenere::encrypt_byte_blocks(message.as_bytes(), &public_key).unwrap();
+        assert_eq!(blocks, vec![encrypted_message.into_bytes()]);
+        assert_eq!(
+            Vigenere::decrypt_byte_blocks(&blocks, &private_key).unwrap(),
+            message.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_create_keys_with_length() {
+        let (public_key, _private_key) = Vigenere::create_keys_with_length(3);
+        assert_eq!(public_key.keyword.len(), 3);
+        assert!(public_key.keyword.chars().all(|c| c.is_ascii_uppercase()));
+    }
+
+    #[test]
+    fn test_to_public_key() {
+        let key = Vigenere::to_public_key("LEMON").unwrap();
+
+        assert_eq!(key.keyword, "LEMON");
+        assert_eq!(Vigenere::to_string(&key), "LEMON");
+    }
+}
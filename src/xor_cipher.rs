@@ -0,0 +1,251 @@
+//! Implementaion of XOR stream cipher
+//!
+//! This module contains the implementation of the trait `EncryptionProtocol`.
+//! XOR cipher is a minimal symmetric protocol: in the sandbox, the shared key
+//! is broadcasted to all users as a public key, so it is intended only for
+//! simulation and benchmarking purposes.
+use crate::encryption_protocol::{EncryptionProtocol, ProtocolError};
+use rand::Rng;
+
+/// Default length of the key (in bytes).
+pub const DEFAULT_KEY_LENGTH: usize = 16;
+
+/// Struct for public key in XOR cipher.
+///
+/// The key consists of random bytes.
+#[derive(Clone)]
+pub struct PublicKey {
+    pub(crate) key: Vec<u8>,
+}
+
+/// Struct for private key in XOR cipher.
+///
+/// The key consists of random bytes.
+/// Note that the private key is equal to the public key.
+#[derive(Clone)]
+pub struct PrivateKey {
+    pub(crate) key: Vec<u8>,
+}
+
+/// Implementation of the trait `EncryptionProtocol`.
+///
+/// Contains helper methods for creating keys and applying the key,
+/// and the implementation of trait methods.
+pub struct XorCipher {}
+
+impl XorCipher {
+    /// Creates keys with random bytes of the given length.
+    pub fn create_keys_with_length(length: usize) -> (PublicKey, PrivateKey) {
+        let mut key: Vec<u8> = vec![0; length];
+        rand::thread_rng().fill(&mut key[..]);
+
+        (PublicKey { key: key.clone() }, PrivateKey { key })
+    }
+
+    fn apply_key(bytes: &[u8], key: &[u8]) -> Vec<u8> {
+        bytes
+            .iter()
+            .zip(key.iter().cycle())
+            .map(|(byte, key_byte)| byte ^ key_byte)
+            .collect()
+    }
+}
+
+impl EncryptionProtocol for XorCipher {
+    /// Implementation of `PublicKey` for XOR cipher is used.
+    type PublicKey = PublicKey;
+    /// Implementation of `PrivateKey` for XOR cipher is used.
+    type PrivateKey = PrivateKey;
+    /// `ProtocolError` is used.
+    type Error = ProtocolError;
+
+    /// The bytes of the message are XORed with the cyclically repeated key.
+    /// The result is hex-encoded.
+    fn encrypt_bytes(message: &[u8], pub_key: &PublicKey) -> Result<Vec<u8>, ProtocolError> {
+        Ok(Self::apply_key(message, &pub_key.key)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+            .into_bytes())
+    }
+
+    /// The hex-encoded bytes of the message are XORed with the cyclically repeated key.
+    fn decrypt_bytes(message: &[u8], priv_key: &PrivateKey) -> Result<Vec<u8>, ProtocolError> {
+        let invalid = || ProtocolError::InvalidCiphertext(String::from("expected a hex string"));
+        if !message.len().is_multiple_of(2) || !message.is_ascii() {
+            return Err(invalid());
+        }
+        let message = std::str::from_utf8(message).map_err(|_| invalid())?;
+        let bytes: Vec<u8> = (0..message.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&message[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+
+        Ok(Self::apply_key(&bytes, &priv_key.key))
+    }
+
+    /// The message is encrypted as a single block, so the key stream continues
+    /// across the blocks of `encrypt_blocks` instead of restarting in every block.
+    fn encrypt_blocks(message: &str, pub_key: &PublicKey) -> Result<Vec<String>, ProtocolError> {
+        Ok(vec![Self::encrypt(message, pub_key)?])
+    }
+
+    /// The hex-encoded blocks are concatenated and decrypted at once, as the key stream
+    /// continues across them (see `encrypt_blocks`).
+    fn decrypt_blocks(blocks: &[String], priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Self::decrypt(&blocks.concat(), priv_key)
+    }
+
+    /// The bytes are encrypted as a single block (see `encrypt_blocks`).
+    fn encrypt_byte_blocks(
+        message: &[u8],
+        pub_key: &PublicKey,
+    ) -> Result<Vec<Vec<u8>>, ProtocolError> {
+        Ok(vec![Self::encrypt_bytes(message, pub_key)?])
+    }
+
+    /// The blocks are concatenated and decrypted at once (see `decrypt_blocks`).
+    fn decrypt_byte_blocks(
+        blocks: &[Vec<u8>],
+        priv_key: &PrivateKey,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        Self::decrypt_bytes(&blocks.concat(), priv_key)
+    }
+
+    /// The method generates a random key of `DEFAULT_KEY_LENGTH` bytes.
+    fn create_keys() -> (PublicKey, PrivateKey) {
+        Self::create_keys_with_length(DEFAULT_KEY_LENGTH)
+    }
+
+    /// Parses a hex string to public key.
+    fn to_public_key(message: &str) -> Result<PublicKey, ProtocolError> {
+        if message.is_empty() || !message.len().is_multiple_of(2) || !message.is_ascii() {
+            return Err(ProtocolError::InvalidPublicKey(String::from(
+                "expected a non-empty hex string",
+            )));
+        }
+        let key: Vec<u8> = (0..message.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&message[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| ProtocolError::InvalidPublicKey(String::from("expected a hex string")))?;
+
+        Ok(PublicKey { key })
+    }
+
+    /// Creates a hex string from public key.
+    fn to_string(pub_key: &Self::PublicKey) -> String {
+        pub_key
+            .key
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Creates a hex string from private key (the same as for the public key).
+    fn private_key_to_string(priv_key: &PrivateKey) -> Result<String, ProtocolError> {
+        Ok(Self::to_string(&PublicKey {
+            key: priv_key.key.clone(),
+        }))
+    }
+
+    /// Parses a hex string to private key.
+    fn to_private_key(message: &str) -> Result<PrivateKey, ProtocolError> {
+        Self::to_public_key(message)
+            .map(|pub_key| PrivateKey { key: pub_key.key })
+            .map_err(|_| ProtocolError::InvalidPrivateKey(String::from("expected a hex string")))
+    }
+
+    fn protocol_name() -> &'static str {
+        "XOR"
+    }
+
+    /// The key size is the size of the default key (`DEFAULT_KEY_LENGTH` bytes).
+    fn key_size_bits() -> usize {
+        DEFAULT_KEY_LENGTH * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption_protocol::EncryptionProtocol;
+    use crate::xor_cipher::XorCipher;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let (public_key, private_key) = XorCipher::create_keys();
+        let encrypted_message = XorCipher::encrypt("hello", &public_key).unwrap();
+        assert_eq!(encrypted_message.len(), 10);
+        let decrypted_message = XorCipher::decrypt(&encrypted_message, &private_key).unwrap();
+        assert_eq!(decrypted_message, "hello");
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_unicode() {
+        let (public_key, private_key) = XorCipher::create_keys_with_length(3);
+        for message in ["héllo wörld", "Привет, мир!", "こんにちは 🦀🔐"] {
+            let encrypted_message = XorCipher::encrypt(message, &public_key).unwrap();
+            let decrypted_message = XorCipher::decrypt(&encrypted_message, &private_key).unwrap();
+            assert_eq!(decrypted_message, message);
+        }
+    }
+
+    #[test]
+    fn test_key_stream_continues_across_blocks() {
+        let (public_key, private_key) = XorCipher::create_keys();
+        let message = "The same key stream is used for the whole message.";
+        let blocks = XorCipher::encrypt_blocks(message, &public_key).unwrap();
+        assert_eq!(
+            XorCipher::join_ciphertext(&blocks),
+            XorCipher::encrypt(message, &public_key).unwrap()
+        );
+        assert_eq!(
+            XorCipher::decrypt_blocks(&blocks, &private_key).unwrap(),
+            message
+        );
+
+        let blocks = XorCipher::encrypt_byte_blocks(message.as_bytes(), &public_key).unwrap();
+        assert_eq!(
+            blocks,
+            vec![XorCipher::encrypt_bytes(message.as_bytes(), &public_key).unwrap()]
+        );
+        assert_eq!(
+            XorCipher::decrypt_byte_blocks(&blocks, &private_key).unwrap(),
+            message.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_to_public_key() {
+        let key = XorCipher::to_public_key("00ff10").unwrap();
+
+        assert_eq!(key.key, vec![0, 255, 16]);
+        assert_eq!(XorCipher::to_string(&key), "00ff10");
+    }
+
+    #[test]
+    fn test_to_public_key_malformed() {
+        assert!(XorCipher::to_public_key("").is_err());
+        assert!(XorCipher::to_public_key("0").is_err());
+        assert!(XorCipher::to_public_key("zz").is_err());
+        assert!(XorCipher::to_public_key("éé").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_random_bytes() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let (public_key, private_key) = XorCipher::create_keys();
+        for length in [0, 1, 7, 100] {
+            let mut bytes: Vec<u8> = vec![0; length];
+            rng.fill(&mut bytes[..]);
+            let encrypted = XorCipher::encrypt_bytes(&bytes, &public_key).unwrap();
+            assert_eq!(
+                XorCipher::decrypt_bytes(&encrypted, &private_key).unwrap(),
+                bytes
+            );
+        }
+    }
+}
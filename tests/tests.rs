@@ -1,10 +1,15 @@
-use cryptography_sandbox::env::Env;
-use cryptography_sandbox::message::MessageType;
+use cryptography_sandbox::any_protocol::{AnyProtocol, ProtocolKind};
+use cryptography_sandbox::caesar::Caesar;
+use cryptography_sandbox::elgamal::ElGamal;
+use cryptography_sandbox::env::{Env, HookDecision, SendError};
+use cryptography_sandbox::message::{MessageBuilder, MessageType};
 use cryptography_sandbox::rsa::RSA;
+use cryptography_sandbox::vigenere::Vigenere;
+use cryptography_sandbox::xor_cipher::XorCipher;
 
 #[test]
 fn test_get_user() {
-    let mut env: Env<RSA> = Env::new();
+    let mut env: Env<RSA> = Env::in_memory();
 
     env.create_user("Alice");
     env.create_user("Bob");
@@ -21,7 +26,7 @@ fn test_get_user() {
 
 #[test]
 fn test_create_keys() {
-    let mut env: Env<RSA> = Env::new();
+    let mut env: Env<RSA> = Env::in_memory();
 
     env.create_user("Alice");
     env.create_user("Bob");
@@ -31,13 +36,10 @@ fn test_create_keys() {
         .expect("name not found")
         .create_keys();
     assert_eq!(key.get_receiver(), "");
-    let is_public_key_type = match key.get_message_type() {
-        MessageType::Message => false,
-        MessageType::PublicKey => true,
-    };
+    let is_public_key_type = matches!(key.get_message_type(), MessageType::PublicKey);
     assert!(is_public_key_type);
     let key_message: String = String::from(key.get_message());
-    env.send_message(key);
+    env.send_message(key).unwrap();
 
     assert!(
         env.get_user("Bob")
@@ -48,14 +50,15 @@ fn test_create_keys() {
     let mes = env
         .get_user("Alice")
         .expect("name not found")
-        .read_last_message();
+        .read_last_message()
+        .unwrap();
     let user_message: String = String::from(mes.get_message());
     assert_eq!(key_message, user_message);
 }
 
 #[test]
 fn test_send_message() {
-    let mut env: Env<RSA> = Env::new();
+    let mut env: Env<RSA> = Env::in_memory();
 
     env.create_user("Alice");
     env.create_user("Bob");
@@ -64,43 +67,46 @@ fn test_send_message() {
         .get_mut_user("Bob")
         .expect("name not found")
         .create_keys();
-    env.send_message(key);
+    env.send_message(key).unwrap();
 
     let message = env
         .get_user("Alice")
         .expect("name not found")
-        .create_message("Bob", "Hello, Bob!");
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
     assert_eq!(message.get_sender(), "Alice");
     assert_eq!(message.get_receiver(), "Bob");
-    let is_message_type = match message.get_message_type() {
-        MessageType::Message => true,
-        MessageType::PublicKey => false,
-    };
+    let is_message_type = matches!(message.get_message_type(), MessageType::Message);
     assert!(is_message_type);
-    env.send_message(message);
+    env.send_message(message).unwrap();
     let received_message = env
         .get_user("Bob")
         .expect("name not found")
-        .read_last_message();
+        .read_last_message()
+        .unwrap();
     assert_eq!(received_message.get_message(), "Hello, Bob!");
 }
 
 #[test]
-#[should_panic(expected = "receiver's public key not found")]
 fn test_nonexisting_public_key() {
-    let mut env: Env<RSA> = Env::new();
+    let mut env: Env<RSA> = Env::in_memory();
 
     env.create_user("Alice");
     env.create_user("Bob");
 
-    env.get_user("Alice")
+    let result = env
+        .get_user("Alice")
         .expect("name not found")
         .create_message("Bob", "Hello, Bob!");
+    assert_eq!(
+        result.err(),
+        Some(String::from("public key of 'Bob' not found"))
+    );
 }
 
 #[test]
 fn test_change_keys() {
-    let mut env: Env<RSA> = Env::new();
+    let mut env: Env<RSA> = Env::in_memory().with_self_delivery(true);
 
     env.create_user("Alice");
     env.create_user("Bob");
@@ -109,34 +115,30 @@ fn test_change_keys() {
         .get_mut_user("Bob")
         .expect("name not found")
         .create_keys();
-    env.send_message(key);
+    env.send_message(key).unwrap();
 
     let message = env
         .get_user("Alice")
         .expect("name not found")
-        .create_message("Bob", "Hello, Bob!");
-    env.send_message(message);
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
 
     let new_key = env
         .get_mut_user("Bob")
         .expect("name not found")
         .create_keys();
-    env.send_message(new_key);
+    env.send_message(new_key).unwrap();
 
     let last_message = env
         .get_user("Bob")
         .expect("name not found")
-        .read_last_message();
-    let is_public_key_type = match last_message.get_message_type() {
-        MessageType::Message => false,
-        MessageType::PublicKey => true,
-    };
+        .read_last_message()
+        .unwrap();
+    let is_public_key_type = matches!(last_message.get_message_type(), MessageType::PublicKey);
     assert!(is_public_key_type);
     let first_message = env.get_user("Bob").expect("name not found").read_message(0);
-    let is_public_key_type = match first_message.get_message_type() {
-        MessageType::Message => false,
-        MessageType::PublicKey => true,
-    };
+    let is_public_key_type = matches!(first_message.get_message_type(), MessageType::PublicKey);
     assert!(is_public_key_type);
     let received_message = env.get_user("Bob").expect("name not found").read_message(1);
     assert_eq!(received_message.get_message(), "Hello, Bob!");
@@ -144,7 +146,7 @@ fn test_change_keys() {
 
 #[test]
 fn test_send_to_myself() {
-    let mut env: Env<RSA> = Env::new();
+    let mut env: Env<RSA> = Env::in_memory();
 
     env.create_user("Alice");
 
@@ -152,24 +154,26 @@ fn test_send_to_myself() {
         .get_mut_user("Alice")
         .expect("name not found")
         .create_keys();
-    env.send_message(key);
+    env.send_message(key).unwrap();
 
     let message = env
         .get_user("Alice")
         .expect("name not found")
-        .create_message("Alice", "Hello, me!");
-    env.send_message(message);
+        .create_message("Alice", "Hello, me!")
+        .unwrap();
+    env.send_message(message).unwrap();
 
     let received_message = env
         .get_user("Alice")
         .expect("name not found")
-        .read_last_message();
+        .read_last_message()
+        .unwrap();
     assert_eq!(received_message.get_message(), "Hello, me!");
 }
 
 #[test]
 fn test_communication() {
-    let mut env: Env<RSA> = Env::new();
+    let mut env: Env<RSA> = Env::in_memory().with_self_delivery(true);
 
     env.create_user("Alice");
     env.create_user("Bob");
@@ -178,39 +182,42 @@ fn test_communication() {
         .get_mut_user("Bob")
         .expect("name not found")
         .create_keys();
-    env.send_message(bob_key);
+    env.send_message(bob_key).unwrap();
 
     let alice_key = env
         .get_mut_user("Alice")
         .expect("name not found")
         .create_keys();
-    env.send_message(alice_key);
+    env.send_message(alice_key).unwrap();
 
     let first_message = env
         .get_user("Alice")
         .expect("name not found")
-        .create_message("Bob", "Hello, Bob!");
-    env.send_message(first_message);
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    env.send_message(first_message).unwrap();
     let new_bob_key = env
         .get_mut_user("Bob")
         .expect("name not found")
         .create_keys();
-    env.send_message(new_bob_key);
+    env.send_message(new_bob_key).unwrap();
     let second_message = env
         .get_user("Bob")
         .expect("name not found")
-        .create_message("Alice", "Hello, Alice! How are you?");
-    env.send_message(second_message);
+        .create_message("Alice", "Hello, Alice! How are you?")
+        .unwrap();
+    env.send_message(second_message).unwrap();
     let new_alice_key = env
         .get_mut_user("Alice")
         .expect("name not found")
         .create_keys();
-    env.send_message(new_alice_key);
+    env.send_message(new_alice_key).unwrap();
     let third_message = env
         .get_user("Alice")
         .expect("name not found")
-        .create_message("Bob", "I'm OK, thanks. And you?");
-    env.send_message(third_message);
+        .create_message("Bob", "I'm OK, thanks. And you?")
+        .unwrap();
+    env.send_message(third_message).unwrap();
 
     let alice_messages = env
         .get_user("Alice")
@@ -260,3 +267,729 @@ fn test_communication() {
     assert_eq!(sixth_message.get_receiver(), "Bob");
     assert_eq!(sixth_message.get_message(), "I'm OK, thanks. And you?");
 }
+
+#[test]
+fn test_delete_and_notify() {
+    let mut env: Env<RSA> = Env::in_memory().with_self_delivery(true);
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    let message_id = message.get_id();
+    env.send_message(message).unwrap();
+
+    let (deleted_message, receipt) = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .delete_and_notify(1);
+    assert_eq!(deleted_message.get_id(), message_id);
+    let receipt = receipt.expect("receipt not created");
+    assert_eq!(receipt.get_receiver(), "Alice");
+    env.send_message(receipt).unwrap();
+
+    let received_receipt = env
+        .get_user("Alice")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_receipt.get_sender(), "Bob");
+    let is_delete_receipt_type = matches!(
+        received_receipt.get_message_type(),
+        MessageType::DeleteReceipt
+    );
+    assert!(is_delete_receipt_type);
+    assert_eq!(received_receipt.get_message(), &message_id.to_string());
+}
+
+#[test]
+fn test_acknowledge() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    let message_id = message.get_id();
+    env.send_message(message).unwrap();
+
+    let bob = env.get_user("Bob").expect("name not found");
+    let ack = bob.acknowledge(&bob.read_last_message().unwrap());
+    assert_eq!(ack.get_receiver(), "Alice");
+    env.send_message(ack.clone()).unwrap();
+    env.send_message(ack).unwrap();
+
+    let alice = env.get_user("Alice").expect("name not found");
+    let received_ack = alice.read_last_message().unwrap();
+    assert_eq!(received_ack.get_sender(), "Bob");
+    assert_eq!(received_ack.get_message_type(), MessageType::Ack);
+    assert_eq!(received_ack.get_in_reply_to(), Some(message_id));
+    assert_eq!(alice.acked_ids(), vec![message_id]);
+    let bob = env.get_user("Bob").expect("name not found");
+    assert!(bob.acked_ids().is_empty());
+}
+
+#[test]
+fn test_identity_survives_key_rotation() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let identity = env
+        .get_mut_user("Alice")
+        .expect("name not found")
+        .create_identity();
+    assert_eq!(identity.get_message_type(), MessageType::Identity);
+    env.send_message(identity).unwrap();
+    for name in ["Alice", "Bob"] {
+        let key = env
+            .get_mut_user(name)
+            .expect("name not found")
+            .create_keys();
+        env.send_message(key).unwrap();
+    }
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_signed_message("Bob", "Hello, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
+
+    for _ in 0..2 {
+        let key = env
+            .get_mut_user("Alice")
+            .expect("name not found")
+            .create_keys();
+        env.send_message(key).unwrap();
+    }
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_signed_message("Bob", "Hello again, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
+
+    let bob = env.get_user("Bob").expect("name not found");
+    let signed_messages = bob.messages_of_type(MessageType::Message);
+    assert_eq!(signed_messages.len(), 2);
+    for message in &signed_messages {
+        assert!(bob.verify_message(message));
+    }
+}
+
+#[test]
+fn test_sender_verification() {
+    let mut env: Env<RSA> = Env::in_memory().with_sender_verification(true);
+
+    for name in ["Alice", "Bob", "Mallory"] {
+        env.create_user(name);
+        let identity = env
+            .get_mut_user(name)
+            .expect("name not found")
+            .create_identity();
+        env.send_message(identity).unwrap();
+    }
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
+
+    let message = env
+        .get_user("Mallory")
+        .expect("name not found")
+        .create_message("Bob", "Hello from Alice!")
+        .unwrap();
+    let forged = MessageBuilder::new()
+        .sender("Alice")
+        .receiver("Bob")
+        .text(message.get_message())
+        .message_type(MessageType::Message)
+        .session_key(1)
+        .build()
+        .unwrap();
+    assert_eq!(
+        env.send_message(forged),
+        Err(SendError::SenderNotVerified(String::from("Alice")))
+    );
+    let forged_identity = env
+        .get_mut_user("Mallory")
+        .expect("name not found")
+        .create_identity();
+    let forged_identity = MessageBuilder::new()
+        .sender("Alice")
+        .text(forged_identity.get_message())
+        .message_type(MessageType::Identity)
+        .build()
+        .unwrap();
+    assert!(env.send_message(forged_identity).is_err());
+
+    env.create_user("Carol");
+    let key = env
+        .get_mut_user("Carol")
+        .expect("name not found")
+        .create_keys();
+    assert_eq!(
+        env.send_message(key),
+        Err(SendError::SenderNotVerified(String::from("Carol")))
+    );
+
+    let bob = env.get_user("Bob").expect("name not found");
+    let messages = bob.messages_of_type(MessageType::Message);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].get_message(), "Hello, Bob!");
+}
+
+#[test]
+fn test_known_contacts() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+    env.create_user("Carol");
+    assert!(
+        env.get_user("Bob")
+            .expect("name not found")
+            .known_contacts()
+            .is_empty()
+    );
+
+    for name in ["Carol", "Alice"] {
+        let key = env
+            .get_mut_user(name)
+            .expect("name not found")
+            .create_keys();
+        env.send_message(key).unwrap();
+    }
+
+    let bob = env.get_user("Bob").expect("name not found");
+    assert_eq!(bob.known_contacts(), vec!["Alice", "Carol"]);
+    assert!(bob.has_key_for("Alice"));
+    assert!(!bob.has_key_for("Bob"));
+}
+
+#[test]
+fn test_block_user() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+    env.create_user("Carol");
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    env.get_mut_user("Bob")
+        .expect("name not found")
+        .block("Alice");
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let message = env
+        .get_user("Carol")
+        .expect("name not found")
+        .create_message("Bob", "Hi, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let key = env
+        .get_mut_user("Alice")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let bob = env.get_user("Bob").expect("name not found");
+    assert!(bob.is_blocked("Alice"));
+    assert_eq!(bob.read_all_messages().len(), 1);
+    assert_eq!(bob.read_last_message().unwrap().get_message(), "Hi, Bob!");
+    assert!(!bob.has_key_for("Alice"));
+    assert!(env.get_user("Carol").unwrap().has_key_for("Alice"));
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, everyone!")
+        .unwrap();
+    env.send_to_recipients(message, &["Bob", "Carol"]).unwrap();
+    env.create_group("room").unwrap();
+    env.join_group("room", "Alice").unwrap();
+    env.join_group("room", "Bob").unwrap();
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, room!")
+        .unwrap();
+    env.send_to_group("room", message).unwrap();
+    assert_eq!(env.get_user("Bob").unwrap().read_all_messages().len(), 1);
+    assert_eq!(
+        env.get_user("Carol")
+            .unwrap()
+            .read_last_message()
+            .unwrap()
+            .get_receiver(),
+        "Bob, Carol"
+    );
+
+    env.get_mut_user("Bob")
+        .expect("name not found")
+        .unblock("Alice");
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello again!")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let bob = env.get_user("Bob").expect("name not found");
+    assert_eq!(
+        bob.read_last_message().unwrap().get_message(),
+        "Hello again!"
+    );
+}
+
+#[test]
+fn test_envelope_message() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    for name in ["Alice", "Bob", "Carol", "Dave", "Eve"] {
+        env.create_user(name);
+        env.rotate_keys(name).unwrap();
+    }
+    let recipients = ["Dave", "Bob", "Carol"];
+    let envelope = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_envelope_message(&recipients, "Meeting at noon")
+        .unwrap();
+    assert_eq!(envelope.get_receiver(), "Bob, Carol, Dave");
+    assert!(!envelope.get_message().contains("Meeting"));
+    env.send_to_recipients(envelope.clone(), &recipients)
+        .unwrap();
+
+    for name in recipients {
+        let received = env
+            .get_user(name)
+            .expect("name not found")
+            .read_last_message()
+            .unwrap();
+        assert_eq!(received.get_message_type(), MessageType::Envelope);
+        assert_eq!(received.get_decrypt_error(), None);
+        assert_eq!(received.get_message(), "Meeting at noon");
+    }
+
+    // A non-recipient cannot read the envelope even if it is delivered to them.
+    env.send_to_recipients(envelope, &["Eve"]).unwrap();
+    let intercepted = env
+        .get_user("Eve")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(
+        intercepted.get_decrypt_error(),
+        Some(&String::from("not a recipient of the envelope"))
+    );
+    assert_ne!(intercepted.get_message(), "Meeting at noon");
+}
+
+#[test]
+fn test_missing_sequence_numbers() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+    env.create_user("Carol");
+    env.rotate_keys("Bob").unwrap();
+    env.set_send_hook(Box::new(|message| {
+        if message.get_sequence_number() == Some(3) {
+            HookDecision::Drop
+        } else {
+            HookDecision::Deliver
+        }
+    }));
+
+    for i in 1..=4 {
+        let message = env
+            .get_user("Alice")
+            .expect("name not found")
+            .create_message("Bob", &format!("Message {}", i))
+            .unwrap();
+        assert_eq!(message.get_sequence_number(), None);
+        // Messages that are created but never sent do not consume numbers.
+        env.get_user("Alice")
+            .expect("name not found")
+            .create_message("Bob", "Draft")
+            .unwrap();
+        env.send_message(message).unwrap();
+    }
+    let message = env
+        .get_user("Carol")
+        .expect("name not found")
+        .create_message("Bob", "Hi, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
+
+    let bob = env.get_user("Bob").expect("name not found");
+    let received: Vec<Option<u64>> = bob
+        .messages_from("Alice", false)
+        .iter()
+        .map(|message| message.get_sequence_number())
+        .collect();
+    assert_eq!(received, vec![Some(1), Some(2), Some(4)]);
+    assert_eq!(bob.missing_sequence_numbers("Alice"), vec![3]);
+    assert_eq!(
+        bob.read_last_message().unwrap().get_sequence_number(),
+        Some(1)
+    );
+    assert!(bob.missing_sequence_numbers("Carol").is_empty());
+    assert!(bob.missing_sequence_numbers("Dave").is_empty());
+}
+
+#[test]
+fn test_mixed_protocols() {
+    let mut env: Env<AnyProtocol> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Alice")
+        .expect("name not found")
+        .create_keys_with(ProtocolKind::Rsa);
+    assert!(key.get_message().starts_with("RSA:"));
+    env.send_message(key).unwrap();
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys_with(ProtocolKind::Caesar);
+    assert!(key.get_message().starts_with("Caesar:"));
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    assert!(message.get_message().starts_with("Caesar:"));
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Hello, Bob!");
+
+    let message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .create_message("Alice", "Hello, Alice!")
+        .unwrap();
+    assert!(message.get_message().starts_with("RSA:"));
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Hello, Alice!");
+
+    // Key rotation keeps the protocol of the user.
+    env.rotate_keys("Bob").unwrap();
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello again, Bob!")
+        .unwrap();
+    assert!(message.get_message().starts_with("Caesar:"));
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Hello again, Bob!");
+}
+
+#[test]
+fn test_elgamal_send_message() {
+    let mut env: Env<ElGamal> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob! How are you?")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Hello, Bob! How are you?");
+}
+
+#[test]
+fn test_key_exchange() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let request = env
+        .get_mut_user("Alice")
+        .expect("name not found")
+        .initiate_exchange("Bob");
+    env.send_message(request).unwrap();
+
+    let alice_secret = env
+        .get_user("Alice")
+        .expect("name not found")
+        .get_shared_secret("Bob");
+    let bob_secret = env
+        .get_user("Bob")
+        .expect("name not found")
+        .get_shared_secret("Alice");
+    assert!(alice_secret.is_some());
+    assert_eq!(alice_secret, bob_secret);
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob!")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Hello, Bob!");
+}
+
+#[test]
+fn test_key_exchange_offline() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+    env.set_online("Bob", false).unwrap();
+
+    let request = env
+        .get_mut_user("Alice")
+        .expect("name not found")
+        .initiate_exchange("Bob");
+    env.send_message(request).unwrap();
+    assert!(
+        env.get_user("Alice")
+            .expect("name not found")
+            .get_shared_secret("Bob")
+            .is_none()
+    );
+
+    env.set_online("Bob", true).unwrap();
+    let alice_secret = env
+        .get_user("Alice")
+        .expect("name not found")
+        .get_shared_secret("Bob");
+    let bob_secret = env
+        .get_user("Bob")
+        .expect("name not found")
+        .get_shared_secret("Alice");
+    assert!(alice_secret.is_some());
+    assert_eq!(alice_secret, bob_secret);
+}
+
+#[test]
+fn test_caesar_send_message() {
+    let mut env: Env<Caesar> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob! How are you?")
+        .unwrap();
+    assert_ne!(message.get_message(), "Hello, Bob! How are you?");
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Hello, Bob! How are you?");
+}
+
+#[test]
+fn test_vigenere_send_message() {
+    let mut env: Env<Vigenere> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Hello, Bob! How are you?")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Hello, Bob! How are you?");
+}
+
+#[test]
+fn test_xor_cipher_send_unicode_message() {
+    let mut env: Env<XorCipher> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Привет, Боб! 🦀")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "Привет, Боб! 🦀");
+}
+
+#[test]
+fn test_send_empty_message() {
+    let mut env: Env<Caesar> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "")
+        .unwrap();
+    env.send_message(message).unwrap();
+    let received_message = env
+        .get_user("Bob")
+        .expect("name not found")
+        .read_last_message()
+        .unwrap();
+    assert_eq!(received_message.get_message(), "");
+}
+
+#[test]
+fn test_key_rotation_between_composition_and_sending() {
+    let mut env: Env<RSA> = Env::in_memory();
+
+    env.create_user("Alice");
+    env.create_user("Bob");
+
+    let key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(key).unwrap();
+
+    let old_message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Composed before rotation")
+        .unwrap();
+
+    let new_key = env
+        .get_mut_user("Bob")
+        .expect("name not found")
+        .create_keys();
+    env.send_message(new_key).unwrap();
+
+    let new_message = env
+        .get_user("Alice")
+        .expect("name not found")
+        .create_message("Bob", "Composed after rotation")
+        .unwrap();
+    env.send_message(old_message).unwrap();
+    env.send_message(new_message).unwrap();
+
+    let bob = env.get_user("Bob").expect("name not found");
+    let messages = bob.messages_from("Alice", false);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].get_message(), "Composed before rotation");
+    assert_eq!(messages[1].get_message(), "Composed after rotation");
+}